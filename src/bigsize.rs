@@ -1,10 +1,11 @@
 use core::fmt;
-use std::io::{self, Write, Read};
+use crate::io::{self, Write, Read};
 use crate::ser::{Writeable, Readable, DecodeError};
 
 /// BigSize is identical to the CompactSize encoding used in bitcoin, but replaces the 
 /// little-endian encoding of multi-byte values with big-endian.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct BigSize(pub u64);
 
 impl Writeable for BigSize {
@@ -15,13 +16,13 @@ impl Writeable for BigSize {
             (x as u8).write(writer)
         } else if x < 0x10000 {
             0xfdu8.write(writer)?;
-            (x as u16).write(writer)
+            Ok(1 + (x as u16).write(writer)?)
         } else if x < 0x100000000 {
             0xfeu8.write(writer)?;
-            (x as u32).write(writer)
+            Ok(1 + (x as u32).write(writer)?)
         } else {
             0xffu8.write(writer)?;
-            (x as u64).write(writer)
+            Ok(1 + x.write(writer)?)
         }
     }
 
@@ -38,7 +39,7 @@ impl Writeable for BigSize {
             write!(writer, "{:08x}", x as u32)
         } else {
             write!(writer, "{:02x}", 0xffu8)?;
-            write!(writer, "{:016x}", x as u64)
+            write!(writer, "{:016x}", x)
         }
     }
 }
@@ -50,23 +51,23 @@ impl Readable for BigSize {
         if size == 0xfd {
             let x: u16 = Readable::read(reader)?;
             if x < 0xfd {
-                Err(DecodeError::InvalidData)
+                Err(DecodeError::NonCanonicalBigSize)
             } else {
                 Ok(BigSize(x as u64))
             }
         } else if size == 0xfe {
             let x: u32 = Readable::read(reader)?;
             if x < 0x10000 {
-                Err(DecodeError::InvalidData)
+                Err(DecodeError::NonCanonicalBigSize)
             } else {
                 Ok(BigSize(x as u64))
             }
         } else if size == 0xff {
             let x: u64 = Readable::read(reader)?;
             if x < 0x100000000 {
-                Err(DecodeError::InvalidData)
+                Err(DecodeError::NonCanonicalBigSize)
             } else {
-                Ok(BigSize(x as u64))
+                Ok(BigSize(x))
             }
         } else {
             Ok(BigSize(size as u64))
@@ -82,10 +83,12 @@ impl fmt::LowerHex for BigSize {
 
 #[cfg(test)]
 mod tests {
+    use alloc::{string::String, vec::Vec};
+
     use super::*;
-    use std::io::Cursor;
 
     #[derive(Clone, Debug)]
+    #[allow(dead_code)]
     enum Value {
         Title(String),
         Number(u64),
@@ -140,8 +143,10 @@ mod tests {
 
         for vector in test_vectors {
             if let (Value::Number(val), Value::Hex(res)) = (vector[1].clone(), vector[2].clone()) {
-                let bytes = BigSize(val).encode();
-                assert_eq!(hex::encode(bytes), res);
+                let mut bytes = Vec::new();
+                let len = BigSize(val).write(&mut bytes).unwrap();
+                assert_eq!(hex::encode(&bytes), res);
+                assert_eq!(len, bytes.len());
             }
         }
     }
@@ -202,19 +207,19 @@ mod tests {
                 Value::Title("two byte not canonical".into()),
                 Value::Number(0),
                 Value::Hex("fd00fc".into()),
-                Value::Error(Some(DecodeError::InvalidData)),
+                Value::Error(Some(DecodeError::NonCanonicalBigSize)),
             ],
             [
                 Value::Title("four byte not canonical".into()),
                 Value::Number(0),
                 Value::Hex("fe0000ffff".into()),
-                Value::Error(Some(DecodeError::InvalidData)),
+                Value::Error(Some(DecodeError::NonCanonicalBigSize)),
             ],
             [
                 Value::Title("eight byte not canonical".into()),
                 Value::Number(0),
                 Value::Hex("ff00000000ffffffff".into()),
-                Value::Error(Some(DecodeError::InvalidData)),
+                Value::Error(Some(DecodeError::NonCanonicalBigSize)),
             ],
             [
                 Value::Title("two byte short read".into()),
@@ -265,8 +270,7 @@ mod tests {
                 (vector[1].clone(), vector[2].clone(), vector[3].clone()) {
 
                 let bytes = hex::decode(input.clone()).expect("parse test input");
-                let mut buff = Cursor::new(bytes);
-                let bigsize = match BigSize::read(&mut buff) {
+                let bigsize = match BigSize::read(&mut bytes.as_slice()) {
                     Ok(bs) => bs,
                     Err(e) => { assert_eq!(Some(e), err); continue }
                 };
@@ -276,4 +280,11 @@ mod tests {
 
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_as_number() {
+        assert_eq!(serde_json::to_string(&BigSize(0xfd)).unwrap(), "253");
+        assert_eq!(serde_json::from_str::<BigSize>("18446744073709551615").unwrap(), BigSize(u64::MAX));
+    }
 }
@@ -0,0 +1,655 @@
+//! Route blinding, as defined by BOLT #4.
+//!
+//! The recipient of a payment builds a blinded path from an introduction node to itself. The path
+//! hides the node ids after the introduction node and carries, encrypted for each node, the data
+//! it needs to forward the payment. The sender routes to the introduction node as usual, and each
+//! node of the path hands the next one the path key it needs to find its data.
+
+use std::fmt;
+
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, AeadInPlace, Nonce, Tag};
+use secp256k1::{PublicKey, SecretKey, Secp256k1, ecdh::SharedSecret};
+
+use crate::io::{self, Read, Write};
+use crate::features::Features;
+use crate::onion::{blinding_factor, generate_key, peel_onion, HopPayload, OnionError, OnionHop, OnionPacket, PayloadError, PeeledOnion};
+use crate::pretty::impl_describe_fields;
+use crate::ser::{Readable, Writeable, DecodeError, TU32, TU64};
+use crate::tlv::{TLVStream, impl_tlv_value_fixed};
+use crate::types::ShortChannelId;
+use crate::tlv_namespace;
+
+/// The size of the MAC which follows the encrypted recipient data
+const MAC_LEN: usize = 16;
+
+tlv_namespace! {
+    /// The records of the data the recipient encrypts for each node of a blinded path.
+    pub enum EncryptedDataTlvs: "encrypted_data_tlvs" {
+        /// Makes the data of every hop the same length
+        1 => Padding(Vec<u8>) as "padding", optional;
+        /// The channel to forward the payment through
+        2 => ShortChannelId(ShortChannelId) as "short_channel_id", optional;
+        /// The node to forward the payment to, instead of a channel
+        4 => NextNodeId(PublicKey) as "next_node_id", optional;
+        /// Lets the recipient check that the payment came through its own path
+        6 => PathId(Vec<u8>) as "path_id", optional;
+        /// The path key to hand to the next node, when the path continues another one
+        8 => NextPathKeyOverride(PublicKey) as "next_path_key_override", optional;
+        10 => PaymentRelay(PaymentRelay) as "payment_relay", optional;
+        12 => PaymentConstraints(PaymentConstraints) as "payment_constraints", optional;
+        /// The features the payment may use
+        14 => AllowedFeatures(Features) as "allowed_features", optional;
+    }
+}
+
+/// The fees and cltv_expiry_delta a node of a blinded path charges for forwarding, in place of
+/// those of its channel_update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaymentRelay {
+    pub cltv_expiry_delta: u16,
+    pub fee_proportional_millionths: u32,
+    pub fee_base_msat: u32,
+}
+
+/// Within its record, `fee_base_msat` spans the rest of the value.
+impl Readable for PaymentRelay {
+    fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let cltv_expiry_delta = Readable::read(reader)?;
+        let fee_proportional_millionths = Readable::read(reader)?;
+        let fee_base_msat: TU32 = Readable::read(reader)?;
+        Ok(PaymentRelay { cltv_expiry_delta, fee_proportional_millionths, fee_base_msat: fee_base_msat.0 })
+    }
+}
+
+impl Writeable for PaymentRelay {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.cltv_expiry_delta.write(writer)?;
+        len += self.fee_proportional_millionths.write(writer)?;
+        Ok(len + TU32(self.fee_base_msat).write(writer)?)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.cltv_expiry_delta.write_fmt(writer)?;
+        self.fee_proportional_millionths.write_fmt(writer)?;
+        TU32(self.fee_base_msat).write_fmt(writer)
+    }
+}
+
+/// Limits on the payments the recipient accepts through a blinded path, so that the path can't
+/// be probed with other payments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaymentConstraints {
+    pub max_cltv_expiry: u32,
+    pub htlc_minimum_msat: u64,
+}
+
+/// Within its record, `htlc_minimum_msat` spans the rest of the value.
+impl Readable for PaymentConstraints {
+    fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let max_cltv_expiry = Readable::read(reader)?;
+        let htlc_minimum_msat: TU64 = Readable::read(reader)?;
+        Ok(PaymentConstraints { max_cltv_expiry, htlc_minimum_msat: htlc_minimum_msat.0 })
+    }
+}
+
+impl Writeable for PaymentConstraints {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let len = self.max_cltv_expiry.write(writer)?;
+        Ok(len + TU64(self.htlc_minimum_msat).write(writer)?)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.max_cltv_expiry.write_fmt(writer)?;
+        TU64(self.htlc_minimum_msat).write_fmt(writer)
+    }
+}
+
+impl_tlv_value_fixed!(PaymentRelay, PaymentConstraints);
+impl_describe_fields!(PaymentRelay, { cltv_expiry_delta, fee_proportional_millionths, fee_base_msat });
+impl_describe_fields!(PaymentConstraints, { max_cltv_expiry, htlc_minimum_msat });
+
+#[cfg(feature = "serde")]
+crate::tlv::impl_serde_value!(PaymentRelay, PaymentConstraints);
+
+/// Why a blinded path couldn't be built, or why a node of the path refuses an HTLC. Nodes within
+/// the path report any of these as `invalid_onion_blinding`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlindingError {
+    /// A blinded path needs at least the introduction node
+    EmptyPath,
+    /// The data to blind has a `next_path_key_override`, which can only lead into another path
+    UnexpectedOverride,
+    /// A key derivation gave an invalid key
+    InvalidKey,
+    /// The encrypted_recipient_data wasn't encrypted for this node and path key
+    DecryptionFailed,
+    /// The decrypted data isn't a valid `encrypted_data_tlvs` stream
+    Decode(DecodeError),
+    Onion(OnionError),
+    Payload(PayloadError),
+}
+
+impl std::error::Error for BlindingError {}
+
+impl fmt::Display for BlindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlindingError::EmptyPath => write!(f, "blinded path is empty"),
+            BlindingError::UnexpectedOverride => write!(f, "next_path_key_override in the data to blind"),
+            BlindingError::InvalidKey => write!(f, "invalid blinded key"),
+            BlindingError::DecryptionFailed => write!(f, "encrypted_recipient_data decryption failed"),
+            BlindingError::Decode(e) => write!(f, "encrypted_recipient_data: {}", e),
+            BlindingError::Onion(e) => write!(f, "{}", e),
+            BlindingError::Payload(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<OnionError> for BlindingError {
+    fn from(e: OnionError) -> Self {
+        BlindingError::Onion(e)
+    }
+}
+
+impl From<PayloadError> for BlindingError {
+    fn from(e: PayloadError) -> Self {
+        BlindingError::Payload(e)
+    }
+}
+
+/// A node of the path to blind, with the data the recipient has for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathNode {
+    pub node_id: PublicKey,
+    pub recipient_data: TLVStream<EncryptedDataTlvs>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlindedHop {
+    pub blinded_node_id: PublicKey,
+    pub encrypted_recipient_data: Vec<u8>,
+}
+
+/// A path the recipient hands to senders, e.g. in an invoice. Only the introduction node is
+/// named, the other hops are known by their blinded node ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlindedPath {
+    pub introduction_node_id: PublicKey,
+    /// The path key of the introduction node
+    pub first_path_key: PublicKey,
+    /// The blinded hops, starting with the introduction node's
+    pub hops: Vec<BlindedHop>,
+}
+
+/// The data a node of a blinded path decrypted, along with the path key of the next node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnblindedData {
+    pub recipient_data: TLVStream<EncryptedDataTlvs>,
+    pub next_path_key: PublicKey,
+}
+
+/// An HTLC received through a blinded path, once its onion is peeled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlindedReceive {
+    pub peeled: PeeledOnion,
+    pub payload: HopPayload,
+    pub recipient_data: TLVStream<EncryptedDataTlvs>,
+    /// The path key for the update_add_htlc to the next node, unless this node is the recipient
+    pub next_path_key: PublicKey,
+}
+
+/// Encrypts `data` with ChaCha20-Poly1305 under `rho`, with a zero nonce and no associated data,
+/// followed by the 16-byte MAC.
+fn encrypt(rho: &[u8; 32], data: &[u8]) -> Vec<u8> {
+    let cipher = ChaCha20Poly1305::new(rho.into());
+    let mut res = data.to_vec();
+    let tag = cipher.encrypt_in_place_detached(&Nonce::default(), &[], &mut res)
+        .expect("data is within the cipher's limits");
+    res.extend_from_slice(&tag);
+    res
+}
+
+fn decrypt(rho: &[u8; 32], ciphertext: &[u8]) -> Result<Vec<u8>, BlindingError> {
+    if ciphertext.len() < MAC_LEN {
+        return Err(BlindingError::DecryptionFailed)
+    }
+    let (data, tag) = ciphertext.split_at(ciphertext.len() - MAC_LEN);
+    let cipher = ChaCha20Poly1305::new(rho.into());
+    let mut res = data.to_vec();
+    cipher.decrypt_in_place_detached(&Nonce::default(), &[], &mut res, Tag::from_slice(tag))
+        .map_err(|_| BlindingError::DecryptionFailed)?;
+    Ok(res)
+}
+
+/// Blinds `path`, from the introduction node to the recipient, with the random `session_key`.
+///
+/// The data of the nodes can't contain a `next_path_key_override`, since the path keys of the
+/// following hops are derived from `session_key`: use [`create_blinded_path_to`] to lead into
+/// another blinded path.
+pub fn create_blinded_path(session_key: &SecretKey, path: &[PathNode]) -> Result<BlindedPath, BlindingError> {
+    blind_path(session_key, path, None)
+}
+
+/// Blinds `path` with the random `session_key` and appends `next`, a blinded path built by
+/// someone else. The last node of `path` gets a `next_path_key_override` with the path key of
+/// `next`, and its data must lead to `next`'s introduction node.
+///
+/// `next.first_path_key` must be the path key of `next.hops[0]`, as it is for any path from
+/// [`create_blinded_path`]. This can't be checked without the introduction node's secret, and
+/// with any other key the introduction node fails to decrypt its data.
+pub fn create_blinded_path_to(session_key: &SecretKey, path: &[PathNode], next: &BlindedPath) -> Result<BlindedPath, BlindingError> {
+    blind_path(session_key, path, Some(next))
+}
+
+/// Blinds `path`, then appends the hops of `next` with an override leading into it from the last
+/// node. Overrides in the data of `path` itself are refused.
+fn blind_path(session_key: &SecretKey, path: &[PathNode], next: Option<&BlindedPath>) -> Result<BlindedPath, BlindingError> {
+    if path.iter().any(|node| node.recipient_data.get(8).is_some()) {
+        return Err(BlindingError::UnexpectedOverride)
+    }
+    let first = path.first().ok_or(BlindingError::EmptyPath)?;
+    if next.is_some_and(|next| next.hops.is_empty()) {
+        return Err(BlindingError::EmptyPath)
+    }
+
+    let secp_ctx = Secp256k1::new();
+    let mut ephemeral_secret = *session_key;
+    let mut hops = Vec::with_capacity(path.len() + next.map_or(0, |next| next.hops.len()));
+    for (i, node) in path.iter().enumerate() {
+        let path_key = PublicKey::from_secret_key(&secp_ctx, &ephemeral_secret);
+        let shared_secret = SharedSecret::new(&node.node_id, &ephemeral_secret).secret_bytes();
+
+        let mut blinded_node_id = node.node_id;
+        blinded_node_id.mul_assign(&secp_ctx, &generate_key(b"blinded_node_id", &shared_secret))
+            .map_err(|_| BlindingError::InvalidKey)?;
+        let data = match next {
+            Some(next) if i == path.len() - 1 => {
+                let mut recipient_data = node.recipient_data.clone();
+                recipient_data.insert(EncryptedDataTlvs::NextPathKeyOverride(next.first_path_key));
+                recipient_data.encode()
+            }
+            _ => node.recipient_data.encode(),
+        }.expect("writing to a vec doesn't fail");
+        let encrypted_recipient_data = encrypt(&generate_key(b"rho", &shared_secret), &data);
+        hops.push(BlindedHop { blinded_node_id, encrypted_recipient_data });
+
+        ephemeral_secret.mul_assign(&blinding_factor(&path_key, &shared_secret))
+            .map_err(|_| BlindingError::InvalidKey)?;
+    }
+    if let Some(next) = next {
+        hops.extend_from_slice(&next.hops);
+    }
+    Ok(BlindedPath {
+        introduction_node_id: first.node_id,
+        first_path_key: PublicKey::from_secret_key(&secp_ctx, session_key),
+        hops,
+    })
+}
+
+impl BlindedPath {
+    /// The onion hops paying `amt_to_forward` to the recipient along the path, to be appended to
+    /// the route leading to the introduction node. `total_amount_msat` is the amount of the whole
+    /// payment, which may be split over several paths.
+    pub fn onion_hops(&self, amt_to_forward: u64, outgoing_cltv_value: u32, total_amount_msat: u64) -> Result<Vec<OnionHop>, BlindingError> {
+        let last = self.hops.len().checked_sub(1).ok_or(BlindingError::EmptyPath)?;
+        Ok(self.hops.iter().enumerate().map(|(i, hop)| {
+            let mut payload = HopPayload {
+                encrypted_recipient_data: Some(hop.encrypted_recipient_data.clone()),
+                ..Default::default()
+            };
+            // The introduction node doesn't get a path key in update_add_htlc
+            if i == 0 {
+                payload.current_path_key = Some(self.first_path_key);
+            }
+            if i == last {
+                payload.amt_to_forward = Some(amt_to_forward);
+                payload.outgoing_cltv_value = Some(outgoing_cltv_value);
+                payload.total_amount_msat = Some(total_amount_msat);
+            }
+            OnionHop {
+                node_id: if i == 0 { self.introduction_node_id } else { hop.blinded_node_id },
+                payload: payload.encode().expect("writing to a vec doesn't fail"),
+            }
+        }).collect())
+    }
+}
+
+/// The secret a node uses to peel the onion of an HTLC which came with `path_key` in its
+/// update_add_htlc.
+pub fn blinded_node_secret(node_secret: &SecretKey, path_key: &PublicKey) -> Result<SecretKey, BlindingError> {
+    let shared_secret = SharedSecret::new(path_key, node_secret).secret_bytes();
+    let mut secret = *node_secret;
+    secret.mul_assign(&generate_key(b"blinded_node_id", &shared_secret)).map_err(|_| BlindingError::InvalidKey)?;
+    Ok(secret)
+}
+
+/// Decrypts the `encrypted_recipient_data` a node got with `path_key`, and derives the path key of
+/// the next node.
+pub fn decrypt_recipient_data(node_secret: &SecretKey, path_key: &PublicKey, encrypted_recipient_data: &[u8]) -> Result<UnblindedData, BlindingError> {
+    let shared_secret = SharedSecret::new(path_key, node_secret).secret_bytes();
+    let data = decrypt(&generate_key(b"rho", &shared_secret), encrypted_recipient_data)?;
+    let recipient_data: TLVStream<EncryptedDataTlvs> = Readable::read(&mut &data[..]).map_err(BlindingError::Decode)?;
+
+    let next_path_key = match recipient_data.get(8) {
+        Some(EncryptedDataTlvs::NextPathKeyOverride(key)) => *key,
+        _ => {
+            let mut key = *path_key;
+            key.mul_assign(&Secp256k1::verification_only(), &blinding_factor(path_key, &shared_secret))
+                .map_err(|_| BlindingError::InvalidKey)?;
+            key
+        }
+    };
+    Ok(UnblindedData { recipient_data, next_path_key })
+}
+
+/// Peels the onion of an HTLC received within a blinded path. `path_key` is the one from
+/// update_add_htlc, which only the introduction node doesn't get: it finds it in its payload as
+/// `current_path_key` instead.
+pub fn peel_blinded_onion(node_secret: &SecretKey, path_key: Option<&PublicKey>, packet: &OnionPacket, associated_data: &[u8]) -> Result<BlindedReceive, BlindingError> {
+    let peeled = match path_key {
+        Some(path_key) => peel_onion(&blinded_node_secret(node_secret, path_key)?, packet, associated_data)?,
+        None => peel_onion(node_secret, packet, associated_data)?,
+    };
+    let payload: HopPayload = Readable::read(&mut &peeled.payload[..]).map_err(PayloadError::Decode)?;
+    payload.check_blinded(peeled.next.is_none())?;
+
+    let path_key = match (path_key, &payload.current_path_key) {
+        (Some(key), None) | (None, Some(key)) => key,
+        (Some(_), Some(_)) => return Err(PayloadError::UnexpectedRecord(12).into()),
+        (None, None) => return Err(PayloadError::MissingRecord(12).into()),
+    };
+    let encrypted_recipient_data = payload.encrypted_recipient_data.as_ref().expect("checked");
+    let UnblindedData { recipient_data, next_path_key } = decrypt_recipient_data(node_secret, path_key, encrypted_recipient_data)?;
+    Ok(BlindedReceive { peeled, payload, recipient_data, next_path_key })
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{PublicKey, Secp256k1, SecretKey, ecdh::SharedSecret};
+
+    use crate::onion::{create_onion_packet, generate_key, HopPayload, OnionError, OnionHop, PayloadError};
+    use crate::ser::{Readable, Writeable};
+    use crate::tlv::TLVStream;
+    use crate::types::ShortChannelId;
+    use super::{blinded_node_secret, create_blinded_path, create_blinded_path_to, decrypt_recipient_data, peel_blinded_onion};
+    use super::{BlindedPath, BlindingError, EncryptedDataTlvs, PathNode, PaymentConstraints, PaymentRelay};
+
+    const ASSOCIATED_DATA: [u8; 32] = [0x42; 32];
+
+    fn secrets() -> Vec<SecretKey> {
+        (0x41..=0x43).map(|b| SecretKey::from_slice(&[b; 32]).unwrap()).collect()
+    }
+
+    fn node_id(secret: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), secret)
+    }
+
+    fn forward_data(scid: u64) -> TLVStream<EncryptedDataTlvs> {
+        let mut data = TLVStream::new();
+        data.insert(EncryptedDataTlvs::ShortChannelId(ShortChannelId(scid)));
+        data.insert(EncryptedDataTlvs::PaymentRelay(PaymentRelay {
+            cltv_expiry_delta: 144,
+            fee_proportional_millionths: 100,
+            fee_base_msat: 1000,
+        }));
+        data.insert(EncryptedDataTlvs::PaymentConstraints(PaymentConstraints {
+            max_cltv_expiry: 800_000,
+            htlc_minimum_msat: 1,
+        }));
+        data
+    }
+
+    fn path() -> Vec<PathNode> {
+        let mut final_data = TLVStream::new();
+        final_data.insert(EncryptedDataTlvs::PathId(vec![0xaa; 32]));
+        let secrets = secrets();
+        vec![
+            PathNode { node_id: node_id(&secrets[0]), recipient_data: forward_data(0x226) },
+            PathNode { node_id: node_id(&secrets[1]), recipient_data: forward_data(0x227) },
+            PathNode { node_id: node_id(&secrets[2]), recipient_data: final_data },
+        ]
+    }
+
+    #[test]
+    fn encrypted_data_encoding() {
+        let data = forward_data(0x226);
+        let encoded = concat!(
+            "02", "08", "0000000000000226",
+            "0a", "08", "0090", "00000064", "03e8",
+            "0c", "05", "000c3500", "01",
+        );
+        assert_eq!(hex::encode(data.encode().unwrap()), encoded);
+        let decoded: TLVStream<EncryptedDataTlvs> = Readable::read(&mut &hex::decode(encoded).unwrap()[..]).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(
+            format!("{:#}", decoded),
+            "short_channel_id 0x0x550, payment_relay cltv_expiry_delta=144 fee_proportional_millionths=100 fee_base_msat=1000, \
+             payment_constraints max_cltv_expiry=800000 htlc_minimum_msat=1"
+        );
+    }
+
+    #[test]
+    fn unblind_each_node() {
+        let session_key = SecretKey::from_slice(&[0x51; 32]).unwrap();
+        let path = path();
+        let blinded = create_blinded_path(&session_key, &path).unwrap();
+        assert_eq!(blinded.introduction_node_id, path[0].node_id);
+        assert_eq!(blinded.first_path_key, node_id(&session_key));
+
+        let mut path_key = blinded.first_path_key;
+        for ((secret, node), hop) in secrets().iter().zip(&path).zip(&blinded.hops) {
+            assert_ne!(hop.blinded_node_id, node.node_id);
+            assert_eq!(node_id(&blinded_node_secret(secret, &path_key).unwrap()), hop.blinded_node_id);
+            let unblinded = decrypt_recipient_data(secret, &path_key, &hop.encrypted_recipient_data).unwrap();
+            assert_eq!(unblinded.recipient_data, node.recipient_data);
+            path_key = unblinded.next_path_key;
+        }
+
+        let hop = &blinded.hops[1];
+        assert_eq!(
+            decrypt_recipient_data(&secrets()[1], &blinded.first_path_key, &hop.encrypted_recipient_data),
+            Err(BlindingError::DecryptionFailed)
+        );
+        assert_eq!(create_blinded_path(&session_key, &[]), Err(BlindingError::EmptyPath));
+    }
+
+    #[test]
+    fn next_path_key_override() {
+        let secrets: Vec<SecretKey> = (0x41..=0x45).map(|b| SecretKey::from_slice(&[b; 32]).unwrap()).collect();
+        let mut path: Vec<PathNode> = secrets.iter().map(|s| PathNode { node_id: node_id(s), recipient_data: forward_data(0x226) }).collect();
+        let next = create_blinded_path(&SecretKey::from_slice(&[0x61; 32]).unwrap(), &path[3..]).unwrap();
+        path[2].recipient_data = TLVStream::new();
+        path[2].recipient_data.insert(EncryptedDataTlvs::NextNodeId(next.introduction_node_id));
+        let blinded = create_blinded_path_to(&SecretKey::from_slice(&[0x51; 32]).unwrap(), &path[..3], &next).unwrap();
+        assert_eq!(blinded.hops.len(), 5);
+        assert_eq!(blinded.hops[3..], next.hops[..]);
+
+        // The last node of the first part hands the path key of the second one
+        let mut path_key = blinded.first_path_key;
+        for ((secret, node), hop) in secrets.iter().zip(&path).zip(&blinded.hops) {
+            assert_eq!(node_id(&blinded_node_secret(secret, &path_key).unwrap()), hop.blinded_node_id);
+            let unblinded = decrypt_recipient_data(secret, &path_key, &hop.encrypted_recipient_data).unwrap();
+            assert_eq!(unblinded.recipient_data.get(2), node.recipient_data.get(2));
+            path_key = unblinded.next_path_key;
+            if hop == &blinded.hops[2] {
+                assert_eq!(unblinded.recipient_data.get(8), Some(&EncryptedDataTlvs::NextPathKeyOverride(next.first_path_key)));
+                assert_eq!(path_key, next.first_path_key);
+            }
+        }
+
+        path[2].recipient_data.insert(EncryptedDataTlvs::NextPathKeyOverride(next.first_path_key));
+        let session_key = SecretKey::from_slice(&[0x51; 32]).unwrap();
+        assert_eq!(create_blinded_path(&session_key, &path), Err(BlindingError::UnexpectedOverride));
+        assert_eq!(create_blinded_path_to(&session_key, &path[..3], &next), Err(BlindingError::UnexpectedOverride));
+        assert_eq!(create_blinded_path_to(&session_key, &[], &next), Err(BlindingError::EmptyPath));
+    }
+
+    /// The blinded path of the BOLT #4 route blinding test vector, which Bob builds in front of the
+    /// Dave -> Eve path Eve gave him, with a `next_path_key_override` in Carol's data. Each hop's
+    /// path key, blinded node id and encoded TLVs are those of the vector, and its encrypted data is
+    /// pinned byte for byte.
+    #[test]
+    fn route_blinding_vector() {
+        let secrets: Vec<SecretKey> = (0x42..=0x45).map(|b| SecretKey::from_slice(&[b; 32]).unwrap()).collect();
+        let data = |records: Vec<EncryptedDataTlvs>, unknown: Option<(u64, &str)>| {
+            let mut data = TLVStream::new();
+            records.into_iter().for_each(|r| data.insert(r));
+            if let Some((record_type, value)) = unknown {
+                data.insert_unknown(record_type, hex::decode(value).unwrap());
+            }
+            data
+        };
+        let dave_eve = [
+            PathNode {
+                node_id: node_id(&secrets[2]),
+                recipient_data: data(vec![EncryptedDataTlvs::Padding(vec![0; 35]), EncryptedDataTlvs::ShortChannelId(ShortChannelId(561))], None),
+            },
+            PathNode {
+                node_id: node_id(&secrets[3]),
+                recipient_data: data(vec![EncryptedDataTlvs::PathId(vec![0xde, 0xad, 0xbe, 0xef])], Some((65535, "06c1"))),
+            },
+        ];
+        let bob_carol = [
+            PathNode {
+                node_id: node_id(&secrets[0]),
+                recipient_data: data(vec![EncryptedDataTlvs::Padding(vec![0; 26]), EncryptedDataTlvs::ShortChannelId(ShortChannelId(1729))], Some((561, "123456"))),
+            },
+            PathNode {
+                node_id: node_id(&secrets[1]),
+                recipient_data: data(vec![EncryptedDataTlvs::NextNodeId(node_id(&secrets[2]))], None),
+            },
+        ];
+        let next = create_blinded_path(&SecretKey::from_slice(&[0x01; 32]).unwrap(), &dave_eve).unwrap();
+        let blinded = create_blinded_path_to(&SecretKey::from_slice(&[0x02; 32]).unwrap(), &bob_carol, &next).unwrap();
+
+        let test_vectors = [
+            (
+                "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c",
+                "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+                "03da173ad2aee2f701f17e59fbd16cb708906d69838a5f088e8123fb36e89a2c25",
+                "011a0000000000000000000000000000000000000000000000000000020800000000000006c1fd023103123456",
+                "cd4100ff9c09ed28102b210ac73aa12d63e90852cebc496c49f57c49982088b49f2e70b992870ae43b829897cfbeb07e90fd030e1d5b64b55154a6a7c1",
+            ),
+            (
+                "027f31ebc5462c1fdce1b737ecff52d37d75dea43ce11c74d25aa297165faa2007",
+                "034e09f450a80c3d252b258aba0a61215bf60dda3b0dc78ffb0736ea1259dfd8a0",
+                "02e466727716f044290abf91a14a6d90e87487da160c2a3cbd0d465d7a78eb83a7",
+                "0421032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e6686809910821031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                "ca26157e44ab01e82becf86497e1d05ad3e70903d22721210af41d791bf406873024d95b7a1ad128b2526932febfeeab237000563c1f33c78530b3880f8407326eef8bc004932b22323d13343ef740019c08e538e5c5",
+            ),
+            (
+                "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                "036861b366f284f0a11738ffbf7eda46241a8977592878fe3175ae1d1e4754eccf",
+                "0123000000000000000000000000000000000000000000000000000000000000000000000002080000000000000231",
+                "0fa0a72cff3b64a3d6e1e4903cf8c8b0a17144aeb249dcb86561adee1f679ee8db3e561d9c43815fd4bcebf6f58c5498e6f11cafba85a52dd86c126a2952fe",
+            ),
+            (
+                "02edabbd16b41c8371b92ef2f04c1185b4f03b6dcd52ba9b78d9d7c89c8f221145",
+                "03e09038ee76e50f444b19abf0a555e8697e035f62937168b80adf0931b31ce52a",
+                "021982a48086cb8984427d3727fe35a03d396b234f0701f5249daa12e8105c8dae",
+                "0604deadbeeffdffff0206c1",
+                "dd04a0f2c66edc677bbca8abaa6496cb4959543135a5ade8565ccdac",
+            ),
+        ];
+        assert_eq!(blinded.introduction_node_id.to_string(), test_vectors[0].0);
+        assert_eq!(blinded.first_path_key.to_string(), test_vectors[0].1);
+
+        let mut path_key = blinded.first_path_key;
+        let nodes = bob_carol.iter().chain(&dave_eve);
+        for (i, ((node, hop), (node_id, expected_path_key, blinded_node_id, encoded_tlvs, encrypted_data))) in nodes.zip(&blinded.hops).zip(test_vectors).enumerate() {
+            assert_eq!(node.node_id.to_string(), node_id);
+            assert_eq!(path_key.to_string(), expected_path_key);
+            assert_eq!(hop.blinded_node_id.to_string(), blinded_node_id);
+            assert_eq!(hex::encode(&hop.encrypted_recipient_data), encrypted_data);
+
+            let unblinded = decrypt_recipient_data(&secrets[i], &path_key, &hop.encrypted_recipient_data).unwrap();
+            assert_eq!(hex::encode(unblinded.recipient_data.encode().unwrap()), encoded_tlvs);
+            if i == 1 {
+                assert_eq!(unblinded.recipient_data.get(8), Some(&EncryptedDataTlvs::NextPathKeyOverride(next.first_path_key)));
+            } else {
+                assert_eq!(unblinded.recipient_data, node.recipient_data);
+            }
+            path_key = unblinded.next_path_key;
+        }
+
+        // Bob's shared secret and rho
+        let shared_secret = SharedSecret::new(&blinded.first_path_key, &secrets[0]).secret_bytes();
+        assert_eq!(hex::encode(shared_secret), "76771bab0cc3d0de6e6f60147fd7c9c7249a5ced3d0612bdfaeec3b15452229d");
+        assert_eq!(hex::encode(generate_key(b"rho", &shared_secret)), "ba217b23c0978d84c4a19be8a9ff64bc1b40ed0d7ecf59521567a5b3a9a1dd48");
+    }
+
+    #[test]
+    fn pay_through_blinded_path() {
+        let secrets = secrets();
+        let blinded = create_blinded_path(&SecretKey::from_slice(&[0x51; 32]).unwrap(), &path()).unwrap();
+        let hops = blinded.onion_hops(1000, 700_000, 3000).unwrap();
+        let (packet, _) = create_onion_packet(&SecretKey::from_slice(&[0x52; 32]).unwrap(), &hops, &ASSOCIATED_DATA).unwrap();
+
+        // The introduction node finds its path key in the payload
+        let intro = peel_blinded_onion(&secrets[0], None, &packet, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(intro.recipient_data, forward_data(0x226));
+        assert_eq!(intro.payload.current_path_key, Some(blinded.first_path_key));
+        let packet = intro.peeled.next.unwrap();
+
+        // The next ones get it in update_add_htlc
+        let middle = peel_blinded_onion(&secrets[1], Some(&intro.next_path_key), &packet, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(middle.recipient_data, forward_data(0x227));
+        let packet = middle.peeled.next.unwrap();
+
+        assert_eq!(
+            peel_blinded_onion(&secrets[2], None, &packet, &ASSOCIATED_DATA).unwrap_err(),
+            BlindingError::Onion(OnionError::InvalidHmac)
+        );
+        let recipient = peel_blinded_onion(&secrets[2], Some(&middle.next_path_key), &packet, &ASSOCIATED_DATA).unwrap();
+        assert!(recipient.peeled.next.is_none());
+        assert_eq!(recipient.recipient_data.get(6), Some(&EncryptedDataTlvs::PathId(vec![0xaa; 32])));
+        assert_eq!(recipient.payload.amt_to_forward, Some(1000));
+        assert_eq!(recipient.payload.total_amount_msat, Some(3000));
+
+        let empty = BlindedPath { hops: vec![], ..blinded };
+        assert_eq!(empty.onion_hops(1000, 700_000, 3000), Err(BlindingError::EmptyPath));
+    }
+
+    #[test]
+    fn blinded_onion_failures() {
+        let secrets = secrets();
+        let blinded = create_blinded_path(&SecretKey::from_slice(&[0x51; 32]).unwrap(), &path()).unwrap();
+        let path_key = blinded.first_path_key;
+        let final_payload = HopPayload {
+            amt_to_forward: Some(1000),
+            outgoing_cltv_value: Some(700_000),
+            encrypted_recipient_data: Some(blinded.hops[0].encrypted_recipient_data.clone()),
+            total_amount_msat: Some(1000),
+            ..Default::default()
+        };
+
+        // A single hop onion for the first node of the path
+        let peel = |payload: &HopPayload, path_key: Option<&PublicKey>| {
+            let node_id = match path_key {
+                Some(key) => node_id(&blinded_node_secret(&secrets[0], key).unwrap()),
+                None => node_id(&secrets[0]),
+            };
+            let hops = [OnionHop { node_id, payload: payload.encode().unwrap() }];
+            let (packet, _) = create_onion_packet(&SecretKey::from_slice(&[0x52; 32]).unwrap(), &hops, &ASSOCIATED_DATA).unwrap();
+            peel_blinded_onion(&secrets[0], path_key, &packet, &ASSOCIATED_DATA)
+        };
+        assert!(peel(&final_payload, Some(&path_key)).is_ok());
+
+        let failures = [
+            (final_payload.clone(), None, PayloadError::MissingRecord(12)),
+            (HopPayload { current_path_key: Some(path_key), ..final_payload.clone() }, Some(&path_key), PayloadError::UnexpectedRecord(12)),
+            (HopPayload { total_amount_msat: None, ..final_payload.clone() }, Some(&path_key), PayloadError::MissingRecord(18)),
+            (HopPayload { short_channel_id: Some(ShortChannelId(1)), ..final_payload.clone() }, Some(&path_key), PayloadError::UnexpectedRecord(6)),
+        ];
+        for (payload, path_key, error) in failures {
+            assert_eq!(peel(&payload, path_key).unwrap_err(), BlindingError::Payload(error));
+        }
+
+        let mut tampered = final_payload;
+        tampered.encrypted_recipient_data.as_mut().unwrap()[0] ^= 1;
+        assert_eq!(peel(&tampered, Some(&path_key)).unwrap_err(), BlindingError::DecryptionFailed);
+    }
+}
@@ -0,0 +1,566 @@
+//! Decoding of messages borrowing their opaque fields from the input buffer.
+//!
+//! The types here mirror the owned messages of `msgs`, except that padding, scripts, onion
+//! packets, failure reasons and TLV values are `&'a [u8]` slices of the input. Decoding them
+//! doesn't allocate, which matters when most messages are only inspected and dropped.
+
+use core::marker::PhantomData;
+
+use secp256k1::{PublicKey, ecdsa::Signature};
+
+use crate::bigsize::BigSize;
+use crate::msgs::*;
+use crate::ser::{Readable, DecodeError, TU16, TU32, TU64};
+use crate::tlv::TLVNamespace;
+use crate::types::{Txid, ChannelId, PaymentHash, PaymentPreimage, ShortChannelId};
+use crate::wire::{Type, MAX_MESSAGE_LEN};
+
+/// Objects that can be decoded from a byte slice, borrowing from it rather than allocating.
+pub trait ReadableRef<'a>: Sized {
+    fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError>;
+}
+
+/// Fixed-size types don't allocate when decoded, so they are read as usual.
+macro_rules! impl_readable_ref_owned {
+    ($($ty: ty),* $(,)?) => {
+        $(
+            impl<'a> ReadableRef<'a> for $ty {
+                fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+                    Readable::read(reader)
+                }
+            }
+        )*
+    }
+}
+
+impl_readable_ref_owned!(u8, u16, u32, u64, BigSize, TU16, TU32, TU64);
+impl_readable_ref_owned!(PublicKey, Signature, Txid, ChainHash);
+impl_readable_ref_owned!(ChannelId, PaymentHash, PaymentPreimage, ShortChannelId);
+
+impl<'a, const N: usize> ReadableRef<'a> for &'a [u8; N] {
+    fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+        read_slice(reader, N)?.try_into().map_err(|_| DecodeError::ShortRead)
+    }
+}
+
+/// Takes the next `len` bytes off the reader.
+pub fn read_slice<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if reader.len() < len {
+        return Err(DecodeError::ShortRead)
+    }
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(bytes)
+}
+
+/// A TLV stream whose records are left in the input buffer.
+///
+/// The structure of the stream is checked when it's read, as for `TLVStream`: canonical types
+/// and lengths, increasing types, no unknown even types and the required records of `N`. Known
+/// values are also checked by decoding them, which only allocates for value types that do, such
+/// as scripts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TLVStreamRef<'a, N: TLVNamespace> {
+    bytes: &'a [u8],
+    namespace: PhantomData<N>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TLVRecordRef<'a> {
+    pub record_type: u64,
+    pub value: &'a [u8],
+}
+
+/// Iterates over the records of a `TLVStreamRef`.
+#[derive(Debug, Clone)]
+pub struct TLVRecords<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> TLVRecords<'a> {
+    fn read_record(&mut self) -> Result<TLVRecordRef<'a>, DecodeError> {
+        let record_type: BigSize = Readable::read(&mut self.bytes)?;
+        let length: BigSize = Readable::read(&mut self.bytes)?;
+        let len = usize::try_from(length.0).map_err(|_| DecodeError::ShortRead)?;
+        let value = read_slice(&mut self.bytes, len)?;
+        Ok(TLVRecordRef { record_type: record_type.0, value })
+    }
+}
+
+impl<'a> Iterator for TLVRecords<'a> {
+    type Item = TLVRecordRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None
+        }
+        // The stream was checked when it was read
+        self.read_record().ok()
+    }
+}
+
+impl<'a, N: TLVNamespace> TLVStreamRef<'a, N> {
+    pub fn records(&self) -> TLVRecords<'a> {
+        TLVRecords { bytes: self.bytes }
+    }
+
+    /// The raw value of the record of `record_type`, if present.
+    pub fn get(&self, record_type: u64) -> Option<&'a [u8]> {
+        self.records().find(|r| r.record_type == record_type).map(|r| r.value)
+    }
+
+    /// Decodes the known record of `record_type`, if present.
+    pub fn decode(&self, record_type: u64) -> Option<N> {
+        let value = self.get(record_type)?;
+        // Known values were checked when the stream was read
+        N::read_record(record_type, value).ok().flatten()
+    }
+
+    /// The encoded stream
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+/// Like any TLV stream, it spans the rest of the reader.
+impl<'a, N: TLVNamespace> ReadableRef<'a> for TLVStreamRef<'a, N> {
+    fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+        let bytes = *reader;
+        let mut records = TLVRecords { bytes };
+        let mut prev: Option<u64> = None;
+        while !records.bytes.is_empty() {
+            let record = records.read_record()?;
+            if let Some(prev) = prev {
+                if prev >= record.record_type {
+                    return Err(DecodeError::TlvOutOfOrder { prev, got: record.record_type })
+                }
+            }
+            prev = Some(record.record_type);
+
+            let known = N::read_record(record.record_type, record.value).map_err(|e| {
+                match N::record_name(record.record_type) {
+                    Some(name) => e.in_field(name),
+                    None => e,
+                }
+            })?;
+            // It's ok to be odd
+            if known.is_none() && record.record_type.is_multiple_of(2) {
+                return Err(DecodeError::UnknownEvenType(record.record_type))
+            }
+        }
+
+        let stream = TLVStreamRef { bytes, namespace: PhantomData };
+        for typ in N::KNOWN_TYPES {
+            if N::is_required(*typ) && stream.get(*typ).is_none() {
+                return Err(DecodeError::MissingRequiredRecord(*typ))
+            }
+        }
+        *reader = &[];
+        Ok(stream)
+    }
+}
+
+/// Declares a borrowed message made up of fields which are all `ReadableRef`, read in order.
+macro_rules! borrowed_msg {
+    ($(#[$meta: meta])* $st: ident { $($field: ident: $ty: ty),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $st<'a> {
+            $( pub $field: $ty, )*
+        }
+
+        impl<'a> ReadableRef<'a> for $st<'a> {
+            fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+                Ok($st {
+                    $( $field: ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field(stringify!($field)))?, )*
+                })
+            }
+        }
+    }
+}
+
+/// Declares a borrowed message ending in a u16 length-prefixed byte array.
+macro_rules! borrowed_data_msg {
+    ($(#[$meta: meta])* $st: ident { $($field: ident: $ty: ty,)* ..$data: ident }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $st<'a> {
+            $( pub $field: $ty, )*
+            pub $data: &'a [u8],
+        }
+
+        impl<'a> ReadableRef<'a> for $st<'a> {
+            fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+                $( let $field: $ty = ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field(stringify!($field)))?; )*
+                let len: u16 = ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field(stringify!($data)))?;
+                let $data = read_slice(reader, len as usize).map_err(|e| e.in_field(stringify!($data)))?;
+                Ok($st { $($field,)* $data })
+            }
+        }
+    }
+}
+
+borrowed_data_msg!(
+    /// A borrowed `error`
+    ErrorMessageRef { channel_id: ChannelId, ..data }
+);
+borrowed_data_msg!(
+    /// A borrowed `warning`
+    WarningMessageRef { channel_id: ChannelId, ..data }
+);
+borrowed_data_msg!(
+    /// A borrowed `ping`
+    PingRef { num_pong_bytes: u16, ..ignored }
+);
+borrowed_data_msg!(
+    /// A borrowed `pong`
+    PongRef { ..ignored }
+);
+borrowed_data_msg!(
+    /// A borrowed `shutdown`
+    ShutdownRef { channel_id: ChannelId, ..scriptpubkey }
+);
+borrowed_data_msg!(
+    /// A borrowed `update_fail_htlc`
+    UpdateFailHTLCRef { channel_id: ChannelId, id: u64, ..reason }
+);
+
+borrowed_msg!(
+    /// A borrowed `open_channel`
+    OpenChannelRef {
+        chain_hash: ChainHash,
+        temp_channel_id: ChannelId,
+        funding_sats: u64,
+        push_msat: u64,
+        dust_limit_sats: u64,
+        max_htlc_value_in_flight_msat: u64,
+        channel_reserve_sats: u64,
+        htlc_min_msat: u64,
+        feerate_per_kw: u32,
+        to_self_delay: u16,
+        max_accepted_htlcs: u16,
+        funding_pubkey: PublicKey,
+        revocation_basepoint: PublicKey,
+        payment_basepoint: PublicKey,
+        delayed_payment_basepoint: PublicKey,
+        htlc_basepoint: PublicKey,
+        first_per_commitment_point: PublicKey,
+        channel_flags: u8,
+        tlv_stream: TLVStreamRef<'a, OpenChannelTlvs>,
+    }
+);
+borrowed_msg!(
+    /// A borrowed `accept_channel`
+    AcceptChannelRef {
+        temp_channel_id: ChannelId,
+        dust_limit_sats: u64,
+        max_htlc_value_in_flight_msat: u64,
+        channel_reserve_sats: u64,
+        htlc_min_msat: u64,
+        min_depth: u32,
+        to_self_delay: u16,
+        max_accepted_htlcs: u16,
+        funding_pubkey: PublicKey,
+        revocation_basepoint: PublicKey,
+        payment_basepoint: PublicKey,
+        delayed_payment_basepoint: PublicKey,
+        htlc_basepoint: PublicKey,
+        first_per_commitment_point: PublicKey,
+        accept_channel_tlvs: TLVStreamRef<'a, AcceptChannelTlvs>,
+    }
+);
+borrowed_msg!(
+    /// A borrowed `closing_signed`
+    ClosingSignedRef {
+        channel_id: ChannelId,
+        fee_sats: u64,
+        signature: Signature,
+        tlv_stream: TLVStreamRef<'a, ClosingSignedTlvs>,
+    }
+);
+borrowed_msg!(
+    /// A borrowed `update_add_htlc`
+    UpdateAddHTLCRef {
+        channel_id: ChannelId,
+        id: u64,
+        amount_msat: u64,
+        payment_hash: PaymentHash,
+        cltv_expiry: u32,
+        onion_routing_packet: &'a [u8; 1366],
+        tlv_stream: TLVStreamRef<'a, UpdateAddHTLCTlvs>,
+    }
+);
+
+/// A borrowed `commitment_signed`, whose HTLC signatures are checked but left in the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitmentSignedRef<'a> {
+    pub channel_id: ChannelId,
+    pub signature: Signature,
+    pub num_htlc: u16,
+    htlc_signatures: &'a [u8],
+}
+
+impl<'a> CommitmentSignedRef<'a> {
+    pub fn htlc_signatures(&self) -> impl Iterator<Item = Signature> + 'a {
+        // Each signature was checked when the message was read
+        self.htlc_signatures.chunks_exact(64).filter_map(|s| Signature::from_compact(s).ok())
+    }
+}
+
+impl<'a> ReadableRef<'a> for CommitmentSignedRef<'a> {
+    fn read_ref(reader: &mut &'a [u8]) -> Result<Self, DecodeError> {
+        let channel_id = ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field("channel_id"))?;
+        let signature = ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field("signature"))?;
+        let num_htlc: u16 = ReadableRef::read_ref(reader).map_err(|e: DecodeError| e.in_field("num_htlc"))?;
+        let htlc_signatures = read_slice(reader, num_htlc as usize * 64)
+            .map_err(|e| e.in_field("htlc_signature"))?;
+        for s in htlc_signatures.chunks_exact(64) {
+            Signature::from_compact(s).map_err(|_| DecodeError::InvalidSignature.in_field("htlc_signature"))?;
+        }
+        Ok(CommitmentSignedRef { channel_id, signature, num_htlc, htlc_signatures })
+    }
+}
+
+/// A message decoded with `read_message_ref`. Messages without any opaque field don't allocate
+/// when decoded and are the owned ones; `init` is owned too since its features are merged.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageRef<'a> {
+    Warning(WarningMessageRef<'a>),
+    Init(Init),
+    Error(ErrorMessageRef<'a>),
+    Ping(PingRef<'a>),
+    Pong(PongRef<'a>),
+    OpenChannel(OpenChannelRef<'a>),
+    AcceptChannel(AcceptChannelRef<'a>),
+    FundingCreated(FundingCreated),
+    FundingSigned(FundingSigned),
+    FundingLocked(FundingLocked),
+    Shutdown(ShutdownRef<'a>),
+    ClosingSigned(ClosingSignedRef<'a>),
+    UpdateAddHTLC(UpdateAddHTLCRef<'a>),
+    UpdateFulfillHTLC(UpdateFulfillHTLC),
+    UpdateFailHTLC(UpdateFailHTLCRef<'a>),
+    CommitmentSigned(CommitmentSignedRef<'a>),
+    RevokeAndACK(RevokeAndACK),
+    UpdateFee(UpdateFee),
+    UpdateFailMalformedHTLC(UpdateFailMalformedHTLC),
+    ChannelReestablish(ChannelReestablish),
+    /// A message of odd type which we don't understand
+    Unknown { typ: u16, payload: &'a [u8] },
+}
+
+/// Like `wire::read_message`, but over a buffer holding exactly one message, which the decoded
+/// message borrows from.
+pub fn read_message_ref(bytes: &[u8]) -> Result<MessageRef<'_>, DecodeError> {
+    if bytes.len() > MAX_MESSAGE_LEN {
+        return Err(DecodeError::MessageTooLong)
+    }
+    let mut reader = bytes;
+    let reader = &mut reader;
+    let typ: u16 = ReadableRef::read_ref(reader)?;
+
+    let msg = match typ {
+        WarningMessage::TYPE => MessageRef::Warning(ReadableRef::read_ref(reader)?),
+        Init::TYPE => MessageRef::Init(Readable::read(reader)?),
+        ErrorMessage::TYPE => MessageRef::Error(ReadableRef::read_ref(reader)?),
+        Ping::TYPE => MessageRef::Ping(ReadableRef::read_ref(reader)?),
+        Pong::TYPE => MessageRef::Pong(ReadableRef::read_ref(reader)?),
+        OpenChannel::TYPE => MessageRef::OpenChannel(ReadableRef::read_ref(reader)?),
+        AcceptChannel::TYPE => MessageRef::AcceptChannel(ReadableRef::read_ref(reader)?),
+        FundingCreated::TYPE => MessageRef::FundingCreated(Readable::read(reader)?),
+        FundingSigned::TYPE => MessageRef::FundingSigned(Readable::read(reader)?),
+        FundingLocked::TYPE => MessageRef::FundingLocked(Readable::read(reader)?),
+        Shutdown::TYPE => MessageRef::Shutdown(ReadableRef::read_ref(reader)?),
+        ClosingSigned::TYPE => MessageRef::ClosingSigned(ReadableRef::read_ref(reader)?),
+        UpdateAddHTLC::TYPE => MessageRef::UpdateAddHTLC(ReadableRef::read_ref(reader)?),
+        UpdateFulfillHTLC::TYPE => MessageRef::UpdateFulfillHTLC(Readable::read(reader)?),
+        UpdateFailHTLC::TYPE => MessageRef::UpdateFailHTLC(ReadableRef::read_ref(reader)?),
+        CommitmentSigned::TYPE => MessageRef::CommitmentSigned(ReadableRef::read_ref(reader)?),
+        RevokeAndACK::TYPE => MessageRef::RevokeAndACK(Readable::read(reader)?),
+        UpdateFee::TYPE => MessageRef::UpdateFee(Readable::read(reader)?),
+        UpdateFailMalformedHTLC::TYPE => MessageRef::UpdateFailMalformedHTLC(Readable::read(reader)?),
+        ChannelReestablish::TYPE => MessageRef::ChannelReestablish(Readable::read(reader)?),
+        // It's ok to be odd
+        t if t % 2 == 0 => return Err(DecodeError::UnknownEvenType(t as u64)),
+        t => {
+            let payload = *reader;
+            *reader = &[];
+            MessageRef::Unknown { typ: t, payload }
+        }
+    };
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec::Vec};
+
+    use crate::msgs::{OpenChannelTlvs, ClosingSignedTlvs, FeeRange};
+    use crate::ser::DecodeError;
+    use crate::tlv::N1;
+    use crate::wire::{read_message, Message};
+    use super::{read_message_ref, MessageRef, ReadableRef, TLVStreamRef};
+
+    const CHANNEL_ID: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+    const POINT: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const SIGNATURE: &str = concat!(
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222"
+    );
+
+    /// Messages of every borrowed type, which `tests/zero_copy.rs` also decodes without allocating.
+    fn test_vectors() -> Vec<Vec<u8>> {
+        [
+            [ "0001", CHANNEL_ID, "0004", "74657374" ].concat(),
+            [ "0011", CHANNEL_ID, "0000" ].concat(),
+            "001200040002aaaa".to_string(),
+            "00130004bbbbbbbb".to_string(),
+            [
+                "0020",
+                "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+                CHANNEL_ID,
+                &"00".repeat(56),
+                &POINT.repeat(6),
+                "01",
+            ].concat(),
+            [ "0026", CHANNEL_ID, "0016", "0014751e76e8199196d454941c45d1b3a323f1433bd6" ].concat(),
+            [
+                "0027", CHANNEL_ID, "00000000000001f4", SIGNATURE,
+                "0110", "00000000000001f4", "00000000000003e8",
+            ].concat(),
+            [
+                "0080", CHANNEL_ID, "0000000000000002", "00000000000186a0",
+                &"03".repeat(32), "00000090", &"05".repeat(1366), "0021", POINT,
+            ].concat(),
+            [ "0083", CHANNEL_ID, "0000000000000002", "0003", "010203" ].concat(),
+            [ "0084", CHANNEL_ID, SIGNATURE, "0002", SIGNATURE, SIGNATURE ].concat(),
+            [ "0086", CHANNEL_ID, "000003e8" ].concat(),
+            "8001deadbeef".to_string(),
+        ].iter().map(|v| hex::decode(v).unwrap()).collect()
+    }
+
+    #[test]
+    fn same_as_owned_decoding() {
+        let open_channel_tlvs = [
+            "0020",
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+            CHANNEL_ID,
+            &"00".repeat(56),
+            &POINT.repeat(6),
+            "01",
+            "0000", "0102", "1000",
+        ].concat();
+        let mut test_vectors = test_vectors();
+        test_vectors.push(hex::decode(open_channel_tlvs).unwrap());
+
+        for vector in test_vectors {
+            let owned = read_message(&mut vector.as_slice()).expect("no failure");
+            let borrowed = read_message_ref(&vector).expect("no failure");
+            match (owned, borrowed) {
+                (Message::Warning(o), MessageRef::Warning(b)) => {
+                    assert_eq!((o.channel_id, o.data.as_slice()), (b.channel_id, b.data));
+                },
+                (Message::Error(o), MessageRef::Error(b)) => assert_eq!(o.data, b.data),
+                (Message::Ping(o), MessageRef::Ping(b)) => {
+                    assert_eq!((o.num_pong_bytes, o.ignored.as_slice()), (b.num_pong_bytes, b.ignored));
+                },
+                (Message::Pong(o), MessageRef::Pong(b)) => assert_eq!(o.ignored, b.ignored),
+                (Message::OpenChannel(o), MessageRef::OpenChannel(b)) => {
+                    assert_eq!(o.chain_hash, b.chain_hash);
+                    assert_eq!(o.first_per_commitment_point, b.first_per_commitment_point);
+                    assert_eq!(o.tlv_stream.get(0), b.tlv_stream.decode(0).as_ref());
+                    assert_eq!(o.tlv_stream.get(1), b.tlv_stream.decode(1).as_ref());
+                    assert_eq!(o.tlv_stream.len(), b.tlv_stream.records().count());
+                },
+                (Message::Shutdown(o), MessageRef::Shutdown(b)) => {
+                    assert_eq!(o.scriptpubkey.as_bytes(), b.scriptpubkey);
+                },
+                (Message::ClosingSigned(o), MessageRef::ClosingSigned(b)) => {
+                    assert_eq!(o.signature, b.signature);
+                    assert_eq!(
+                        b.tlv_stream.decode(1),
+                        Some(ClosingSignedTlvs::FeeRange(FeeRange { min_fee_satoshis: 500, max_fee_satoshis: 1000 }))
+                    );
+                    assert_eq!(o.tlv_stream.get(1), b.tlv_stream.decode(1).as_ref());
+                },
+                (Message::UpdateAddHTLC(o), MessageRef::UpdateAddHTLC(b)) => {
+                    assert_eq!(o.payment_hash, b.payment_hash);
+                    assert_eq!(&o.onion_routing_packet, b.onion_routing_packet);
+                    assert_eq!(o.tlv_stream.get(0), b.tlv_stream.decode(0).as_ref());
+                },
+                (Message::UpdateFailHTLC(o), MessageRef::UpdateFailHTLC(b)) => assert_eq!(o.reason, b.reason),
+                (Message::CommitmentSigned(o), MessageRef::CommitmentSigned(b)) => {
+                    assert_eq!(o.htlc_signature, b.htlc_signatures().collect::<Vec<_>>());
+                },
+                (Message::UpdateFee(o), MessageRef::UpdateFee(b)) => assert_eq!(o, b),
+                (Message::Unknown { typ, payload }, MessageRef::Unknown { typ: t, payload: p }) => {
+                    assert_eq!((typ, payload.as_slice()), (t, p));
+                },
+                (o, b) => panic!("{:?} decoded as {:?}", o, b),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let test_vectors = [
+            ("0012000400020000aa", None),
+            ("001200040004aa", Some(DecodeError::ShortRead.in_field("ignored"))),
+            ("00120004", Some(DecodeError::ShortRead.in_field("ignored"))),
+            ("0011000000", Some(DecodeError::ShortRead.in_field("channel_id"))),
+            ("8000", Some(DecodeError::UnknownEvenType(0x8000))),
+        ];
+
+        for (vector, err) in test_vectors {
+            let bytes = hex::decode(vector).unwrap();
+            match err {
+                Some(err) => assert_eq!(read_message_ref(&bytes).unwrap_err(), err),
+                None => assert!(read_message_ref(&bytes).is_ok()),
+            }
+        }
+
+        let mut bytes = vec![0x80, 0x01];
+        bytes.resize(65536, 0);
+        assert_eq!(read_message_ref(&bytes).unwrap_err(), DecodeError::MessageTooLong);
+    }
+
+    #[test]
+    fn tlv_stream_ref() {
+        let bytes = hex::decode(concat!("01", "01", "01", "21", "00", "fd00fe", "02", "0226")).unwrap();
+        let mut reader = bytes.as_slice();
+        let stream: TLVStreamRef<N1> = ReadableRef::read_ref(&mut reader).expect("no failure");
+        assert!(reader.is_empty());
+        assert_eq!(stream.records().map(|r| r.record_type).collect::<Vec<_>>(), [1, 0x21, 254]);
+        assert_eq!(stream.get(254), Some(&[0x02, 0x26][..]));
+        assert_eq!(stream.decode(254), Some(N1::Tlv4(550)));
+
+        let test_vectors = [
+            (concat!("02", "08", "0000000000000226", "01", "01", "2a"), DecodeError::TlvOutOfOrder { prev: 2, got: 1 }),
+            (concat!("12", "00"), DecodeError::UnknownEvenType(0x12)),
+            (concat!("fd0001", "00"), DecodeError::NonCanonicalBigSize),
+            (concat!("01", "01", "00"), DecodeError::InvalidData.in_field("tlv1")),
+            (concat!("0f", "ffffffffffffffffff", "00"), DecodeError::ShortRead),
+        ];
+        for (vector, err) in test_vectors {
+            let bytes = hex::decode(vector).unwrap();
+            let res: Result<TLVStreamRef<N1>, _> = ReadableRef::read_ref(&mut bytes.as_slice());
+            assert_eq!(res.unwrap_err(), err);
+        }
+
+        let bytes = hex::decode(concat!("00", "00", "01", "00")).unwrap();
+        let res: TLVStreamRef<OpenChannelTlvs> = ReadableRef::read_ref(&mut bytes.as_slice()).unwrap();
+        assert_eq!(res.records().count(), 2);
+
+        let bytes = hex::decode(concat!("05", "01", "01")).unwrap();
+        let res: Result<TLVStreamRef<Required>, _> = ReadableRef::read_ref(&mut bytes.as_slice());
+        assert_eq!(res.unwrap_err(), DecodeError::MissingRequiredRecord(2));
+    }
+
+    crate::tlv_namespace! {
+        enum Required: "required" {
+            2 => Even(u16) as "even", required;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Async transport over tokio streams.
+//!
+//! The handshake is performed with `connect` or `accept`, after which messages are exchanged
+//! through a `Framed` stream using `MessageCodec`, without blocking a thread per peer.
+
+use bytes::{Buf, BytesMut};
+use secp256k1::{PublicKey, SecretKey};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::ser::Writeable;
+use crate::transport::{
+    Handshake, MessageEncryptor, TransportError, ACT_ONE_LEN, ACT_TWO_LEN, ACT_THREE_LEN,
+    LENGTH_HEADER_LEN, MAC_LEN,
+};
+use crate::wire::{self, Message};
+
+/// Encrypts and decrypts the frames of an established connection: an encrypted 2-byte length,
+/// then the encrypted message, each with its MAC.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    encryptor: MessageEncryptor,
+    /// The length of the message whose header has been decrypted but whose body is incomplete.
+    /// The header consumed a nonce, so it can't be decrypted again.
+    pending_len: Option<usize>,
+}
+
+impl MessageCodec {
+    pub fn new(encryptor: MessageEncryptor) -> Self {
+        MessageCodec { encryptor, pending_len: None }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = TransportError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, TransportError> {
+        let len = match self.pending_len {
+            Some(len) => len,
+            None => {
+                if src.len() < LENGTH_HEADER_LEN {
+                    src.reserve(LENGTH_HEADER_LEN - src.len());
+                    return Ok(None)
+                }
+                let header = src[..LENGTH_HEADER_LEN].try_into().expect("length checked");
+                let len = self.encryptor.decrypt_length_header(&header)? as usize;
+                src.advance(LENGTH_HEADER_LEN);
+                self.pending_len = Some(len);
+                len
+            }
+        };
+
+        if src.len() < len + MAC_LEN {
+            src.reserve(len + MAC_LEN - src.len());
+            return Ok(None)
+        }
+        self.pending_len = None;
+        let body = src.split_to(len + MAC_LEN);
+        let msg = self.encryptor.decrypt_message(&body)?;
+        Ok(Some(wire::read_message(&mut &msg[..])?))
+    }
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = TransportError;
+
+    fn encode(&mut self, msg: &Message, dst: &mut BytesMut) -> Result<(), TransportError> {
+        let frame = self.encryptor.encrypt_message(&msg.encode()?)?;
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = TransportError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), TransportError> {
+        self.encode(&msg, dst)
+    }
+}
+
+/// Performs the handshake as the initiator and returns the framed connection.
+pub async fn connect<S>(mut stream: S, our_node_secret: SecretKey, their_node_id: PublicKey, ephemeral_key: SecretKey) -> Result<Framed<S, MessageCodec>, TransportError>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let mut handshake = Handshake::new_outbound(our_node_secret, their_node_id, ephemeral_key);
+    stream.write_all(&handshake.act_one()?).await?;
+    stream.flush().await?;
+
+    let mut act_two = [0; ACT_TWO_LEN];
+    stream.read_exact(&mut act_two).await?;
+    let (act_three, encryptor) = handshake.process_act_two(&act_two)?;
+    stream.write_all(&act_three).await?;
+    stream.flush().await?;
+
+    Ok(Framed::new(stream, MessageCodec::new(encryptor)))
+}
+
+/// Performs the handshake as the responder and returns the initiator's node id along with the
+/// framed connection.
+pub async fn accept<S>(mut stream: S, our_node_secret: SecretKey, ephemeral_key: SecretKey) -> Result<(PublicKey, Framed<S, MessageCodec>), TransportError>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let mut handshake = Handshake::new_inbound(our_node_secret, ephemeral_key);
+
+    let mut act_one = [0; ACT_ONE_LEN];
+    stream.read_exact(&mut act_one).await?;
+    stream.write_all(&handshake.process_act_one(&act_one)?).await?;
+    stream.flush().await?;
+
+    let mut act_three = [0; ACT_THREE_LEN];
+    stream.read_exact(&mut act_three).await?;
+    let (their_node_id, encryptor) = handshake.process_act_three(&act_three)?;
+
+    Ok((their_node_id, Framed::new(stream, MessageCodec::new(encryptor))))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use futures_util::{SinkExt, StreamExt};
+    use secp256k1::{PublicKey, SecretKey};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::msgs::{Ping, Pong};
+    use crate::transport::{Handshake, MessageEncryptor, TransportError};
+    use crate::types::ChannelId;
+    use crate::wire::Message;
+    use super::{accept, connect, MessageCodec};
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn node_id(secret: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(&secp256k1::Secp256k1::new(), secret)
+    }
+
+    fn encryptors() -> (MessageEncryptor, MessageEncryptor) {
+        let mut initiator = Handshake::new_outbound(secret(0x11), node_id(&secret(0x21)), secret(0x12));
+        let mut responder = Handshake::new_inbound(secret(0x21), secret(0x22));
+        let act_two = responder.process_act_one(&initiator.act_one().unwrap()).unwrap();
+        let (act_three, a) = initiator.process_act_two(&act_two).unwrap();
+        let (_, b) = responder.process_act_three(&act_three).unwrap();
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn framed_exchange() {
+        let (a, b) = tokio::io::duplex(64);
+
+        let responder = async {
+            let (their_node_id, mut framed) = accept(b, secret(0x21), secret(0x22)).await.unwrap();
+            while let Some(msg) = framed.next().await {
+                if let Message::Ping(ping) = msg.unwrap() {
+                    framed.send(Message::Pong(Pong::new(ping.num_pong_bytes))).await.unwrap();
+                }
+            }
+            their_node_id
+        };
+
+        let initiator = async {
+            let mut framed = connect(a, secret(0x11), node_id(&secret(0x21)), secret(0x12)).await.unwrap();
+            for n in [0, 4, 1000] {
+                framed.send(Message::Ping(Ping::new(n, 10))).await.unwrap();
+                assert_eq!(framed.next().await.unwrap().unwrap(), Message::Pong(Pong::new(n)));
+            }
+        };
+
+        let (their_node_id, ()) = tokio::join!(responder, initiator);
+        assert_eq!(their_node_id, node_id(&secret(0x11)));
+    }
+
+    #[test]
+    fn decode_partial_frames() {
+        let (a, b) = encryptors();
+        let (mut sender, mut receiver) = (MessageCodec::new(a), MessageCodec::new(b));
+
+        let msgs = [
+            Message::Ping(Ping::new(4, 2)),
+            Message::Error(crate::msgs::ErrorMessage::new(ChannelId([2; 32]), "oops")),
+        ];
+        let mut frames = BytesMut::new();
+        for msg in &msgs {
+            sender.encode(msg, &mut frames).unwrap();
+        }
+
+        // Feed the frames a byte at a time, as a slow peer would
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in frames {
+            src.extend_from_slice(&[byte]);
+            if let Some(msg) = receiver.decode(&mut src).unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, msgs);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decode_corrupted_frame() {
+        let (a, b) = encryptors();
+        let (mut sender, mut receiver) = (MessageCodec::new(a), MessageCodec::new(b));
+
+        let mut frame = BytesMut::new();
+        sender.encode(Message::Ping(Ping::new(4, 2)), &mut frame).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        assert_eq!(receiver.decode(&mut frame).unwrap_err(), TransportError::DecryptionFailed);
+    }
+}
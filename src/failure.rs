@@ -0,0 +1,633 @@
+//! Failure messages returned along the route of an HTLC, as defined by BOLT #4.
+//!
+//! The node which fails an HTLC wraps a `FailureMessage` with the secret it shares with the
+//! sender and obfuscates it. Each hop on the way back obfuscates it again with its own secret, so
+//! only the sender, who knows every shared secret, can tell which hop failed and why.
+
+use std::fmt;
+
+use bitcoin::hashes::{Hash, sha256};
+
+use crate::io::{self, Read, Write};
+use crate::bigsize::BigSize;
+use crate::msgs::{UpdateFailHTLC, UpdateFailMalformedHTLC};
+use crate::onion::{apply_stream, generate_key, hmac, HopKeys, OnionError, HMAC_LEN, ONION_PACKET_LEN};
+use crate::pretty::Describe;
+use crate::ser::{Readable, Writeable, DecodeError, FixedLengthReadable};
+use crate::types::ChannelId;
+
+/// The onion itself could not be processed, so the failure is reported with
+/// update_fail_malformed_htlc
+pub const BADONION: u16 = 0x8000;
+/// The failure is permanent, retrying won't help
+pub const PERM: u16 = 0x4000;
+/// The failure is with the node rather than a channel
+pub const NODE: u16 = 0x2000;
+/// A channel_update of the failing channel is included
+pub const UPDATE: u16 = 0x1000;
+
+/// The message and its padding add up to at least this many bytes, so that failures can't be told
+/// apart by their length.
+const PADDED_LEN: usize = 256;
+
+/// The fields of failure messages. They use the usual encodings, except for `channel_update` which
+/// is prefixed by its u16 length.
+trait FailureField: Sized + Describe {
+    fn read_field<R: Read>(reader: &mut R) -> Result<Self, DecodeError>;
+    fn write_field<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error>;
+}
+
+macro_rules! impl_failure_field {
+    ($($ty: ty),*) => {
+        $(
+            impl FailureField for $ty {
+                fn read_field<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                    Readable::read(reader)
+                }
+
+                fn write_field<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                    self.write(writer)
+                }
+            }
+        )*
+    }
+}
+
+impl_failure_field!(u16, u32, u64, BigSize, [u8; 32]);
+
+impl FailureField for Vec<u8> {
+    fn read_field<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let len: u16 = Readable::read(reader)?;
+        FixedLengthReadable::read(reader, len as usize)
+    }
+
+    fn write_field<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let len = u16::try_from(self.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field exceeds 65535 bytes"))?
+            .write(writer)?;
+        writer.write_all(self)?;
+        Ok(len + self.len())
+    }
+}
+
+/// Defines `FailureMessage` with the encoding and description of each failure code.
+macro_rules! failure_messages {
+    ($(
+        $(#[$doc: meta])*
+        ($code: expr) => $variant: ident $({ $($field: ident: $ty: ty),* $(,)? })? as $name: literal;
+    )*) => {
+        /// The failure an HTLC was failed with, which may come with details about the failing
+        /// channel or the HTLC as it was received.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum FailureMessage {
+            $(
+                $(#[$doc])*
+                $variant $({ $($field: $ty),* })?,
+            )*
+            /// A failure code we don't know, along with the data which follows it
+            Unknown { code: u16, data: Vec<u8> },
+        }
+
+        impl FailureMessage {
+            /// The failure code, including its flags.
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(FailureMessage::$variant { .. } => $code,)*
+                    FailureMessage::Unknown { code, .. } => *code,
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn write_data<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                match self {
+                    $(
+                        FailureMessage::$variant $({ $($field),* })? => {
+                            #[allow(unused_mut)]
+                            let mut len = 0;
+                            $($(len += $field.write_field(writer)?;)*)?
+                            Ok(len)
+                        }
+                    )*
+                    FailureMessage::Unknown { data, .. } => {
+                        writer.write_all(data)?;
+                        Ok(data.len())
+                    }
+                }
+            }
+        }
+
+        impl Readable for FailureMessage {
+            fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                let code: u16 = Readable::read(reader)?;
+                match code {
+                    $(
+                        code if code == $code => Ok(FailureMessage::$variant $({ $(
+                            $field: FailureField::read_field(reader)
+                                .map_err(|e: DecodeError| e.in_field(stringify!($field)))?,
+                        )* })?),
+                    )*
+                    code => {
+                        let mut data = Vec::new();
+                        reader.read_to_end(&mut data).map_err(|e| DecodeError::Io(e.kind()))?;
+                        Ok(FailureMessage::Unknown { code, data })
+                    }
+                }
+            }
+        }
+
+        impl Describe for FailureMessage {
+            fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $(
+                        FailureMessage::$variant $({ $($field),* })? => {
+                            f.write_str($name)?;
+                            $($(
+                                f.write_str(concat!(" ", stringify!($field), "="))?;
+                                $field.describe(f)?;
+                            )*)?
+                            Ok(())
+                        }
+                    )*
+                    FailureMessage::Unknown { code, data } => {
+                        write!(f, "unknown code={:#06x} data=", code)?;
+                        data.describe(f)
+                    }
+                }
+            }
+        }
+    }
+}
+
+failure_messages! {
+    /// The realm byte of a legacy hop payload was not understood
+    (PERM | 1) => InvalidRealm as "invalid_realm";
+    (NODE | 2) => TemporaryNodeFailure as "temporary_node_failure";
+    (PERM | NODE | 2) => PermanentNodeFailure as "permanent_node_failure";
+    /// The node requires a feature which the onion doesn't have
+    (PERM | NODE | 3) => RequiredNodeFeatureMissing as "required_node_feature_missing";
+    (BADONION | PERM | 4) => InvalidOnionVersion { sha256_of_onion: [u8; 32] } as "invalid_onion_version";
+    (BADONION | PERM | 5) => InvalidOnionHmac { sha256_of_onion: [u8; 32] } as "invalid_onion_hmac";
+    (BADONION | PERM | 6) => InvalidOnionKey { sha256_of_onion: [u8; 32] } as "invalid_onion_key";
+    /// The channel can't handle the HTLC right now, e.g. it is out of liquidity
+    (UPDATE | 7) => TemporaryChannelFailure { channel_update: Vec<u8> } as "temporary_channel_failure";
+    (PERM | 8) => PermanentChannelFailure as "permanent_channel_failure";
+    (PERM | 9) => RequiredChannelFeatureMissing as "required_channel_feature_missing";
+    /// The onion names a channel which doesn't lead anywhere from this node
+    (PERM | 10) => UnknownNextPeer as "unknown_next_peer";
+    (UPDATE | 11) => AmountBelowMinimum { htlc_msat: u64, channel_update: Vec<u8> } as "amount_below_minimum";
+    /// The fee left for the channel, given its `channel_update`, is too low
+    (UPDATE | 12) => FeeInsufficient { htlc_msat: u64, channel_update: Vec<u8> } as "fee_insufficient";
+    /// The cltv_expiry doesn't leave the channel's cltv_expiry_delta
+    (UPDATE | 13) => IncorrectCltvExpiry { cltv_expiry: u32, channel_update: Vec<u8> } as "incorrect_cltv_expiry";
+    (UPDATE | 14) => ExpiryTooSoon { channel_update: Vec<u8> } as "expiry_too_soon";
+    /// The final node doesn't know the payment_hash, or the amount or payment_secret are wrong.
+    /// `height` is the final node's best known block height.
+    (PERM | 15) => IncorrectOrUnknownPaymentDetails { htlc_msat: u64, height: u32 } as "incorrect_or_unknown_payment_details";
+    (18) => FinalIncorrectCltvExpiry { cltv_expiry: u32 } as "final_incorrect_cltv_expiry";
+    (19) => FinalIncorrectHtlcAmount { incoming_htlc_amt: u64 } as "final_incorrect_htlc_amount";
+    (UPDATE | 20) => ChannelDisabled { disabled_flags: u16, channel_update: Vec<u8> } as "channel_disabled";
+    (21) => ExpiryTooFar as "expiry_too_far";
+    /// The hop payload could not be decoded or lacks a record, at `offset` in the payload
+    (PERM | 22) => InvalidOnionPayload { record_type: BigSize, offset: u16 } as "invalid_onion_payload";
+    /// Not all parts of a multi-part payment arrived in time
+    (23) => MppTimeout as "mpp_timeout";
+    (BADONION | PERM | 24) => InvalidOnionBlinding { sha256_of_onion: [u8; 32] } as "invalid_onion_blinding";
+}
+
+impl FailureMessage {
+    /// Whether the onion was unreadable, see [`BADONION`].
+    pub fn is_bad_onion(&self) -> bool {
+        self.code() & BADONION != 0
+    }
+
+    /// Whether retrying along the same route can't succeed, see [`PERM`].
+    pub fn is_permanent(&self) -> bool {
+        self.code() & PERM != 0
+    }
+
+    /// Whether the failing node rather than a channel is to blame, see [`NODE`].
+    pub fn is_node(&self) -> bool {
+        self.code() & NODE != 0
+    }
+
+    /// Whether the message carries a `channel_update`, see [`UPDATE`].
+    pub fn has_update(&self) -> bool {
+        self.code() & UPDATE != 0
+    }
+}
+
+/// Fields of known failures are followed by an optional TLV stream, which is left unread.
+impl Writeable for FailureMessage {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let len = self.code().write(writer)?;
+        Ok(len + self.write_data(writer)?)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FailureMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.describe(f)
+        }
+        Writeable::write_fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureError {
+    /// The failure message doesn't fit in update_fail_htlc
+    MessageTooLong,
+    /// None of the hops' secrets match the packet's HMAC
+    UnknownOrigin,
+    /// The hop at index `hop` of the route returned a failure which doesn't decode
+    InvalidMessage { hop: usize, error: DecodeError },
+}
+
+impl std::error::Error for FailureError {}
+
+impl fmt::Display for FailureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureError::MessageTooLong => write!(f, "failure message too long"),
+            FailureError::UnknownOrigin => write!(f, "failure doesn't come from any hop of the route"),
+            FailureError::InvalidMessage { hop, error } => write!(f, "invalid failure from hop {}: {}", hop, error),
+        }
+    }
+}
+
+/// A failure the sender attributed to a hop of the route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedFailure {
+    /// The index in the route of the node which failed the HTLC
+    pub hop: usize,
+    pub message: FailureMessage,
+}
+
+/// Builds the `reason` of update_fail_htlc, as the node failing the HTLC, from the
+/// `shared_secret` it got when peeling the onion.
+pub fn create_failure_packet(shared_secret: &[u8; 32], failure: &FailureMessage) -> Result<Vec<u8>, FailureError> {
+    let message = failure.encode().expect("writing to a vec doesn't fail");
+    if message.len() > u16::MAX as usize - HMAC_LEN - 4 {
+        return Err(FailureError::MessageTooLong)
+    }
+    let pad_len = PADDED_LEN.saturating_sub(message.len());
+
+    let mut payload = Vec::with_capacity(4 + message.len() + pad_len);
+    (message.len() as u16).write(&mut payload).expect("writing to a vec doesn't fail");
+    payload.extend_from_slice(&message);
+    (pad_len as u16).write(&mut payload).expect("writing to a vec doesn't fail");
+    payload.resize(payload.len() + pad_len, 0);
+
+    let mut packet = hmac(&generate_key(b"um", shared_secret), &[&payload]).to_vec();
+    packet.extend_from_slice(&payload);
+    obfuscate_failure(shared_secret, &mut packet);
+    Ok(packet)
+}
+
+/// Obfuscates a failure packet with the `shared_secret` of this hop, before passing it back
+/// towards the sender. The node which created the packet has already done so.
+pub fn obfuscate_failure(shared_secret: &[u8; 32], packet: &mut [u8]) {
+    apply_stream(&generate_key(b"ammag", shared_secret), packet);
+}
+
+/// Finds out which hop of the route failed the HTLC, given the `hop_keys` returned when creating
+/// the onion, by removing each hop's obfuscation in turn until the HMAC matches.
+pub fn decode_failure(hop_keys: &[HopKeys], packet: &[u8]) -> Result<DecodedFailure, FailureError> {
+    if packet.len() < HMAC_LEN + 4 {
+        return Err(FailureError::UnknownOrigin)
+    }
+    let mut packet = packet.to_vec();
+    for (hop, keys) in hop_keys.iter().enumerate() {
+        obfuscate_failure(&keys.shared_secret, &mut packet);
+        let (mac, payload) = packet.split_at(HMAC_LEN);
+        if hmac(&generate_key(b"um", &keys.shared_secret), &[payload]) != mac {
+            continue
+        }
+        let message = read_failure_payload(payload).map_err(|error| FailureError::InvalidMessage { hop, error })?;
+        return Ok(DecodedFailure { hop, message })
+    }
+    Err(FailureError::UnknownOrigin)
+}
+
+fn read_failure_payload(mut payload: &[u8]) -> Result<FailureMessage, DecodeError> {
+    let message: Vec<u8> = FailureField::read_field(&mut payload).map_err(|e| e.in_field("failuremsg"))?;
+    let _pad: Vec<u8> = FailureField::read_field(&mut payload).map_err(|e| e.in_field("pad"))?;
+    if !payload.is_empty() {
+        return Err(DecodeError::LengthMismatch { expected: 0, got: payload.len() })
+    }
+    Readable::read(&mut &message[..])
+}
+
+/// Why an update_fail_malformed_htlc received from downstream is refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MalformedError {
+    /// The failure_code lacks the `BADONION` bit, for which the channel must be failed
+    MissingBadOnion(u16),
+    /// The sha256_of_onion isn't that of the onion we sent. We may retry, or fail the HTLC
+    /// upstream with another failure.
+    OnionMismatch,
+}
+
+impl std::error::Error for MalformedError {}
+
+impl fmt::Display for MalformedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MalformedError::MissingBadOnion(code) => write!(f, "malformed HTLC failure code {:#06x} lacks BADONION", code),
+            MalformedError::OnionMismatch => write!(f, "malformed HTLC doesn't commit to the onion we sent"),
+        }
+    }
+}
+
+fn sha256_of_onion(onion_routing_packet: &[u8; ONION_PACKET_LEN]) -> [u8; 32] {
+    sha256::Hash::hash(onion_routing_packet).into_inner()
+}
+
+impl FailureMessage {
+    /// The `BADONION` failure for an onion which couldn't be peeled, committing to the
+    /// `onion_routing_packet` as it was received.
+    ///
+    /// Errors found after the HMAC checked out, such as an invalid payload length, aren't
+    /// `BADONION`: the node can report them in a failure packet, e.g. as `invalid_onion_payload`.
+    pub fn from_onion_error(error: &OnionError, onion_routing_packet: &[u8; ONION_PACKET_LEN]) -> Option<Self> {
+        let sha256_of_onion = sha256_of_onion(onion_routing_packet);
+        match error {
+            OnionError::InvalidVersion(_) => Some(FailureMessage::InvalidOnionVersion { sha256_of_onion }),
+            OnionError::InvalidHmac => Some(FailureMessage::InvalidOnionHmac { sha256_of_onion }),
+            OnionError::InvalidKey => Some(FailureMessage::InvalidOnionKey { sha256_of_onion }),
+            OnionError::InvalidPayloadLength | OnionError::RouteTooLong => None,
+        }
+    }
+}
+
+/// Fails the HTLC `id` with update_fail_malformed_htlc, since peeling its onion failed with
+/// `error`. Returns `None` for errors which are reported with update_fail_htlc instead, see
+/// [`FailureMessage::from_onion_error`].
+pub fn fail_malformed_htlc(channel_id: ChannelId, id: u64, onion_routing_packet: &[u8; ONION_PACKET_LEN], error: &OnionError) -> Option<UpdateFailMalformedHTLC> {
+    let failure = FailureMessage::from_onion_error(error, onion_routing_packet)?;
+    let sha256_of_onion = sha256_of_onion(onion_routing_packet);
+    Some(UpdateFailMalformedHTLC { channel_id, id, sha256_of_onion, failure_code: failure.code() })
+}
+
+/// Turns an update_fail_malformed_htlc, received for an HTLC we forwarded with
+/// `onion_routing_packet`, into the update_fail_htlc failing HTLC `id` of the incoming
+/// `channel_id`.
+///
+/// The downstream node couldn't build a failure packet, so we do as the failing node, using the
+/// `shared_secret` from peeling the incoming onion.
+pub fn fail_htlc_from_malformed(malformed: &UpdateFailMalformedHTLC, onion_routing_packet: &[u8; ONION_PACKET_LEN], shared_secret: &[u8; 32], channel_id: ChannelId, id: u64) -> Result<UpdateFailHTLC, MalformedError> {
+    if malformed.failure_code & BADONION == 0 {
+        return Err(MalformedError::MissingBadOnion(malformed.failure_code))
+    }
+    if malformed.sha256_of_onion != sha256_of_onion(onion_routing_packet) {
+        return Err(MalformedError::OnionMismatch)
+    }
+
+    // BADONION failures we don't know also carry just the sha256_of_onion
+    let mut encoded = malformed.failure_code.encode().expect("writing to a vec doesn't fail");
+    encoded.extend_from_slice(&malformed.sha256_of_onion);
+    let failure: FailureMessage = Readable::read(&mut &encoded[..]).expect("BADONION failures are a sha256");
+
+    let reason = create_failure_packet(shared_secret, &failure).expect("BADONION failures fit");
+    Ok(UpdateFailHTLC::new(channel_id, id, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::{Hash, sha256};
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use crate::bigsize::BigSize;
+    use crate::msgs::{UpdateFailHTLC, UpdateFailMalformedHTLC};
+    use crate::types::ChannelId;
+    use crate::onion::{create_onion_packet, hop_keys, peel_onion, HopKeys, OnionError, OnionHop, OnionPacket};
+    use crate::ser::{DecodeError, Readable, Writeable};
+    use super::{create_failure_packet, decode_failure, obfuscate_failure, fail_malformed_htlc, fail_htlc_from_malformed};
+    use super::{DecodedFailure, FailureError, FailureMessage, MalformedError, BADONION, NODE, PERM, UPDATE};
+
+    fn route_keys() -> Vec<HopKeys> {
+        let secp_ctx = Secp256k1::new();
+        let route: Vec<PublicKey> = (0x41..0x46)
+            .map(|b| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[b; 32]).unwrap()))
+            .collect();
+        hop_keys(&SecretKey::from_slice(&[0x41; 32]).unwrap(), &route).unwrap()
+    }
+
+    #[test]
+    fn failure_codes() {
+        assert_eq!(FailureMessage::TemporaryNodeFailure.code(), 0x2002);
+        assert_eq!(FailureMessage::InvalidOnionHmac { sha256_of_onion: [0; 32] }.code(), BADONION | PERM | 5);
+        assert_eq!(FailureMessage::TemporaryChannelFailure { channel_update: vec![] }.code(), 0x1007);
+        assert_eq!(FailureMessage::IncorrectOrUnknownPaymentDetails { htlc_msat: 0, height: 0 }.code(), 0x400f);
+        assert_eq!(FailureMessage::MppTimeout.code(), 23);
+
+        let failure = FailureMessage::PermanentNodeFailure;
+        assert!(failure.is_permanent() && failure.is_node() && !failure.has_update() && !failure.is_bad_onion());
+        let failure = FailureMessage::FeeInsufficient { htlc_msat: 1000, channel_update: vec![0x01, 0x02] };
+        assert!(failure.has_update() && !failure.is_permanent());
+        assert_eq!(failure.code() & (NODE | UPDATE), UPDATE);
+    }
+
+    #[test]
+    fn failure_encoding() {
+        let failure = FailureMessage::FeeInsufficient { htlc_msat: 1000, channel_update: vec![0x01, 0x02] };
+        let encoded = concat!("100c", "00000000000003e8", "0002", "0102");
+        assert_eq!(failure.to_string(), encoded);
+        assert_eq!(format!("{:#}", failure), "fee_insufficient htlc_msat=1000 channel_update=0102");
+        assert_eq!(FailureMessage::read(&mut &hex::decode(encoded).unwrap()[..]).unwrap(), failure);
+
+        let failure = FailureMessage::InvalidOnionPayload { record_type: BigSize(65537), offset: 3 };
+        assert_eq!(failure.to_string(), "4016fe000100010003");
+        assert_eq!(FailureMessage::read(&mut &failure.encode().unwrap()[..]).unwrap(), failure);
+
+        // Trailing TLV records are ignored
+        let read = |vector: &str| FailureMessage::read(&mut &hex::decode(vector).unwrap()[..]);
+        assert_eq!(read("2002010100").unwrap(), FailureMessage::TemporaryNodeFailure);
+        assert_eq!(read("0042beef").unwrap(), FailureMessage::Unknown { code: 0x42, data: vec![0xbe, 0xef] });
+        assert_eq!(format!("{:#}", read("0042beef").unwrap()), "unknown code=0x0042 data=beef");
+        assert_eq!(read("10070005").unwrap_err(), DecodeError::ShortRead.in_field("channel_update"));
+
+        // A channel_update too long for its u16 length can't be written
+        let failure = FailureMessage::TemporaryChannelFailure { channel_update: vec![0; 70_000] };
+        assert_eq!(failure.encode().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn attribute_failures() {
+        let keys = route_keys();
+        let failure = FailureMessage::IncorrectOrUnknownPaymentDetails { htlc_msat: 1000, height: 800_000 };
+
+        // The final node fails the HTLC, each other hop obfuscates on the way back
+        let mut packet = create_failure_packet(&keys[4].shared_secret, &failure).unwrap();
+        assert_eq!(packet.len(), 32 + 2 + 2 + 256);
+        for key in keys[..4].iter().rev() {
+            obfuscate_failure(&key.shared_secret, &mut packet);
+        }
+        let msg = UpdateFailHTLC::new(ChannelId::ALL, 0, packet);
+        assert_eq!(msg.len, 292);
+        assert_eq!(decode_failure(&keys, &msg.reason), Ok(DecodedFailure { hop: 4, message: failure }));
+
+        let failure = FailureMessage::TemporaryChannelFailure { channel_update: vec![0xab; 300] };
+        let mut packet = create_failure_packet(&keys[1].shared_secret, &failure).unwrap();
+        assert_eq!(packet.len(), 32 + 2 + 2 + 2 + 300 + 2);
+        obfuscate_failure(&keys[0].shared_secret, &mut packet);
+        assert_eq!(decode_failure(&keys, &packet), Ok(DecodedFailure { hop: 1, message: failure }));
+    }
+
+    /// The "returning errors" vector of BOLT #4: the final node of the test route fails with
+    /// temporary_node_failure, and the packet is obfuscated by each hop on its way back.
+    #[test]
+    fn failure_vector() {
+        let keys = route_keys();
+        let failure = FailureMessage::TemporaryNodeFailure;
+        let mut packet = create_failure_packet(&keys[4].shared_secret, &failure).unwrap();
+
+        let mut payload = packet.clone();
+        obfuscate_failure(&keys[4].shared_secret, &mut payload);
+        let raw = ["4c2fc8bc08510334b6833ad9c3e79cd1b52ae59dfe5c2a4b23ead50f09f7ee0b0002200200fe", &"00".repeat(254)].concat();
+        assert_eq!(hex::encode(&payload), raw);
+
+        let obfuscated = [
+        concat!(
+            "a5e6bd0c74cb347f10cce367f949098f2457d14c046fd8a22cb96efb30b0fdcda8cb9168b50f2fd45edd73c1b0c8b330",
+            "02df376801ff58aaa94000bf8a86f92620f343baef38a580102395ae3abf9128d1047a0736ff9b83d456740ebbb4aeb3",
+            "aa9737f18fb4afb4aa074fb26c4d702f42968888550a3bded8c05247e045b866baef0499f079fdaeef6538f31d44deaf",
+            "ffdfd3afa2fb4ca9082b8f1c465371a9894dd8c243fb4847e004f5256b3e90e2edde4c9fb3082ddfe4d1e734cacd96ef",
+            "0706bf63c9984e22dc98851bcccd1c3494351feb458c9c6af41c0044bea3c47552b1d992ae542b17a2d0bba1a096c78d",
+            "169034ecb55b6e3a7263c26017f033031228833c1daefc0dedb8cf7c3e37c9c37ebfe42f3225c326e8bcfd338804c145",
+            "b16e34e4",
+        ),
+        concat!(
+            "c49a1ce81680f78f5f2000cda36268de34a3f0a0662f55b4e837c83a8773c22aa081bab1616a0011585323930fa5b9fa",
+            "e0c85770a2279ff59ec427ad1bbff9001c0cd1497004bd2a0f68b50704cf6d6a4bf3c8b6a0833399a24b3456961ba007",
+            "36785112594f65b6b2d44d9f5ea4e49b5e1ec2af978cbe31c67114440ac51a62081df0ed46d4a3df295da0b0fe25c011",
+            "5019f03f15ec86fabb4c852f83449e812f141a9395b3f70b766ebbd4ec2fae2b6955bd8f32684c15abfe8fd3a6261e52",
+            "650e8807a92158d9f1463261a925e4bfba44bd20b166d532f0017185c3a6ac7957adefe45559e3072c8dc35abeba835a",
+            "8cb01a71a15c736911126f27d46a36168ca5ef7dccd4e2886212602b181463e0dd30185c96348f9743a02aca8ec27c0b",
+            "90dca270",
+        ),
+        concat!(
+            "a5d3e8634cfe78b2307d87c6d90be6fe7855b4f2cc9b1dfb19e92e4b79103f61ff9ac25f412ddfb7466e74f81b3e5455",
+            "63cdd8f5524dae873de61d7bdfccd496af2584930d2b566b4f8d3881f8c043df92224f38cf094cfc09d9265598953152",
+            "4593ec6d6caec1863bdfaa79229b5020acc034cd6deeea1021c50586947b9b8e6faa83b81fbfa6133c0af5d6b07c017f",
+            "7158fa94f0d206baf12dda6b68f785b773b360fd0497e16cc402d779c8d48d0fa6315536ef0660f3f4e1865f5b38ea49",
+            "c7da4fd959de4e83ff3ab686f059a45c65ba2af4a6a79166aa0f496bf04d06987b6d2ea205bdb0d347718b9aeff5b61d",
+            "fff344993a275b79717cd815b6ad4c0beb568c4ac9c36ff1c315ec1119a1993c4b61e6eaa0375e0aaf738ac691abd326",
+            "3bf937e3",
+        ),
+        concat!(
+            "aac3200c4968f56b21f53e5e374e3a2383ad2b1b6501bbcc45abc31e59b26881b7dfadbb56ec8dae8857add94e6702fb",
+            "4c3a4de22e2e669e1ed926b04447fc73034bb730f4932acd62727b75348a648a1128744657ca6a4e713b9b646c3ca66c",
+            "ac02cdab44dd3439890ef3aaf61708714f7375349b8da541b2548d452d84de7084bb95b3ac2345201d624d31f4d52078",
+            "aa0fa05a88b4e20202bd2b86ac5b52919ea305a8949de95e935eed0319cf3cf19ebea61d76ba92532497fcdc9411d06b",
+            "cd4275094d0a4a3c5d3a945e43305a5a9256e333e1f64dbca5fcd4e03a39b9012d197506e06f29339dfee3331995b216",
+            "15337ae060233d39befea925cc262873e0530408e6990f1cbd233a150ef7b004ff6166c70c68d9f8c853c1abca640b86",
+            "60db2921",
+        ),
+        concat!(
+            "9c5add3963fc7f6ed7f148623c84134b5647e1306419dbe2174e523fa9e2fbed3a06a19f899145610741c83ad40b7712",
+            "aefaddec8c6baf7325d92ea4ca4d1df8bce517f7e54554608bf2bd8071a4f52a7a2f7ffbb1413edad81eeea5785aa9d9",
+            "90f2865dc23b4bc3c301a94eec4eabebca66be5cf638f693ec256aec514620cc28ee4a94bd9565bc4d4962b9d3641d42",
+            "78fb319ed2b84de5b665f307a2db0f7fbb757366067d88c50f7e829138fde4f78d39b5b5802f1b92a8a820865af5cc79",
+            "f9f30bc3f461c66af95d13e5e1f0381c184572a91dee1c849048a647a1158cf884064deddbf1b0b88dfe2f791428d0ba",
+            "0f6fb2f04e14081f69165ae66d9297c118f0907705c9c4954a199bae0bb96fad763d690e7daa6cfda59ba7f2c8d11448",
+            "b604d12d",
+        ),
+        ];
+        assert_eq!(hex::encode(&packet), obfuscated[0]);
+        for (key, expected) in keys[..4].iter().rev().zip(&obfuscated[1..]) {
+            obfuscate_failure(&key.shared_secret, &mut packet);
+            assert_eq!(hex::encode(&packet), *expected);
+        }
+        assert_eq!(decode_failure(&keys, &packet), Ok(DecodedFailure { hop: 4, message: failure }));
+    }
+
+    #[test]
+    fn unattributable_failures() {
+        let keys = route_keys();
+        let mut packet = create_failure_packet(&keys[2].shared_secret, &FailureMessage::ExpiryTooFar).unwrap();
+        obfuscate_failure(&keys[1].shared_secret, &mut packet);
+        obfuscate_failure(&keys[0].shared_secret, &mut packet);
+
+        let mut tampered = packet.clone();
+        tampered[40] ^= 1;
+        assert_eq!(decode_failure(&keys, &tampered), Err(FailureError::UnknownOrigin));
+        // The hop it came from isn't part of the route
+        assert_eq!(decode_failure(&keys[..2], &packet), Err(FailureError::UnknownOrigin));
+        assert_eq!(decode_failure(&keys, &packet[..10]), Err(FailureError::UnknownOrigin));
+
+        let failure = FailureMessage::Unknown { code: 1, data: vec![0; u16::MAX as usize] };
+        assert_eq!(create_failure_packet(&keys[0].shared_secret, &failure), Err(FailureError::MessageTooLong));
+    }
+
+    #[test]
+    fn malformed_htlcs() {
+        let secp_ctx = Secp256k1::new();
+        let secrets: Vec<SecretKey> = (0x41..0x44).map(|b| SecretKey::from_slice(&[b; 32]).unwrap()).collect();
+        let hops: Vec<OnionHop> = secrets.iter()
+            .map(|secret| OnionHop { node_id: PublicKey::from_secret_key(&secp_ctx, secret), payload: vec![0; 10] })
+            .collect();
+        let (packet, keys) = create_onion_packet(&SecretKey::from_slice(&[0x51; 32]).unwrap(), &hops, &[0x42; 32]).unwrap();
+
+        // The first hop forwards the HTLC, but with another payment_hash
+        let peeled = peel_onion(&secrets[0], &packet, &[0x42; 32]).unwrap();
+        let outgoing = peeled.next.unwrap().to_bytes();
+        let error = peel_onion(&secrets[1], &OnionPacket::from_bytes(&outgoing), &[0x43; 32]).unwrap_err();
+        let malformed = fail_malformed_htlc(ChannelId([1; 32]), 7, &outgoing, &error).unwrap();
+        let sha256_of_onion = sha256::Hash::hash(&outgoing).into_inner();
+        assert_eq!(malformed, UpdateFailMalformedHTLC {
+            channel_id: ChannelId([1; 32]),
+            id: 7,
+            sha256_of_onion,
+            failure_code: BADONION | PERM | 5,
+        });
+
+        let fail = fail_htlc_from_malformed(&malformed, &outgoing, &peeled.shared_secret, ChannelId::ALL, 3).unwrap();
+        assert_eq!(fail.channel_id, ChannelId::ALL);
+        assert_eq!(fail.id, 3);
+        assert_eq!(decode_failure(&keys, &fail.reason), Ok(DecodedFailure {
+            hop: 0,
+            message: FailureMessage::InvalidOnionHmac { sha256_of_onion },
+        }));
+
+        let unknown = UpdateFailMalformedHTLC { failure_code: BADONION | 99, ..malformed.clone() };
+        let fail = fail_htlc_from_malformed(&unknown, &outgoing, &peeled.shared_secret, ChannelId::ALL, 3).unwrap();
+        assert_eq!(decode_failure(&keys, &fail.reason).unwrap().message, FailureMessage::Unknown {
+            code: 0x8063,
+            data: sha256_of_onion.to_vec(),
+        });
+
+        let not_bad_onion = UpdateFailMalformedHTLC { failure_code: PERM | 5, ..malformed.clone() };
+        assert_eq!(
+            fail_htlc_from_malformed(&not_bad_onion, &outgoing, &peeled.shared_secret, ChannelId::ALL, 3),
+            Err(MalformedError::MissingBadOnion(0x4005))
+        );
+        assert_eq!(
+            fail_htlc_from_malformed(&malformed, &packet.to_bytes(), &peeled.shared_secret, ChannelId::ALL, 3),
+            Err(MalformedError::OnionMismatch)
+        );
+    }
+
+    #[test]
+    fn onion_errors() {
+        let onion = [0; 1366];
+        let sha256_of_onion = sha256::Hash::hash(&onion).into_inner();
+        assert_eq!(
+            FailureMessage::from_onion_error(&OnionError::InvalidVersion(1), &onion),
+            Some(FailureMessage::InvalidOnionVersion { sha256_of_onion })
+        );
+        assert_eq!(
+            FailureMessage::from_onion_error(&OnionError::InvalidKey, &onion),
+            Some(FailureMessage::InvalidOnionKey { sha256_of_onion })
+        );
+        assert_eq!(fail_malformed_htlc(ChannelId::ALL, 0, &onion, &OnionError::InvalidKey).unwrap().failure_code, 0xc006);
+        assert_eq!(fail_malformed_htlc(ChannelId::ALL, 0, &onion, &OnionError::InvalidPayloadLength), None);
+    }
+}
@@ -0,0 +1,488 @@
+use core::fmt;
+use alloc::vec::Vec;
+
+use crate::io::{self, Read, Write};
+
+use crate::ser::{Readable, Writeable, DecodeError, FixedLengthReadable};
+use crate::tlv::TLVValue;
+use crate::pretty::Describe;
+
+/// The message or invoice field a feature vector is carried in. A feature may only be set in
+/// the contexts it is defined for; in any other context it is treated as unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureContext {
+    /// `init` message
+    Init,
+    /// `node_announcement` message
+    NodeAnnouncement,
+    /// BOLT #11 invoices
+    Invoice,
+}
+
+use FeatureContext::{Init, NodeAnnouncement, Invoice};
+
+/// Features assigned in BOLT #9. Each feature occupies a pair of bits: the even bit is set when
+/// the feature is required ("compulsory") and the odd bit when it is optional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    DataLossProtect,
+    UpfrontShutdownScript,
+    GossipQueries,
+    VarOnionOptin,
+    GossipQueriesEx,
+    StaticRemoteKey,
+    PaymentSecret,
+    BasicMpp,
+    SupportLargeChannel,
+    AnchorOutputs,
+    AnchorsZeroFeeHtlcTx,
+    RouteBlinding,
+    ShutdownAnySegwit,
+    DualFund,
+    ChannelType,
+    ScidAlias,
+    PaymentMetadata,
+    ZeroConf,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 18] = [
+        Feature::DataLossProtect,
+        Feature::UpfrontShutdownScript,
+        Feature::GossipQueries,
+        Feature::VarOnionOptin,
+        Feature::GossipQueriesEx,
+        Feature::StaticRemoteKey,
+        Feature::PaymentSecret,
+        Feature::BasicMpp,
+        Feature::SupportLargeChannel,
+        Feature::AnchorOutputs,
+        Feature::AnchorsZeroFeeHtlcTx,
+        Feature::RouteBlinding,
+        Feature::ShutdownAnySegwit,
+        Feature::DualFund,
+        Feature::ChannelType,
+        Feature::ScidAlias,
+        Feature::PaymentMetadata,
+        Feature::ZeroConf,
+    ];
+
+    /// The even (required) bit of the feature. The optional bit is the one right above it.
+    pub fn bit(&self) -> usize {
+        match self {
+            Feature::DataLossProtect => 0,
+            Feature::UpfrontShutdownScript => 4,
+            Feature::GossipQueries => 6,
+            Feature::VarOnionOptin => 8,
+            Feature::GossipQueriesEx => 10,
+            Feature::StaticRemoteKey => 12,
+            Feature::PaymentSecret => 14,
+            Feature::BasicMpp => 16,
+            Feature::SupportLargeChannel => 18,
+            Feature::AnchorOutputs => 20,
+            Feature::AnchorsZeroFeeHtlcTx => 22,
+            Feature::RouteBlinding => 24,
+            Feature::ShutdownAnySegwit => 26,
+            Feature::DualFund => 28,
+            Feature::ChannelType => 44,
+            Feature::ScidAlias => 46,
+            Feature::PaymentMetadata => 48,
+            Feature::ZeroConf => 50,
+        }
+    }
+
+    /// The name the feature is given in BOLT #9.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::DataLossProtect => "option_data_loss_protect",
+            Feature::UpfrontShutdownScript => "option_upfront_shutdown_script",
+            Feature::GossipQueries => "gossip_queries",
+            Feature::VarOnionOptin => "var_onion_optin",
+            Feature::GossipQueriesEx => "gossip_queries_ex",
+            Feature::StaticRemoteKey => "option_static_remotekey",
+            Feature::PaymentSecret => "payment_secret",
+            Feature::BasicMpp => "basic_mpp",
+            Feature::SupportLargeChannel => "option_support_large_channel",
+            Feature::AnchorOutputs => "option_anchor_outputs",
+            Feature::AnchorsZeroFeeHtlcTx => "option_anchors_zero_fee_htlc_tx",
+            Feature::RouteBlinding => "option_route_blinding",
+            Feature::ShutdownAnySegwit => "option_shutdown_anysegwit",
+            Feature::DualFund => "option_dual_fund",
+            Feature::ChannelType => "option_channel_type",
+            Feature::ScidAlias => "option_scid_alias",
+            Feature::PaymentMetadata => "option_payment_metadata",
+            Feature::ZeroConf => "option_zeroconf",
+        }
+    }
+
+    /// The contexts the feature may be set in.
+    pub fn contexts(&self) -> &'static [FeatureContext] {
+        match self {
+            Feature::VarOnionOptin |
+            Feature::PaymentSecret |
+            Feature::BasicMpp |
+            Feature::RouteBlinding => &[Init, NodeAnnouncement, Invoice],
+            Feature::PaymentMetadata => &[Invoice],
+            _ => &[Init, NodeAnnouncement],
+        }
+    }
+
+    /// Features which must also be set (as either required or optional) when this one is.
+    pub fn dependencies(&self) -> &'static [Feature] {
+        match self {
+            Feature::GossipQueriesEx => &[Feature::GossipQueries],
+            Feature::PaymentSecret => &[Feature::VarOnionOptin],
+            Feature::BasicMpp => &[Feature::PaymentSecret],
+            Feature::AnchorOutputs => &[Feature::StaticRemoteKey],
+            Feature::AnchorsZeroFeeHtlcTx => &[Feature::StaticRemoteKey],
+            Feature::ZeroConf => &[Feature::ScidAlias],
+            _ => &[],
+        }
+    }
+
+    fn is_known(bit: usize, context: FeatureContext) -> bool {
+        Feature::from_bit(bit).is_some_and(|f| f.contexts().contains(&context))
+    }
+
+    /// Looks up the known feature a bit belongs to, if any.
+    pub fn from_bit(bit: usize) -> Option<Feature> {
+        Feature::ALL.iter().copied().find(|f| f.bit() == bit & !1)
+    }
+}
+
+/// A feature bit vector. On the wire it is a big-endian byte array, with bit 0 being the least
+/// significant bit of the last byte.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Features {
+    /// Little-endian, i.e. bits 0-7 are in the first byte
+    flags: Vec<u8>,
+}
+
+/// Generates a `supports_*` and `requires_*` accessor for a known feature.
+macro_rules! feature_accessors {
+    ($($supports: ident, $requires: ident, $feature: expr);* $(;)?) => {
+        impl Features {
+            $(
+                pub fn $supports(&self) -> bool {
+                    self.supports($feature)
+                }
+
+                pub fn $requires(&self) -> bool {
+                    self.requires($feature)
+                }
+            )*
+        }
+    }
+}
+
+feature_accessors!(
+    supports_data_loss_protect, requires_data_loss_protect, Feature::DataLossProtect;
+    supports_upfront_shutdown_script, requires_upfront_shutdown_script, Feature::UpfrontShutdownScript;
+    supports_gossip_queries, requires_gossip_queries, Feature::GossipQueries;
+    supports_var_onion_optin, requires_var_onion_optin, Feature::VarOnionOptin;
+    supports_static_remote_key, requires_static_remote_key, Feature::StaticRemoteKey;
+    supports_payment_secret, requires_payment_secret, Feature::PaymentSecret;
+    supports_basic_mpp, requires_basic_mpp, Feature::BasicMpp;
+    supports_anchors_zero_fee_htlc_tx, requires_anchors_zero_fee_htlc_tx, Feature::AnchorsZeroFeeHtlcTx;
+    supports_route_blinding, requires_route_blinding, Feature::RouteBlinding;
+    supports_payment_metadata, requires_payment_metadata, Feature::PaymentMetadata;
+);
+
+impl Features {
+    pub fn empty() -> Self {
+        Features { flags: Vec::new() }
+    }
+
+    /// Builds a feature vector from its big-endian wire encoding.
+    pub fn from_be_bytes(mut bytes: Vec<u8>) -> Self {
+        bytes.reverse();
+        let mut features = Features { flags: bytes };
+        features.trim();
+        features
+    }
+
+    /// The big-endian wire encoding, without leading zero bytes.
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        self.flags.iter().rev().copied().collect()
+    }
+
+    fn trim(&mut self) {
+        while self.flags.last() == Some(&0) {
+            self.flags.pop();
+        }
+    }
+
+    pub fn is_set(&self, bit: usize) -> bool {
+        self.flags.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn set_bit(&mut self, bit: usize) {
+        if self.flags.len() <= bit / 8 {
+            self.flags.resize(bit / 8 + 1, 0);
+        }
+        self.flags[bit / 8] |= 1 << (bit % 8);
+    }
+
+    pub fn clear_bit(&mut self, bit: usize) {
+        if let Some(byte) = self.flags.get_mut(bit / 8) {
+            *byte &= !(1 << (bit % 8));
+        }
+        self.trim();
+    }
+
+    /// Iterates over the bits which are set, in increasing order.
+    pub fn set_bits(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.flags.len() * 8).filter(move |bit| self.is_set(*bit))
+    }
+
+    /// Whether the feature is set, either as required or optional.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.is_set(feature.bit()) || self.is_set(feature.bit() + 1)
+    }
+
+    pub fn requires(&self, feature: Feature) -> bool {
+        self.is_set(feature.bit())
+    }
+
+    pub fn set_required(&mut self, feature: Feature) {
+        self.clear_bit(feature.bit() + 1);
+        self.set_bit(feature.bit());
+    }
+
+    pub fn set_optional(&mut self, feature: Feature) {
+        self.clear_bit(feature.bit());
+        self.set_bit(feature.bit() + 1);
+    }
+
+    /// Combines two feature vectors by setting every bit set in either.
+    pub fn or(mut self, other: &Features) -> Self {
+        if self.flags.len() < other.flags.len() {
+            self.flags.resize(other.flags.len(), 0);
+        }
+        for (byte, other) in self.flags.iter_mut().zip(&other.flags) {
+            *byte |= other;
+        }
+        self
+    }
+
+    /// Keeps only the features defined for `context`, plus unknown odd bits which are harmless.
+    pub fn masked(&self, context: FeatureContext) -> Self {
+        let mut features = self.clone();
+        for bit in self.set_bits() {
+            if bit % 2 == 0 && !Feature::is_known(bit, context) {
+                features.clear_bit(bit);
+            }
+        }
+        features
+    }
+
+    /// Checks a received feature vector. Even bits which are not known in `context` are
+    /// required features we don't understand, and every feature set must have all of its
+    /// dependencies set too.
+    pub fn validate(&self, context: FeatureContext) -> Result<(), DecodeError> {
+        for bit in self.set_bits() {
+            if bit % 2 == 0 && !Feature::is_known(bit, context) {
+                return Err(DecodeError::UnknownRequiredFeature)
+            }
+        }
+
+        for feature in Feature::ALL {
+            if !self.supports(feature) {
+                continue
+            }
+            if feature.dependencies().iter().any(|dep| !self.supports(*dep)) {
+                return Err(DecodeError::InvalidData)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The features within bits 0 to 13, which is all that `globalfeatures` may carry.
+    pub fn up_to_bit_13(&self) -> Self {
+        let mut features = Features { flags: self.flags.iter().take(2).copied().collect() };
+        if let Some(byte) = features.flags.get_mut(1) {
+            *byte &= 0x3f;
+        }
+        features.trim();
+        features
+    }
+}
+
+/// Features are written as a u16 length followed by the big-endian bit vector.
+impl Writeable for Features {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let bytes = self.to_be_bytes();
+        let len = (bytes.len() as u16).write(writer)?;
+        writer.write_all(&bytes)?;
+        Ok(len + bytes.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        let bytes = self.to_be_bytes();
+        write!(writer, "{:04x}", bytes.len())?;
+        for byte in bytes {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Readable for Features {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let len: u16 = Readable::read(reader)?;
+        let bytes: Vec<u8> = FixedLengthReadable::read(reader, len as usize)?;
+        Ok(Features::from_be_bytes(bytes))
+    }
+}
+
+/// The hex of the big-endian encoding, e.g. `0a8a`, or with `{:#}` the features by name.
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.describe(f)
+        }
+        self.to_be_bytes().describe(f)
+    }
+}
+
+/// Lists the set bits by feature name, or by number for unknown features, e.g.
+/// `[option_data_loss_protect (required), bit 101 (optional)]`.
+impl Describe for Features {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, bit) in self.set_bits().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match Feature::from_bit(bit) {
+                Some(feature) => f.write_str(feature.name())?,
+                None => write!(f, "bit {}", bit)?,
+            }
+            f.write_str(if bit % 2 == 0 { " (required)" } else { " (optional)" })?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Within a TLV record, such as `channel_type`, the bit vector spans the whole record and has no
+/// length prefix.
+impl TLVValue for Features {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Features::from_be_bytes(bytes.to_vec()))
+    }
+
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let bytes = self.to_be_bytes();
+        writer.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+}
+
+/// Features are serialized as the hex of their big-endian encoding, e.g. `"0a8a"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Features {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::serde_hex::serialize(&self.to_be_bytes(), serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Features {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Features::from_be_bytes(crate::serde_hex::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::tlv::impl_serde_value!(Features);
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::ser::{Readable, Writeable, DecodeError};
+    use super::{Feature, FeatureContext, Features};
+
+    fn features(bits: &[usize]) -> Features {
+        let mut features = Features::empty();
+        for bit in bits {
+            features.set_bit(*bit);
+        }
+        features
+    }
+
+    #[test]
+    fn big_endian_encoding() {
+        let features = features(&[0, 9, 15]);
+        assert_eq!(features.to_be_bytes(), vec![0x82, 0x01]);
+        assert_eq!(hex::encode(features.encode().unwrap()), "00028201");
+
+        let input = hex::decode("0003000201").unwrap();
+        let mut buff = input.as_slice();
+        let features: Features = Readable::read(&mut buff).unwrap();
+        assert_eq!(features.set_bits().collect::<Vec<_>>(), vec![0, 9]);
+        // Leading zero bytes are dropped when re-encoding
+        assert_eq!(hex::encode(features.encode().unwrap()), "00020201");
+    }
+
+    #[test]
+    fn named_accessors() {
+        let mut features = Features::empty();
+        features.set_required(Feature::DataLossProtect);
+        features.set_optional(Feature::VarOnionOptin);
+        features.set_optional(Feature::PaymentSecret);
+        features.set_required(Feature::BasicMpp);
+
+        assert!(features.supports_data_loss_protect() && features.requires_data_loss_protect());
+        assert!(features.supports_var_onion_optin() && !features.requires_var_onion_optin());
+        assert!(features.supports_payment_secret() && !features.requires_payment_secret());
+        assert!(features.supports_basic_mpp() && features.requires_basic_mpp());
+        assert!(!features.supports_anchors_zero_fee_htlc_tx());
+
+        features.set_optional(Feature::BasicMpp);
+        assert!(features.supports_basic_mpp() && !features.requires_basic_mpp());
+        assert_eq!(Feature::from_bit(23), Some(Feature::AnchorsZeroFeeHtlcTx));
+        assert_eq!(Feature::from_bit(2), None);
+    }
+
+    #[test]
+    fn unknown_even_bits_are_rejected() {
+        assert_eq!(features(&[2]).validate(FeatureContext::Init), Err(DecodeError::UnknownRequiredFeature));
+        assert_eq!(features(&[3]).validate(FeatureContext::Init), Ok(()));
+        assert_eq!(features(&[100]).validate(FeatureContext::Init), Err(DecodeError::UnknownRequiredFeature));
+        assert_eq!(features(&[101]).validate(FeatureContext::Init), Ok(()));
+    }
+
+    #[test]
+    fn context_masks() {
+        // option_payment_metadata is only defined for invoices
+        assert_eq!(features(&[48]).validate(FeatureContext::Invoice), Ok(()));
+        assert_eq!(features(&[48]).validate(FeatureContext::Init), Err(DecodeError::UnknownRequiredFeature));
+        assert_eq!(features(&[49]).validate(FeatureContext::Init), Ok(()));
+        // option_data_loss_protect is not an invoice feature
+        assert_eq!(features(&[0]).validate(FeatureContext::Invoice), Err(DecodeError::UnknownRequiredFeature));
+        assert_eq!(features(&[0, 48, 49]).masked(FeatureContext::Init), features(&[0, 49]));
+        assert_eq!(features(&[0, 9, 48]).masked(FeatureContext::Invoice), features(&[9, 48]));
+    }
+
+    #[test]
+    fn dependencies() {
+        // basic_mpp needs payment_secret which needs var_onion_optin
+        assert_eq!(features(&[17]).validate(FeatureContext::Init), Err(DecodeError::InvalidData));
+        assert_eq!(features(&[15, 17]).validate(FeatureContext::Init), Err(DecodeError::InvalidData));
+        assert_eq!(features(&[9, 15, 17]).validate(FeatureContext::Init), Ok(()));
+        assert_eq!(features(&[8, 14, 17]).validate(FeatureContext::Invoice), Ok(()));
+        // option_anchors_zero_fee_htlc_tx needs option_static_remotekey
+        assert_eq!(features(&[23]).validate(FeatureContext::Init), Err(DecodeError::InvalidData));
+        assert_eq!(features(&[13, 23]).validate(FeatureContext::Init), Ok(()));
+    }
+
+    #[test]
+    fn or_and_global_bits() {
+        let merged = features(&[0, 9]).or(&features(&[13, 23]));
+        assert_eq!(merged, features(&[0, 9, 13, 23]));
+        assert_eq!(merged.up_to_bit_13(), features(&[0, 9, 13]));
+        assert_eq!(features(&[14, 15]).up_to_bit_13(), Features::empty());
+    }
+}
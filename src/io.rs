@@ -0,0 +1,220 @@
+//! The subset of `std::io` used by the codec.
+//!
+//! With the `std` feature these are the `std::io` items themselves, so any reader or writer can be
+//! used. Without it, small equivalents are provided for byte slices and vectors.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Read, Take, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::*;
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        UnexpectedEof,
+        WriteZero,
+        Interrupted,
+        Other,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+        error: &'static str,
+    }
+
+    impl Error {
+        pub fn new(kind: ErrorKind, error: &'static str) -> Self {
+            Error { kind, error }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error { kind, error: "" }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.error {
+                "" => write!(f, "{:?}", self.kind),
+                error => f.write_str(error),
+            }
+        }
+    }
+
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+            let mut chunk = [0u8; 64];
+            let start = buf.len();
+            loop {
+                match self.read(&mut chunk) {
+                    Ok(0) => return Ok(buf.len() - start),
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        fn take(self, limit: u64) -> Take<Self> where Self: Sized {
+            Take { inner: self, limit }
+        }
+    }
+
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error>;
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Reads at most `limit` bytes from the inner reader, as returned by `Read::take`.
+    #[derive(Debug)]
+    pub struct Take<R> {
+        inner: R,
+        limit: u64,
+    }
+
+    impl<R> Take<R> {
+        pub fn limit(&self) -> u64 {
+            self.limit
+        }
+
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> Read for Take<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            if self.limit == 0 {
+                return Ok(0)
+            }
+            let max = (buf.len() as u64).min(self.limit) as usize;
+            let n = self.inner.read(&mut buf[..max])?;
+            self.limit -= n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let n = buf.len().min(self.len());
+            let (bytes, rest) = self.split_at(n);
+            buf[..n].copy_from_slice(bytes);
+            *self = rest;
+            Ok(n)
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            (**self).flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{ErrorKind, Read, Write};
+
+    #[test]
+    fn read_from_slices() {
+        let mut reader = &[1u8, 2, 3, 4, 5][..];
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(reader.read(&mut [0; 8]).unwrap(), 3);
+        assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+
+        let mut reader = &[1u8, 2][..];
+        assert_eq!(reader.read_exact(&mut [0; 3]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn take_and_read_to_end() {
+        let bytes: Vec<u8> = (0..200).collect();
+        let mut reader = &bytes[..];
+        let mut take = (&mut reader).take(150);
+        let mut read = Vec::new();
+        assert_eq!(take.read_to_end(&mut read).unwrap(), 150);
+        assert_eq!(read, bytes[..150]);
+        assert_eq!(take.limit(), 0);
+        assert_eq!(reader, &bytes[150..]);
+
+        assert_eq!(reader.read_to_end(&mut read).unwrap(), 50);
+        assert_eq!(read, bytes);
+    }
+
+    #[test]
+    fn write_to_vecs() {
+        let mut writer = Vec::new();
+        writer.write_all(&[1, 2]).unwrap();
+        fn write_three<W: Write>(mut writer: W) {
+            writer.write_all(&[3]).unwrap();
+        }
+        write_three(&mut writer);
+        assert_eq!(writer.write(&[4, 5]).unwrap(), 2);
+        writer.flush().unwrap();
+        assert_eq!(writer, [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,35 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate bitcoin_hashes;
+
+pub mod io;
+pub mod bigsize;
+pub mod tlv;
+pub mod ser;
+pub mod msgs;
+pub mod wire;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "tokio")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod onion;
+#[cfg(feature = "std")]
+pub mod failure;
+#[cfg(feature = "std")]
+pub mod blinding;
+pub mod features;
+pub mod types;
+pub mod ping;
+pub mod borrowed;
+pub mod pretty;
+#[cfg(feature = "serde")]
+mod serde_hex;
+
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub use serde;
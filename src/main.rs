@@ -1,8 +1,191 @@
-mod bigsize;
-mod tlv;
-mod ser;
-mod msgs;
+//! Command line tool to inspect BOLT #1 encodings, e.g. when debugging interoperability with a
+//! peer.
 
-fn main() {
-    println!("Hello, world!");
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use lightning_bolt1::bigsize::BigSize;
+use lightning_bolt1::features::Features;
+use lightning_bolt1::blinding::EncryptedDataTlvs;
+use lightning_bolt1::msgs::{AcceptChannelTlvs, ClosingSignedTlvs, InitTlvs, OpenChannelTlvs, UpdateAddHTLCTlvs};
+use lightning_bolt1::onion::HopPayloadTlvs;
+use lightning_bolt1::ser::{Readable, Writeable};
+use lightning_bolt1::tlv::{TLVNamespace, TLVStream, N1, N2};
+use lightning_bolt1::wire;
+
+const USAGE: &str = "\
+Usage:
+    lightning-bolt1 decode [HEX]
+    lightning-bolt1 decode-tlv --namespace NAMESPACE [HEX]
+    lightning-bolt1 bigsize encode NUMBER
+    lightning-bolt1 bigsize decode HEX
+    lightning-bolt1 features [HEX]
+
+Hex arguments which are left out are read from stdin.
+
+Commands:
+    decode        Decodes a message, starting with its 2-byte type
+    decode-tlv    Decodes a TLV stream, one record per line
+    bigsize       Encodes or decodes a BigSize integer
+    features      Lists the features set in a big-endian feature vector
+
+Namespaces:
+    init_tlvs, open_channel_tlvs, accept_channel_tlvs, closing_signed_tlvs, update_add_htlc_tlvs,
+    payload, encrypted_data_tlvs, n1, n2";
+
+#[derive(Debug, PartialEq)]
+enum Error {
+    /// The arguments don't match any command
+    Usage(String),
+    /// The input could not be decoded
+    Invalid(String),
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args, io::stdin()) {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(Error::Usage(msg)) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            ExitCode::from(2)
+        }
+        Err(Error::Invalid(msg)) => {
+            eprintln!("error: {}", msg);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the command given by `args` and returns what it prints.
+fn run<R: Read>(args: &[String], stdin: R) -> Result<String, Error> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["help" | "--help" | "-h"] => Ok(USAGE.to_string()),
+        ["decode", rest @ ..] => decode(&input(rest, stdin)?),
+        ["decode-tlv", "--namespace", namespace, rest @ ..] => decode_tlv(namespace, &input(rest, stdin)?),
+        ["bigsize", "encode", number] => {
+            let n = number.parse().map_err(|_| Error::Usage(format!("invalid number '{}'", number)))?;
+            Ok(hex::encode(BigSize(n).encode().expect("writing to a vec doesn't fail")))
+        }
+        ["bigsize", "decode", hex] => {
+            let bytes = parse_hex(hex)?;
+            let mut reader = &bytes[..];
+            let n: BigSize = Readable::read(&mut reader).map_err(|e| Error::Invalid(e.to_string()))?;
+            trailing_bytes(reader)?;
+            Ok(n.0.to_string())
+        }
+        ["features", rest @ ..] => Ok(format!("{:#}", Features::from_be_bytes(input(rest, stdin)?))),
+        [] => Err(Error::Usage("missing command".to_string())),
+        _ => Err(Error::Usage(format!("invalid arguments '{}'", args.join(" ")))),
+    }
+}
+
+/// The hex given as the only argument, or read from stdin without arguments.
+fn input<R: Read>(args: &[&str], mut stdin: R) -> Result<Vec<u8>, Error> {
+    match args {
+        [hex] => parse_hex(hex),
+        [] => {
+            let mut hex = String::new();
+            stdin.read_to_string(&mut hex).map_err(|e| Error::Invalid(format!("reading stdin: {}", e)))?;
+            parse_hex(&hex)
+        }
+        _ => Err(Error::Usage(format!("unexpected arguments '{}'", args[1..].join(" ")))),
+    }
+}
+
+/// Accepts hex with surrounding whitespace and an optional `0x` prefix, as copied from logs.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let hex = hex.trim();
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    hex::decode(hex).map_err(|e| Error::Invalid(format!("invalid hex: {}", e)))
+}
+
+fn trailing_bytes(rest: &[u8]) -> Result<(), Error> {
+    match rest.len() {
+        0 => Ok(()),
+        n => Err(Error::Invalid(format!("{} trailing bytes", n))),
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<String, Error> {
+    let msg = wire::read_message(&mut &bytes[..]).map_err(|e| Error::Invalid(e.to_string()))?;
+    Ok(format!("{:#}", msg))
+}
+
+fn decode_tlv(namespace: &str, bytes: &[u8]) -> Result<String, Error> {
+    match namespace {
+        "init_tlvs" => decode_stream::<InitTlvs>(bytes),
+        "open_channel_tlvs" => decode_stream::<OpenChannelTlvs>(bytes),
+        "accept_channel_tlvs" => decode_stream::<AcceptChannelTlvs>(bytes),
+        "closing_signed_tlvs" => decode_stream::<ClosingSignedTlvs>(bytes),
+        "update_add_htlc_tlvs" => decode_stream::<UpdateAddHTLCTlvs>(bytes),
+        "payload" => decode_stream::<HopPayloadTlvs>(bytes),
+        "encrypted_data_tlvs" => decode_stream::<EncryptedDataTlvs>(bytes),
+        "n1" => decode_stream::<N1>(bytes),
+        "n2" => decode_stream::<N2>(bytes),
+        _ => Err(Error::Usage(format!("unknown namespace '{}'", namespace))),
+    }
+}
+
+fn decode_stream<N: TLVNamespace>(bytes: &[u8]) -> Result<String, Error> {
+    let stream: TLVStream<N> = Readable::read(&mut &bytes[..])
+        .map_err(|e| Error::Invalid(format!("{}: {}", N::NAME, e)))?;
+    let records: Vec<String> = stream.records().map(|r| format!("{:#}", r)).collect();
+    Ok(records.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Error};
+
+    fn run_args(args: &[&str], stdin: &str) -> Result<String, Error> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        run(&args, stdin.as_bytes())
+    }
+
+    #[test]
+    fn decode_messages() {
+        assert_eq!(run_args(&["decode", "0012000400020000"], ""), Ok("ping num_pong_bytes=4 ignored=0000".to_string()));
+        assert_eq!(run_args(&["decode"], "0x00100002020100020201\n"), Ok(
+            "init features=[option_data_loss_protect (required), var_onion_optin (optional)] init_tlvs=[]".to_string()
+        ));
+        assert_eq!(run_args(&["decode", "8000"], ""), Err(Error::Invalid("unknown even type 32768".to_string())));
+        assert!(matches!(run_args(&["decode", "zz"], ""), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn decode_tlv_streams() {
+        assert_eq!(
+            run_args(&["decode-tlv", "--namespace", "n1", "0101010208000000000000022621012a"], ""),
+            Ok("tlv1 amount_msat=1\ntlv2 scid=0x0x550\nunknown type=33 value=2a".to_string())
+        );
+        assert_eq!(
+            run_args(&["decode-tlv", "--namespace", "payload", "020203e8040190"], ""),
+            Ok("amt_to_forward 1000\noutgoing_cltv_value 144".to_string())
+        );
+        assert_eq!(run_args(&["decode-tlv", "--namespace", "n2"], ""), Ok(String::new()));
+        assert!(matches!(run_args(&["decode-tlv", "--namespace", "n3", "00"], ""), Err(Error::Usage(_))));
+        assert!(matches!(run_args(&["decode-tlv", "--namespace", "n1", "1200"], ""), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn bigsize() {
+        assert_eq!(run_args(&["bigsize", "encode", "65536"], ""), Ok("fe00010000".to_string()));
+        assert_eq!(run_args(&["bigsize", "decode", "fe00010000"], ""), Ok("65536".to_string()));
+        assert!(matches!(run_args(&["bigsize", "decode", "fd00fc"], ""), Err(Error::Invalid(_))));
+        assert!(matches!(run_args(&["bigsize", "decode", "fc00"], ""), Err(Error::Invalid(_))));
+        assert!(matches!(run_args(&["bigsize", "encode", "-1"], ""), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn features() {
+        assert_eq!(run_args(&["features", "20000000000000000000001082"], ""), Ok(
+            "[option_data_loss_protect (optional), gossip_queries (optional), option_static_remotekey (required), bit 101 (optional)]".to_string()
+        ));
+        assert!(matches!(run_args(&[], ""), Err(Error::Usage(_))));
+        assert!(matches!(run_args(&["features", "00", "00"], ""), Err(Error::Usage(_))));
+    }
 }
@@ -1,167 +1,194 @@
-use std::{io::Read, fmt};
+use core::fmt;
+use alloc::{string::String, vec::Vec};
 
-use bitcoin::{Txid, Script};
+#[cfg(feature = "std")]
+use bitcoin::Network;
 use secp256k1::{PublicKey, ecdsa::Signature};
 
-use crate::{tlv::TLVStream, ser::{Readable, DecodeError, FixedLengthReadable}};
-
+use crate::io::{self, Read, Write};
+use crate::features::{Features, FeatureContext};
+use crate::types::{ChannelId, PaymentHash, PaymentPreimage, Script, Txid};
+use crate::wire::MAX_MESSAGE_LEN;
+use crate::pretty::{Describe, describe_list, impl_describe_fields};
+use crate::tlv_namespace;
+use crate::{tlv::{TLVStream, TLVValue, impl_tlv_value_fixed}, ser::{Readable, ReadableArgs, Writeable, DecodeError, FixedLengthReadable}};
 
 /// Once authentication is complete, the first message reveals the features supported or required
 /// by this node, even if this is a reconnection.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Init {
-    typ: u16,
-    /// Global features length
-    gflen: u16,
-    global_features: Vec<u8>,
-    /// Features length
-    flen: u16,
-    features: Vec<u8>,
-    init_tlvs: TLVStream,
+    /// The features of the node. `globalfeatures` is only kept for backwards compatibility, so it
+    /// is merged in here when reading and mirrors bits 0 to 13 when writing.
+    pub features: Features,
+    pub init_tlvs: TLVStream<InitTlvs>,
 }
 
 /// For simplicity of diagnosis, it's often useful to tell a peer that something is incorrect.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ErrorMessage {
-    typ: u16,
     /// The channel is referred to by channel_id, unless channel_id is 0 (i.e. all bytes are 0),
     /// in which case it refers to all channels.
-    channel_id: [u8; 32],
-    len: u16,
-    data: Vec<u8>,
+    pub channel_id: ChannelId,
+    /// The length of `data` as read, recomputed when writing
+    pub len: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub data: Vec<u8>,
 }
 
 /// For simplicity of diagnosis, it's often useful to tell a peer that something is incorrect.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WarningMessage {
-    typ: u16,
     /// The channel is referred to by channel_id, unless channel_id is 0 (i.e. all bytes are 0),
     /// in which case it refers to all channels.
-    channel_id: [u8; 8],
-    len: u16,
-    data: Vec<u8>,
+    pub channel_id: ChannelId,
+    /// The length of `data` as read, recomputed when writing
+    pub len: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub data: Vec<u8>,
 }
 
 /// In order to allow for the existence of long-lived TCP connections, at times it may be required
 /// that both ends keep alive the TCP connection at the application level. Such messages also allow
 /// obfuscation of traffic patterns.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ping {
-    typ: u16,
-    num_pong_bytes: u16,
-    bytes_len: u16,
-    ignored: Vec<u8>,
+    pub num_pong_bytes: u16,
+    /// The length of `ignored` as read, recomputed when writing
+    pub bytes_len: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub ignored: Vec<u8>,
 }
 
 /// The pong message is to be sent whenever a ping message is received. It serves as a reply and
 /// also serves to keep the connection alive, while explicitly notifying the other end that the
 /// receiver is still active. Within the received ping message, the sender will specify the number
 /// of bytes to be included within the data payload of the pong message.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pong {
-    typ: u16,
-    num_pong_bytes: u16,
-    bytes_len: u16,
-    ignored: Vec<u8>,
+    /// The length of `ignored` as read, recomputed when writing
+    pub bytes_len: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub ignored: Vec<u8>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OpenChannel {
     /// The chain_hash value denotes the exact blockchain that the opened channel will reside within.
     /// This is usually the genesis hash of the respective blockchain. The existence of the
     /// chain_hash allows nodes to open channels across many distinct blockchains as well as have
     /// channels within multiple blockchains opened to the same peer (if it supports the target chains).
-    chain_hash: ChainHash,
+    pub chain_hash: ChainHash,
     /// The temporary_channel_id is used to identify this channel on a per-peer basis until the
     /// funding transaction is established, at which point it is replaced by the channel_id, which
     /// is derived from the funding transaction.
-    temp_channel_id: [u8; 32],
+    pub temp_channel_id: ChannelId,
     /// The amount the sender is putting into the channel.
-    funding_sats: u64,
+    pub funding_sats: u64,
     /// An amount of initial funds that the sender is unconditionally giving to the receiver.
-    push_msat: u64,
+    pub push_msat: u64,
     /// Threshold below which outputs should not be generated for this node's commitment or HTLC
     /// transactions (i.e. HTLCs below this amount plus HTLC transaction fees are not enforceable on-chain).
-    dust_limit_sats: u64,
+    pub dust_limit_sats: u64,
     /// Is a cap on total value of outstanding HTLCs, which allows a node to limit its exposure to HTLCs
-    max_htlc_value_in_flight_msat: u64,
+    pub max_htlc_value_in_flight_msat: u64,
     /// The minimum amount that the other node is to keep as a direct payment.
-    channel_reserve_sats: u64,
+    pub channel_reserve_sats: u64,
     /// Indicates the smallest value HTLC this node will accept.
-    htlc_min_msat: u64,
+    pub htlc_min_msat: u64,
     /// Indicates the initial fee rate in satoshi per 1000-weight (i.e. 1/4 the more normally-used
     /// 'satoshi per 1000 vbytes') that this side will pay for commitment and HTLC transactions
-    feerate_per_kw: u32,
+    pub feerate_per_kw: u32,
     /// Is the number of blocks that the other node's to-self outputs must be delayed, using
     /// OP_CHECKSEQUENCEVERIFY delays; this is how long it will have to wait in case of breakdown
     /// before redeeming its own funds.
-    to_self_delay: u16,
+    pub to_self_delay: u16,
     /// Limits the number of outstanding HTLCs the other node can offer.
-    max_accepted_htlcs: u16,
+    pub max_accepted_htlcs: u16,
     /// The public key in the 2-of-2 multisig script of the funding transaction output.
-    funding_pubkey: PublicKey,
-    revocation_basepoint: PublicKey,
-    payment_basepoint: PublicKey,
-    delayed_payment_basepoint: PublicKey,
-    htlc_basepoint: PublicKey,
+    pub funding_pubkey: PublicKey,
+    pub revocation_basepoint: PublicKey,
+    pub payment_basepoint: PublicKey,
+    pub delayed_payment_basepoint: PublicKey,
+    pub htlc_basepoint: PublicKey,
     /// The per-commitment point to be used for the first commitment transaction,
-    first_per_commitment_point: PublicKey,
+    pub first_per_commitment_point: PublicKey,
     /// Only the least-significant bit of channel_flags is currently defined: announce_channel.
     /// This indicates whether the initiator of the funding flow wishes to advertise this channel
     /// publicly to the network
-    channel_flags: u8,
-    tlv_stream: TLVStream,
-    /// Allows the sending node to commit to where funds will go on mutual close, which the remote
-    /// node should enforce even if a node is compromised later.
-    shutdown_scriptpubkey: PublicKey,
+    pub channel_flags: u8,
+    /// Carries the upfront_shutdown_script, which allows the sending node to commit to where
+    /// funds will go on mutual close, which the remote node should enforce even if a node is
+    /// compromised later.
+    pub tlv_stream: TLVStream<OpenChannelTlvs>,
 }
 
 /// This message contains information about a node and indicates its acceptance of the new channel.
 /// This is the second step toward creating the funding transaction and both versions of the commitment transaction.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AcceptChannel {
-    temp_channel_id: [u8; 32],
-    dust_limit_sats: u64,
-    max_htlc_value_in_flight_msat: u64,
-    channel_reserve_sats: u64,
-    htlc_min_msat: u64,
-    min_depth: u32,
-    to_self_delay: u16,
-    max_accepted_htlcs: u16,
-    funding_pubkey: PublicKey,
-    revocation_basepoint: PublicKey,
-    payment_basepoint: PublicKey,
-    delayed_payment_basepoint: PublicKey,
-    htlc_basepoint: PublicKey,
-    first_per_commitment_point: PublicKey,
-    accept_channel_tlvs: TLVStream,
-    shutdown_scriptpubkey: PublicKey,
+    pub temp_channel_id: ChannelId,
+    pub dust_limit_sats: u64,
+    pub max_htlc_value_in_flight_msat: u64,
+    pub channel_reserve_sats: u64,
+    pub htlc_min_msat: u64,
+    pub min_depth: u32,
+    pub to_self_delay: u16,
+    pub max_accepted_htlcs: u16,
+    pub funding_pubkey: PublicKey,
+    pub revocation_basepoint: PublicKey,
+    pub payment_basepoint: PublicKey,
+    pub delayed_payment_basepoint: PublicKey,
+    pub htlc_basepoint: PublicKey,
+    pub first_per_commitment_point: PublicKey,
+    pub accept_channel_tlvs: TLVStream<AcceptChannelTlvs>,
 }
 
 /// This message describes the outpoint which the funder has created for the initial commitment
 /// transactions. After receiving the peer's signature, via funding_signed, it will broadcast the
 /// funding transaction.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FundingCreated {
-    temp_channel_id: [u8; 32],
-    funding_txid: Txid,
-    funding_output_index: u16,
-    signature: Signature,
+    pub temp_channel_id: ChannelId,
+    pub funding_txid: Txid,
+    pub funding_output_index: u16,
+    pub signature: Signature,
 }
 
 /// This message gives the funder the signature it needs for the first commitment transaction, so
 /// it can broadcast the transaction knowing that funds can be redeemed, if need be.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FundingSigned {
-    channel_id: [u8; 32],
-    signature: Signature,
+    pub channel_id: ChannelId,
+    pub signature: Signature,
 }
 
 /// This message indicates that the funding transaction has reached the minimum_depth asked for in
 /// accept_channel. Once both nodes have sent this, the channel enters normal operating mode.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FundingLocked {
-    channel_id: [u8; 32],
-    next_per_commitment_point: PublicKey,
+    pub channel_id: ChannelId,
+    pub next_per_commitment_point: PublicKey,
 }
 
 /// Either node (or both) can send a shutdown message to initiate closing, along with the
 /// scriptpubkey it wants to be paid to.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Shutdown {
-    channel_id: [u8; 32],
-    len: u16,
-    scriptpubkey: Script,
+    pub channel_id: ChannelId,
+    /// The length of `scriptpubkey` as read, recomputed when writing
+    pub len: u16,
+    pub scriptpubkey: Script,
 }
 
 /// Once shutdown is complete and the channel is empty of HTLCs, the final current commitment
@@ -174,168 +201,1335 @@ pub struct Shutdown {
 /// In the modern method, the funder sends its permissible fee range, and the non-funder has to
 /// pick a fee in this range. If the non-funder chooses the same value, negotiation is complete
 /// after two messages, otherwise the funder will reply with the same value (completing after three messages).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClosingSigned {
-    channel_id: [u8; 32],
-    fee_sats: u64,
-    signature: Signature,
-    tlv_stream: TLVStream,
+    pub channel_id: ChannelId,
+    pub fee_sats: u64,
+    pub signature: Signature,
+    pub tlv_stream: TLVStream<ClosingSignedTlvs>,
 }
 
 /// Either node can send update_add_htlc to offer an HTLC to the other, which is redeemable in
 /// return for a payment preimage.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateAddHTLC {
-    channel_id: [u8; 32],
-    id: u64,
-    amount_msat: u64,
-    payment_hash: [u8; 32], // TODO: Create PaymentHash type if needed
-    cltv_expiry: u32,
+    pub channel_id: ChannelId,
+    pub id: u64,
+    pub amount_msat: u64,
+    pub payment_hash: PaymentHash,
+    pub cltv_expiry: u32,
     /// Contains an obfuscated list of hops and instructions for each hop along the path. It
     /// commits to the HTLC by setting the payment_hash as associated data, i.e. includes the
     /// payment_hash in the computation of HMACs. This prevents replay attacks that would reuse a
     /// previous onion_routing_packet with a different payment_hash.
-    onion_routing_packet: [u8; 1366],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub onion_routing_packet: [u8; 1366],
+    pub tlv_stream: TLVStream<UpdateAddHTLCTlvs>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateFulfillHTLC {
-    channel_id: [u8; 32],
-    id: u64,
-    payment_preimage: [u8; 32],
+    pub channel_id: ChannelId,
+    pub id: u64,
+    pub payment_preimage: PaymentPreimage,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateFailHTLC {
-    channel_id: [u8; 32],
-    id: u64,
-    len: u16,
-    reason: Vec<u8> // TODO: Error type
+    pub channel_id: ChannelId,
+    pub id: u64,
+    /// The length of `reason` as read, recomputed when writing
+    pub len: u16,
+    /// The failure packet, obfuscated by every hop on the way back so that only the sender can
+    /// read it (see the `failure` module).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub reason: Vec<u8>,
 }
 
+/// Fails an HTLC whose onion couldn't be peeled, so that no failure packet could be built. The
+/// upstream node reports the failure_code, which has the `BADONION` bit, in its own packet.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateFailMalformedHTLC {
-    channel_id: [u8; 32],
-    id: u64,
-    sha256_of_onion: [u8; 32],
-    failure_code: u16,
+    pub channel_id: ChannelId,
+    pub id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub sha256_of_onion: [u8; 32],
+    pub failure_code: u16,
 }
 
 /// When a node has changes for the remote commitment, it can apply them, sign the resulting
 /// transaction (as defined in BOLT #3), and send a commitment_signed message.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CommitmentSigned {
-    channel_id: [u8; 32],
-    signature: Signature,
-    num_htlc: u16,
-    htlc_signature: Vec<Signature>,
+    pub channel_id: ChannelId,
+    pub signature: Signature,
+    /// The number of `htlc_signature` as read, recomputed when writing
+    pub num_htlc: u16,
+    pub htlc_signature: Vec<Signature>,
 }
 
 /// Once the recipient of commitment_signed checks the signature and knows it has a valid new
 /// commitment transaction, it replies with the commitment preimage for the previous commitment
 /// transaction in a revoke_and_ack message.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RevokeAndACK {
-    channel_id: [u8; 32],
-    per_commitment_secret: [u8; 32],
-    next_per_commitment_point: PublicKey,
+    pub channel_id: ChannelId,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub per_commitment_secret: [u8; 32],
+    pub next_per_commitment_point: PublicKey,
 }
 
 /// An update_fee message is sent by the node which is paying the Bitcoin fee. Like any update,
 /// it's first committed to the receiver's commitment transaction and then (once acknowledged)
 /// committed to the sender's. Unlike an HTLC, update_fee is never closed but simply replaced.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateFee {
-    channel_id: [u8; 32],
-    feerate_per_kw: u32,
+    pub channel_id: ChannelId,
+    pub feerate_per_kw: u32,
 }
 
 /// Because communication transports are unreliable, and may need to be re-established from time to
 /// time, the design of the transport has been explicitly separated from the protocol.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChannelReestablish {
-    channel_id: [u8; 32],
+    pub channel_id: ChannelId,
     /// A commitment number is a 48-bit incrementing counter for each commitment transaction;
     /// counters are independent for each peer in the channel and start at 0. They're only explicitly
     /// relayed to the other node in the case of re-establishment, otherwise they are implicit.
-    next_commitment_number: u64,
-    next_revocation_number: u64,
-    your_last_per_commitment_secret: [u8; 32],
-    my_current_per_commitment_point: PublicKey,
+    pub next_commitment_number: u64,
+    pub next_revocation_number: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+    pub your_last_per_commitment_secret: [u8; 32],
+    pub my_current_per_commitment_point: PublicKey,
 }
 
-
-
 /// The chain_hash value denotes the exact blockchain that the opened channel will reside within.
 /// This is usually the genesis hash of the respective blockchain. The existence of the
 /// chain_hash allows nodes to open channels across many distinct blockchains as well as have
 /// channels within multiple blockchains opened to the same peer (if it supports the target chains).
-struct ChainHash {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct ChainHash(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))] pub [u8; 32]);
+
+impl ChainHash {
+    /// The genesis block hash of mainnet, in internal byte order
+    pub const BITCOIN: ChainHash = ChainHash([
+        0x6f, 0xe2, 0x8c, 0x0a, 0xb6, 0xf1, 0xb3, 0x72, 0xc1, 0xa6, 0xa2, 0x46, 0xae, 0x63, 0xf7, 0x4f,
+        0x93, 0x1e, 0x83, 0x65, 0xe1, 0x5a, 0x08, 0x9c, 0x68, 0xd6, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]);
+    /// The genesis block hash of testnet3, in internal byte order
+    pub const TESTNET: ChainHash = ChainHash([
+        0x43, 0x49, 0x7f, 0xd7, 0xf8, 0x26, 0x95, 0x71, 0x08, 0xf4, 0xa3, 0x0f, 0xd9, 0xce, 0xc3, 0xae,
+        0xba, 0x79, 0x97, 0x20, 0x84, 0xe9, 0x0e, 0xad, 0x01, 0xea, 0x33, 0x09, 0x00, 0x00, 0x00, 0x00,
+    ]);
+    /// The genesis block hash of the default signet, in internal byte order
+    pub const SIGNET: ChainHash = ChainHash([
+        0xf6, 0x1e, 0xee, 0x3b, 0x63, 0xa3, 0x80, 0xa4, 0x77, 0xa0, 0x63, 0xaf, 0x32, 0xb2, 0xbb, 0xc9,
+        0x7c, 0x9f, 0xf9, 0xf0, 0x1f, 0x2c, 0x42, 0x25, 0xe9, 0x73, 0x98, 0x81, 0x08, 0x00, 0x00, 0x00,
+    ]);
+    /// The genesis block hash of regtest, in internal byte order
+    pub const REGTEST: ChainHash = ChainHash([
+        0x06, 0x22, 0x6e, 0x46, 0x11, 0x1a, 0x0b, 0x59, 0xca, 0xaf, 0x12, 0x60, 0x43, 0xeb, 0x5b, 0xbf,
+        0x28, 0xc3, 0x4f, 0x3a, 0x5e, 0x33, 0x2a, 0x1f, 0xc7, 0xb2, 0xb7, 0x3c, 0xf1, 0x88, 0x91, 0x0f,
+    ]);
+
+    /// The network this chain hash is the genesis block of, if it's a known one.
+    #[cfg(feature = "std")]
+    pub fn network(&self) -> Option<Network> {
+        [Network::Bitcoin, Network::Testnet, Network::Signet, Network::Regtest]
+            .into_iter()
+            .find(|n| ChainHash::from(*n) == *self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Network> for ChainHash {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Bitcoin => ChainHash::BITCOIN,
+            Network::Testnet => ChainHash::TESTNET,
+            Network::Signet => ChainHash::SIGNET,
+            Network::Regtest => ChainHash::REGTEST,
+        }
+    }
+}
+
+tlv_namespace! {
+    pub enum InitTlvs: "init_tlvs" {
+        /// The chains the node is interested in
+        1 => Networks(Vec<ChainHash>) as "networks" [chains], optional;
+        /// The IP address of the remote peer, as seen by the sender, encoded as in node_announcement
+        3 => RemoteAddr(Vec<u8>) as "remote_addr" [data], optional;
+    }
+}
+
+tlv_namespace! {
+    pub enum OpenChannelTlvs: "open_channel_tlvs" {
+        /// Where the sender wants its funds to go on mutual close, possibly empty
+        0 => UpfrontShutdownScript(Script) as "upfront_shutdown_script" [shutdown_scriptpubkey], optional;
+        1 => ChannelType(Features) as "channel_type" [type], optional;
+    }
+}
+
+tlv_namespace! {
+    pub enum AcceptChannelTlvs: "accept_channel_tlvs" {
+        /// Where the sender wants its funds to go on mutual close, possibly empty
+        0 => UpfrontShutdownScript(Script) as "upfront_shutdown_script" [shutdown_scriptpubkey], optional;
+        1 => ChannelType(Features) as "channel_type" [type], optional;
+    }
+}
+
+tlv_namespace! {
+    pub enum ClosingSignedTlvs: "closing_signed_tlvs" {
+        1 => FeeRange(FeeRange) as "fee_range", optional, check = FeeRange::check;
+    }
+}
+
+tlv_namespace! {
+    pub enum UpdateAddHTLCTlvs: "update_add_htlc_tlvs" {
+        /// The path key (formerly blinding_point) of the receiving node, when the HTLC is
+        /// forwarded within a blinded path
+        0 => BlindedPath(PublicKey) as "blinded_path" [path_key], optional;
+    }
+}
+
+/// The fee range, in satoshis, the sender of closing_signed is willing to accept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeRange {
+    pub min_fee_satoshis: u64,
+    pub max_fee_satoshis: u64,
+}
+
+impl FeeRange {
+    /// The sending node MUST set min_fee_satoshis lower than or equal to max_fee_satoshis.
+    fn check(&self) -> Result<(), DecodeError> {
+        if self.min_fee_satoshis > self.max_fee_satoshis {
+            return Err(DecodeError::InvalidData)
+        }
+        Ok(())
+    }
+}
+
+/// Implements `Readable` and `Writeable` for messages made up only of fixed-size fields and a
+/// trailing TLV stream, by reading and writing each field in the order given.
+macro_rules! impl_writeable_msg {
+    ($st: ident, { $($field: ident),* $(,)? }) => {
+        impl Writeable for $st {
+            fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                let mut len = 0;
+                $( len += self.$field.write(writer)?; )*
+                Ok(len)
+            }
+
+            fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+                for byte in self.encode().map_err(|_| fmt::Error)? {
+                    write!(writer, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl Readable for $st {
+            fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                Ok($st {
+                    $( $field: Readable::read(reader).map_err(|e: DecodeError| e.in_field(stringify!($field)))?, )*
+                })
+            }
+        }
+    }
+}
+
+/// Implements `Display` as the lowercase hex encoding of a message, or with `{:#}` as its
+/// description.
+macro_rules! impl_display_hex {
+    ($st: ident) => {
+        impl fmt::Display for $st {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if f.alternate() {
+                    return self.describe(f)
+                }
+                Writeable::write_fmt(self, f)
+            }
+        }
+    }
+}
+
+/// Describes a message as its name followed by the given fields, e.g. `ping num_pong_bytes=4
+/// ignored=0000`. Lengths which only delimit another field are left out.
+macro_rules! impl_describe_msg {
+    ($st: ident, $name: literal, { $($field: ident),* $(,)? }) => {
+        impl Describe for $st {
+            fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str($name)?;
+                $(
+                    f.write_str(concat!(" ", stringify!($field), "="))?;
+                    self.$field.describe(f)?;
+                )*
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `error` and `warning` show their data as text, with unprintable bytes escaped.
+macro_rules! impl_describe_channel_data_msg {
+    ($st: ident, $name: literal) => {
+        impl Describe for $st {
+            fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!($name, " channel_id={} data=\"{}\""), self.channel_id, self.sanitized_data())
+            }
+        }
+    }
+}
+
+/// Chain hashes are shown in internal byte order, as on the wire.
+impl Describe for ChainHash {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.describe(f)
+    }
+}
+
+impl Describe for Vec<ChainHash> {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        describe_list(self, f)
+    }
+}
+
+impl_describe_fields!(FeeRange, { min_fee_satoshis, max_fee_satoshis });
+
+impl Writeable for ChainHash {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.0.write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.0.write_fmt(writer)
+    }
+}
+
+impl Readable for ChainHash {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(ChainHash(Readable::read(reader)?))
+    }
+}
+
+/// A list of chain hashes, such as `networks` in init_tlvs, spans the whole record.
+impl TLVValue for Vec<ChainHash> {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if !bytes.len().is_multiple_of(32) {
+            return Err(DecodeError::InvalidData)
+        }
+        let mut chains = Vec::with_capacity(bytes.len() / 32);
+        let mut reader = bytes;
+        while !reader.is_empty() {
+            chains.push(Readable::read(&mut reader)?);
+        }
+        Ok(chains)
+    }
+
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        for chain in self {
+            len += chain.write(writer)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Writeable for FeeRange {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.min_fee_satoshis.write(writer)?;
+        len += self.max_fee_satoshis.write(writer)?;
+        Ok(len)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        write!(writer, "{:016x}{:016x}", self.min_fee_satoshis, self.max_fee_satoshis)
+    }
+}
+
+impl Readable for FeeRange {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(FeeRange {
+            min_fee_satoshis: Readable::read(reader)?,
+            max_fee_satoshis: Readable::read(reader)?,
+        })
+    }
+}
+
+impl_tlv_value_fixed!(FeeRange);
+
+#[cfg(feature = "serde")]
+crate::tlv::impl_serde_value!(FeeRange, Vec<ChainHash>);
 
 impl Readable for Init {
 	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
-        let typ: u16 = Readable::read(reader)?;
-        let gflen: u16 = Readable::read(reader)?;
-        let global_features: Vec<u8> = FixedLengthReadable::read(reader, gflen as usize)?;
-        let flen: u16 = Readable::read(reader)?;
-        let features: Vec<u8> = FixedLengthReadable::read(reader, flen as usize)?;
-        let init_tlvs: TLVStream = Readable::read(reader)?;
+        let global_features: Features = Readable::read(reader).map_err(|e: DecodeError| e.in_field("globalfeatures"))?;
+        let features: Features = Readable::read(reader).map_err(|e: DecodeError| e.in_field("features"))?;
+        // The receiving node MUST use the logical OR of the two feature maps
+        let features = features.or(&global_features);
+        features.validate(FeatureContext::Init).map_err(|e| e.in_field("features"))?;
+        let init_tlvs: TLVStream<InitTlvs> = Readable::read(reader).map_err(|e: DecodeError| e.in_field("init_tlvs"))?;
 
         Ok(Init {
-            typ,
-            gflen,
-            global_features,
-            flen,
             features,
             init_tlvs
         })
     }
 }
 
+impl Writeable for Init {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.features.up_to_bit_13().write(writer)?;
+        len += self.features.write(writer)?;
+        len += self.init_tlvs.write(writer)?;
+        Ok(len)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        write!(writer, "{:02x}", self)
+    }
+}
+
 impl fmt::Display for Init {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.describe(f)
+        }
         write!(f, "{:02x}", self)
     }
 }
 
+/// `globalfeatures` is left out since it only mirrors bits 0 to 13 of `features`.
+impl Describe for Init {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("init features=")?;
+        self.features.describe(f)?;
+        f.write_str(" init_tlvs=")?;
+        self.init_tlvs.describe(f)
+    }
+}
+
 impl fmt::LowerHex for Init {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:04x}", self.typ)?;
-        write!(f, "{:04x}", self.gflen)?;
-        for byte in &self.global_features {
-            write!(f, "{:02x}", byte)?;
+        self.features.up_to_bit_13().write_fmt(f)?;
+        self.features.write_fmt(f)?;
+        write!(f, "{}", self.init_tlvs)
+    }
+}
+
+/// Writes the u16 count which prefixes a variable-length field from the field itself, so that it
+/// can't disagree with what follows.
+fn write_len<W: Write>(len: usize, writer: &mut W) -> Result<usize, io::Error> {
+    u16::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field exceeds 65535 items"))?
+        .write(writer)
+}
+
+/// Messages made up of a channel_id followed by a length-prefixed byte array.
+macro_rules! impl_writeable_channel_data_msg {
+    ($st: ident) => {
+        impl Readable for $st {
+            fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                let channel_id: ChannelId = Readable::read(reader).map_err(|e: DecodeError| e.in_field("channel_id"))?;
+                let len: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("len"))?;
+                let data: Vec<u8> = FixedLengthReadable::read(reader, len as usize).map_err(|e: DecodeError| e.in_field("data"))?;
+
+                Ok($st { channel_id, len, data })
+            }
         }
-        write!(f, "{:04x}", self.flen)?;
-        for byte in &self.features {
-            write!(f, "{:02x}", byte)?;
+
+        impl Writeable for $st {
+            fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                let mut len = self.channel_id.write(writer)?;
+                len += write_len(self.data.len(), writer)?;
+                writer.write_all(&self.data)?;
+                Ok(len + self.data.len())
+            }
+
+            fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+                for byte in self.encode().map_err(|_| fmt::Error)? {
+                    write!(writer, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
         }
-        write!(f, "{}", self.init_tlvs)
     }
 }
 
+impl_writeable_channel_data_msg!(ErrorMessage);
+impl_writeable_channel_data_msg!(WarningMessage);
+
+/// Constructors and accessors shared by `error` and `warning`.
+macro_rules! impl_channel_data_msg {
+    ($st: ident) => {
+        impl $st {
+            /// Builds the message for `channel_id`, truncating `data` to the 65535 bytes which fit
+            /// in the length field.
+            pub fn new(channel_id: ChannelId, data: impl Into<Vec<u8>>) -> Self {
+                let mut data = data.into();
+                data.truncate(u16::MAX as usize);
+                $st { channel_id, len: data.len() as u16, data }
+            }
+
+            /// Builds the message with a zero channel_id, i.e. referring to all channels.
+            pub fn all_channels(data: impl Into<Vec<u8>>) -> Self {
+                Self::new(ChannelId::ALL, data)
+            }
+
+            pub fn is_all_channels(&self) -> bool {
+                self.channel_id.is_all()
+            }
+
+            /// The data as a string if it is made up solely of printable ASCII characters (byte
+            /// values 32 through 126, inclusive), which is the only case it should be shown
+            /// verbatim.
+            pub fn printable_data(&self) -> Option<&str> {
+                if self.data.iter().all(|b| (32..=126).contains(b)) {
+                    core::str::from_utf8(&self.data).ok()
+                } else {
+                    None
+                }
+            }
+
+            /// The data with every byte outside the printable ASCII range escaped as `\xNN`, so it
+            /// can always be logged safely.
+            pub fn sanitized_data(&self) -> String {
+                let mut res = String::with_capacity(self.data.len());
+                for b in &self.data {
+                    if (32..=126).contains(b) {
+                        res.push(*b as char);
+                    } else {
+                        res.push_str(&format!("\\x{:02x}", b));
+                    }
+                }
+                res
+            }
+
+            pub fn unknown_channel(channel_id: ChannelId) -> Self {
+                Self::new(channel_id, "unknown channel")
+            }
+
+            pub fn invalid_signature(channel_id: ChannelId) -> Self {
+                Self::new(channel_id, "invalid signature")
+            }
+
+            pub fn invalid_chain_hash(channel_id: ChannelId) -> Self {
+                Self::new(channel_id, "unsupported chain_hash")
+            }
+
+            /// Tells the peer why one of its messages could not be decoded.
+            pub fn decode_failed(channel_id: ChannelId, err: &DecodeError) -> Self {
+                Self::new(channel_id, format!("failed to decode message: {}", err))
+            }
+        }
+    }
+}
+
+impl_channel_data_msg!(ErrorMessage);
+impl_channel_data_msg!(WarningMessage);
+
+impl Ping {
+    /// A ping asking for `num_pong_bytes` in the reply, padded with `bytes_len` zeroed bytes.
+    pub fn new(num_pong_bytes: u16, bytes_len: u16) -> Self {
+        Ping { num_pong_bytes, bytes_len, ignored: vec![0; bytes_len as usize] }
+    }
+}
+
+impl Pong {
+    /// A pong carrying `bytes_len` zeroed bytes.
+    pub fn new(bytes_len: u16) -> Self {
+        Pong { bytes_len, ignored: vec![0; bytes_len as usize] }
+    }
+}
+
+impl UpdateFailHTLC {
+    /// Fails the HTLC `id` with a failure packet as `reason`.
+    ///
+    /// # Panics
+    ///
+    /// If `reason` is longer than the 65535 bytes which fit in the length field, which doesn't
+    /// happen for packets built by `failure::create_failure_packet`.
+    pub fn new(channel_id: ChannelId, id: u64, reason: Vec<u8>) -> Self {
+        let len = u16::try_from(reason.len()).expect("failure packet too long");
+        UpdateFailHTLC { channel_id, id, len, reason }
+    }
+}
+
+impl Readable for Ping {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let num_pong_bytes: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("num_pong_bytes"))?;
+        let bytes_len: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("bytes_len"))?;
+        let ignored: Vec<u8> = FixedLengthReadable::read(reader, bytes_len as usize).map_err(|e: DecodeError| e.in_field("ignored"))?;
+
+        Ok(Ping { num_pong_bytes, bytes_len, ignored })
+    }
+}
+
+impl Writeable for Ping {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.num_pong_bytes.write(writer)?;
+        len += write_len(self.ignored.len(), writer)?;
+        writer.write_all(&self.ignored)?;
+        Ok(len + self.ignored.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Readable for Pong {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let bytes_len: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("bytes_len"))?;
+        let ignored: Vec<u8> = FixedLengthReadable::read(reader, bytes_len as usize).map_err(|e: DecodeError| e.in_field("ignored"))?;
+
+        Ok(Pong { bytes_len, ignored })
+    }
+}
+
+impl Writeable for Pong {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let len = write_len(self.ignored.len(), writer)?;
+        writer.write_all(&self.ignored)?;
+        Ok(len + self.ignored.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl_writeable_msg!(OpenChannel, {
+    chain_hash,
+    temp_channel_id,
+    funding_sats,
+    push_msat,
+    dust_limit_sats,
+    max_htlc_value_in_flight_msat,
+    channel_reserve_sats,
+    htlc_min_msat,
+    feerate_per_kw,
+    to_self_delay,
+    max_accepted_htlcs,
+    funding_pubkey,
+    revocation_basepoint,
+    payment_basepoint,
+    delayed_payment_basepoint,
+    htlc_basepoint,
+    first_per_commitment_point,
+    channel_flags,
+    tlv_stream,
+});
+
+/// The receiving node MUST fail the channel if the chain_hash value within the open_channel message
+/// is set to a hash of a chain that is unknown to the receiver, so the chains the node operates on
+/// are given as argument.
+impl ReadableArgs<&[ChainHash]> for OpenChannel {
+	fn read<R: Read>(reader: &mut R, chains: &[ChainHash]) -> Result<Self, DecodeError> {
+        let msg: OpenChannel = Readable::read(reader)?;
+        if !chains.contains(&msg.chain_hash) {
+            return Err(DecodeError::UnknownChain.in_field("chain_hash"))
+        }
+        Ok(msg)
+    }
+}
+
+impl_writeable_msg!(AcceptChannel, {
+    temp_channel_id,
+    dust_limit_sats,
+    max_htlc_value_in_flight_msat,
+    channel_reserve_sats,
+    htlc_min_msat,
+    min_depth,
+    to_self_delay,
+    max_accepted_htlcs,
+    funding_pubkey,
+    revocation_basepoint,
+    payment_basepoint,
+    delayed_payment_basepoint,
+    htlc_basepoint,
+    first_per_commitment_point,
+    accept_channel_tlvs,
+});
+
+impl_writeable_msg!(FundingCreated, { temp_channel_id, funding_txid, funding_output_index, signature });
+impl_writeable_msg!(FundingSigned, { channel_id, signature });
+impl_writeable_msg!(FundingLocked, { channel_id, next_per_commitment_point });
+
+impl Readable for Shutdown {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let channel_id: ChannelId = Readable::read(reader).map_err(|e: DecodeError| e.in_field("channel_id"))?;
+        let len: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("len"))?;
+        let script: Vec<u8> = FixedLengthReadable::read(reader, len as usize).map_err(|e: DecodeError| e.in_field("scriptpubkey"))?;
+
+        Ok(Shutdown { channel_id, len, scriptpubkey: Script::from(script) })
+    }
+}
+
+impl Writeable for Shutdown {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.channel_id.write(writer)?;
+        len += write_len(self.scriptpubkey.len(), writer)?;
+        writer.write_all(self.scriptpubkey.as_bytes())?;
+        Ok(len + self.scriptpubkey.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl_writeable_msg!(ClosingSigned, { channel_id, fee_sats, signature, tlv_stream });
+impl_writeable_msg!(UpdateAddHTLC, {
+    channel_id,
+    id,
+    amount_msat,
+    payment_hash,
+    cltv_expiry,
+    onion_routing_packet,
+    tlv_stream,
+});
+impl_writeable_msg!(UpdateFulfillHTLC, { channel_id, id, payment_preimage });
+
+impl Readable for UpdateFailHTLC {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let channel_id: ChannelId = Readable::read(reader).map_err(|e: DecodeError| e.in_field("channel_id"))?;
+        let id: u64 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("id"))?;
+        let len: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("len"))?;
+        let reason: Vec<u8> = FixedLengthReadable::read(reader, len as usize).map_err(|e: DecodeError| e.in_field("reason"))?;
+
+        Ok(UpdateFailHTLC { channel_id, id, len, reason })
+    }
+}
+
+impl Writeable for UpdateFailHTLC {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.channel_id.write(writer)?;
+        len += self.id.write(writer)?;
+        len += write_len(self.reason.len(), writer)?;
+        writer.write_all(&self.reason)?;
+        Ok(len + self.reason.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl_writeable_msg!(UpdateFailMalformedHTLC, { channel_id, id, sha256_of_onion, failure_code });
+
+impl Readable for CommitmentSigned {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let channel_id: ChannelId = Readable::read(reader).map_err(|e: DecodeError| e.in_field("channel_id"))?;
+        let signature: Signature = Readable::read(reader).map_err(|e: DecodeError| e.in_field("signature"))?;
+        let num_htlc: u16 = Readable::read(reader).map_err(|e: DecodeError| e.in_field("num_htlc"))?;
+        // Don't trust num_htlc for the allocation: no more signatures fit in a message
+        let mut htlc_signature = Vec::with_capacity((num_htlc as usize).min(MAX_MESSAGE_LEN / 64));
+        for _ in 0..num_htlc {
+            htlc_signature.push(Readable::read(reader).map_err(|e: DecodeError| e.in_field("htlc_signature"))?);
+        }
+
+        Ok(CommitmentSigned { channel_id, signature, num_htlc, htlc_signature })
+    }
+}
+
+impl Writeable for CommitmentSigned {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.channel_id.write(writer)?;
+        len += self.signature.write(writer)?;
+        len += write_len(self.htlc_signature.len(), writer)?;
+        for signature in &self.htlc_signature {
+            len += signature.write(writer)?;
+        }
+        Ok(len)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl_writeable_msg!(RevokeAndACK, { channel_id, per_commitment_secret, next_per_commitment_point });
+impl_writeable_msg!(UpdateFee, { channel_id, feerate_per_kw });
+impl_writeable_msg!(ChannelReestablish, {
+    channel_id,
+    next_commitment_number,
+    next_revocation_number,
+    your_last_per_commitment_secret,
+    my_current_per_commitment_point,
+});
+
+impl_display_hex!(ErrorMessage);
+impl_display_hex!(WarningMessage);
+impl_display_hex!(Ping);
+impl_display_hex!(Pong);
+impl_display_hex!(OpenChannel);
+impl_display_hex!(AcceptChannel);
+impl_display_hex!(FundingCreated);
+impl_display_hex!(FundingSigned);
+impl_display_hex!(FundingLocked);
+impl_display_hex!(Shutdown);
+impl_display_hex!(ClosingSigned);
+impl_display_hex!(UpdateAddHTLC);
+impl_display_hex!(UpdateFulfillHTLC);
+impl_display_hex!(UpdateFailHTLC);
+impl_display_hex!(UpdateFailMalformedHTLC);
+impl_display_hex!(CommitmentSigned);
+impl_display_hex!(RevokeAndACK);
+impl_display_hex!(UpdateFee);
+impl_display_hex!(ChannelReestablish);
+
+impl_describe_channel_data_msg!(ErrorMessage, "error");
+impl_describe_channel_data_msg!(WarningMessage, "warning");
+impl_describe_msg!(Ping, "ping", { num_pong_bytes, ignored });
+impl_describe_msg!(Pong, "pong", { ignored });
+impl_describe_msg!(OpenChannel, "open_channel", {
+    chain_hash,
+    temp_channel_id,
+    funding_sats,
+    push_msat,
+    dust_limit_sats,
+    max_htlc_value_in_flight_msat,
+    channel_reserve_sats,
+    htlc_min_msat,
+    feerate_per_kw,
+    to_self_delay,
+    max_accepted_htlcs,
+    funding_pubkey,
+    revocation_basepoint,
+    payment_basepoint,
+    delayed_payment_basepoint,
+    htlc_basepoint,
+    first_per_commitment_point,
+    channel_flags,
+    tlv_stream,
+});
+impl_describe_msg!(AcceptChannel, "accept_channel", {
+    temp_channel_id,
+    dust_limit_sats,
+    max_htlc_value_in_flight_msat,
+    channel_reserve_sats,
+    htlc_min_msat,
+    min_depth,
+    to_self_delay,
+    max_accepted_htlcs,
+    funding_pubkey,
+    revocation_basepoint,
+    payment_basepoint,
+    delayed_payment_basepoint,
+    htlc_basepoint,
+    first_per_commitment_point,
+    accept_channel_tlvs,
+});
+impl_describe_msg!(FundingCreated, "funding_created", { temp_channel_id, funding_txid, funding_output_index, signature });
+impl_describe_msg!(FundingSigned, "funding_signed", { channel_id, signature });
+impl_describe_msg!(FundingLocked, "funding_locked", { channel_id, next_per_commitment_point });
+impl_describe_msg!(Shutdown, "shutdown", { channel_id, scriptpubkey });
+impl_describe_msg!(ClosingSigned, "closing_signed", { channel_id, fee_sats, signature, tlv_stream });
+impl_describe_msg!(UpdateAddHTLC, "update_add_htlc", {
+    channel_id,
+    id,
+    amount_msat,
+    payment_hash,
+    cltv_expiry,
+    onion_routing_packet,
+    tlv_stream,
+});
+impl_describe_msg!(UpdateFulfillHTLC, "update_fulfill_htlc", { channel_id, id, payment_preimage });
+impl_describe_msg!(UpdateFailHTLC, "update_fail_htlc", { channel_id, id, reason });
+impl_describe_msg!(UpdateFailMalformedHTLC, "update_fail_malformed_htlc", { channel_id, id, sha256_of_onion, failure_code });
+impl_describe_msg!(CommitmentSigned, "commitment_signed", { channel_id, signature, htlc_signature });
+impl_describe_msg!(RevokeAndACK, "revoke_and_ack", { channel_id, per_commitment_secret, next_per_commitment_point });
+impl_describe_msg!(UpdateFee, "update_fee", { channel_id, feerate_per_kw });
+impl_describe_msg!(ChannelReestablish, "channel_reestablish", {
+    channel_id,
+    next_commitment_number,
+    next_revocation_number,
+    your_last_per_commitment_secret,
+    my_current_per_commitment_point,
+});
+
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use alloc::string::ToString;
+
+    #[cfg(feature = "std")]
+    use bitcoin::{Network, blockdata::constants::genesis_block, hashes::Hash};
+
+    use crate::io;
+
+    use crate::{ser::{Readable, ReadableArgs, Writeable, DecodeError}, features::Feature, msgs::*, types::*, wire::{read_message, Message, Type}};
 
-    use crate::{ser::{Readable, DecodeError}, msgs::Init};
+    const CHANNEL_ID: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+    const POINT_1: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const POINT_2: &str = "02466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f27";
+    const POINT_3: &str = "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa";
+    const SIGNATURE: &str = concat!(
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222"
+    );
+
+    /// Decodes `vector` as `$ty` and checks that encoding it again gives back identical bytes.
+    macro_rules! do_test_roundtrip {
+        ($ty: ty, $vector: expr) => {
+            let bytes = hex::decode($vector).expect("input");
+            let mut buff = bytes.as_slice();
+            let msg: $ty = Readable::read(&mut buff).expect("no failure");
+            assert_eq!(msg.encode().unwrap(), bytes);
+            assert_eq!(msg.to_string(), hex::encode(&bytes));
+        };
+    }
 
     #[test]
     fn valid_init_msgs() {
         let test_vectors = [
             "001000000000",
-            "001000000000c9012acb0104"
+            "001000000000c9012acb0104",
+            // option_data_loss_protect and optional var_onion_optin
+            "00100002020100020201",
+            // networks with a single chain and a remote_addr
+            "001000000000012043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000030501c0a80101",
         ];
 
         for vector in test_vectors {
-            let mut buff = Cursor::new(hex::decode(vector).expect("input"));
-            let msg: Init = Readable::read(&mut buff).expect("no failure");
-            assert_eq!(msg.to_string(), vector);
+            let input = hex::decode(vector).expect("input");
+            let mut buff = input.as_slice();
+            let msg = match read_message(&mut buff).expect("no failure") {
+                Message::Init(msg) => msg,
+                m => panic!("unexpected message {:?}", m),
+            };
+            assert_eq!(format!("{:04x}{}", Init::TYPE, msg), vector);
+            assert_eq!(hex::encode(Message::Init(msg).encode().unwrap()), vector);
         }
     }
 
+    #[test]
+    fn init_merges_global_features() {
+        // option_data_loss_protect in globalfeatures, optional var_onion_optin in features
+        let input = hex::decode("001000010100020200").expect("input");
+        let mut buff = input.as_slice();
+        let msg = match read_message(&mut buff).expect("no failure") {
+            Message::Init(msg) => msg,
+            m => panic!("unexpected message {:?}", m),
+        };
+        assert!(msg.features.requires_data_loss_protect());
+        assert!(msg.features.supports_var_onion_optin());
+        // globalfeatures mirrors bits 0 to 13 of features when written back
+        assert_eq!(hex::encode(Message::Init(msg).encode().unwrap()), "00100002020100020201");
+    }
+
     #[test]
     fn invalid_init_msgs() {
         let test_vectors = [
-            ("00100000000001", DecodeError::ShortRead),
-            ("001000000000ca012a", DecodeError::UnknownRequiredFeature),
-            ("001000000000c90101c90102", DecodeError::InvalidData),
+            ("00100000000001", DecodeError::ShortRead.in_field("init_tlvs")),
+            ("001000000000ca012a", DecodeError::UnknownEvenType(0xca).in_field("init_tlvs")),
+            ("001000000000c90101c90102", DecodeError::TlvOutOfOrder { prev: 0xc9, got: 0xc9 }.in_field("init_tlvs")),
+            // Unknown even feature bit 2
+            ("00100000000104", DecodeError::UnknownRequiredFeature.in_field("features")),
+            // Unknown even feature bit 2 in globalfeatures
+            ("00100001040000", DecodeError::UnknownRequiredFeature.in_field("features")),
+            // basic_mpp without payment_secret
+            ("001000000003020200", DecodeError::InvalidData.in_field("features")),
+            // networks which isn't a whole number of chain hashes
+            ("0010000000000101aa", DecodeError::InvalidData.in_field("networks").in_field("init_tlvs")),
         ];
 
         for vector in test_vectors {
-            let mut buff = Cursor::new(hex::decode(vector.0).expect("input"));
-            let msg: Result<Init, DecodeError> = Readable::read(&mut buff);
+            let input = hex::decode(vector.0).expect("input");
+            let mut buff = input.as_slice();
+            let msg = read_message(&mut buff);
             assert_eq!(msg.unwrap_err(), vector.1);
         }
     }
+
+    #[test]
+    fn error_and_warning_roundtrip() {
+        do_test_roundtrip!(ErrorMessage, [ CHANNEL_ID, "0004", "74657374" ].concat());
+        do_test_roundtrip!(ErrorMessage, [ &"00".repeat(32), "0000" ].concat());
+        do_test_roundtrip!(WarningMessage, [ CHANNEL_ID, "0004", "74657374" ].concat());
+    }
+
+    #[test]
+    fn error_and_warning_helpers() {
+        let msg = ErrorMessage::all_channels("funding timed out");
+        assert!(msg.is_all_channels());
+        assert_eq!(msg.len, 17);
+        assert_eq!(msg.printable_data(), Some("funding timed out"));
+        assert_eq!(msg.sanitized_data(), "funding timed out");
+
+        let input = hex::decode([ CHANNEL_ID, "0005", "6869000aff" ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: WarningMessage = Readable::read(&mut buff).unwrap();
+        assert!(!msg.is_all_channels());
+        assert_eq!(msg.printable_data(), None);
+        assert_eq!(msg.sanitized_data(), "hi\\x00\\x0a\\xff");
+
+        let msg = ErrorMessage::decode_failed(ChannelId([2; 32]), &DecodeError::ShortRead);
+        assert_eq!(msg.printable_data(), Some("failed to decode message: short read"));
+        assert_eq!(hex::encode(msg.channel_id), CHANNEL_ID);
+        assert_eq!(WarningMessage::new(ChannelId::ALL, vec![0x61; 70000]).len, 65535);
+    }
+
+    #[test]
+    fn ping_pong_roundtrip() {
+        do_test_roundtrip!(Ping, "000400020000");
+        do_test_roundtrip!(Ping, "00000000");
+        do_test_roundtrip!(Pong, "000400000000");
+        do_test_roundtrip!(Pong, "0000");
+    }
+
+    #[test]
+    fn channel_establishment_roundtrip() {
+        let open_channel = [
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+            CHANNEL_ID,
+            "00000000000f4240", "00000000000003e8", "0000000000000222", "000000003b9aca00",
+            "0000000000002710", "00000000000003e8", "000001f4", "0090", "01e3",
+            POINT_1, POINT_2, POINT_3, POINT_1, POINT_2, POINT_3,
+            "01",
+        ].concat();
+        do_test_roundtrip!(OpenChannel, &open_channel);
+
+        // With upfront_shutdown_script and channel_type (static_remote_key)
+        let with_tlvs = [
+            open_channel.as_str(),
+            "00", "16", "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "01", "02", "1000",
+        ].concat();
+        let input = hex::decode(&with_tlvs).unwrap();
+        let mut buff = input.as_slice();
+        let msg: OpenChannel = Readable::read(&mut buff).expect("no failure");
+        match msg.tlv_stream.get(1) {
+            Some(OpenChannelTlvs::ChannelType(features)) => assert!(features.requires_static_remote_key()),
+            _ => panic!(),
+        }
+        assert_eq!(hex::encode(msg.encode().unwrap()), with_tlvs);
+
+        let accept_channel = [
+            CHANNEL_ID,
+            "0000000000000222", "000000003b9aca00", "0000000000002710", "00000000000003e8",
+            "00000003", "0090", "01e3",
+            POINT_1, POINT_2, POINT_3, POINT_1, POINT_2, POINT_3,
+        ].concat();
+        do_test_roundtrip!(AcceptChannel, accept_channel);
+
+        do_test_roundtrip!(FundingCreated, [
+            CHANNEL_ID,
+            "c3f3a0a4cd6bd41bcf1c6bbd9b4a8e0af57f44a5b5fb9e5a4c0ed2ba4ff1e5c3",
+            "0001",
+            SIGNATURE,
+        ].concat());
+        do_test_roundtrip!(FundingSigned, [ CHANNEL_ID, SIGNATURE ].concat());
+        do_test_roundtrip!(FundingLocked, [ CHANNEL_ID, POINT_3 ].concat());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn chain_hash_constants() {
+        for network in [Network::Bitcoin, Network::Testnet, Network::Signet, Network::Regtest] {
+            let genesis = genesis_block(network).block_hash();
+            assert_eq!(ChainHash::from(network).0, genesis.into_inner());
+            assert_eq!(ChainHash::from(network).network(), Some(network));
+        }
+        assert_eq!(ChainHash([0; 32]).network(), None);
+    }
+
+    #[test]
+    fn open_channel_on_unknown_chain() {
+        let open_channel = hex::decode([
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+            CHANNEL_ID,
+            "00000000000f4240", "00000000000003e8", "0000000000000222", "000000003b9aca00",
+            "0000000000002710", "00000000000003e8", "000001f4", "0090", "01e3",
+            POINT_1, POINT_2, POINT_3, POINT_1, POINT_2, POINT_3,
+            "01",
+        ].concat()).unwrap();
+
+        let msg = <OpenChannel as ReadableArgs<_>>::read(&mut &open_channel[..], &[ChainHash::BITCOIN][..]);
+        assert_eq!(msg.expect("no failure").chain_hash, ChainHash::BITCOIN);
+
+        let msg = <OpenChannel as ReadableArgs<_>>::read(&mut &open_channel[..], &[ChainHash::TESTNET, ChainHash::REGTEST][..]);
+        assert_eq!(msg.unwrap_err(), DecodeError::UnknownChain.in_field("chain_hash"));
+    }
+
+    #[test]
+    fn channel_close_roundtrip() {
+        do_test_roundtrip!(Shutdown, [
+            CHANNEL_ID,
+            "0016",
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+        ].concat());
+        do_test_roundtrip!(ClosingSigned, [ CHANNEL_ID, "00000000000001f4", SIGNATURE ].concat());
+        do_test_roundtrip!(ClosingSigned, [
+            CHANNEL_ID, "00000000000001f4", SIGNATURE,
+            "01", "10", "00000000000001f4", "00000000000003e8",
+        ].concat());
+
+        // min_fee_satoshis above max_fee_satoshis
+        let input = hex::decode([
+            CHANNEL_ID, "00000000000001f4", SIGNATURE,
+            "01", "10", "00000000000003e8", "00000000000001f4",
+        ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<ClosingSigned, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::InvalidData.in_field("fee_range").in_field("tlv_stream"));
+    }
+
+    #[test]
+    fn normal_operation_roundtrip() {
+        do_test_roundtrip!(UpdateAddHTLC, [
+            CHANNEL_ID,
+            "0000000000000002",
+            "00000000000186a0",
+            "0303030303030303030303030303030303030303030303030303030303030303",
+            "00000090",
+            &"00".repeat(1366),
+        ].concat());
+        do_test_roundtrip!(UpdateAddHTLC, [
+            CHANNEL_ID,
+            "0000000000000002",
+            "00000000000186a0",
+            "0303030303030303030303030303030303030303030303030303030303030303",
+            "00000090",
+            &"00".repeat(1366),
+            "0021", POINT_1,
+        ].concat());
+        do_test_roundtrip!(UpdateFulfillHTLC, [
+            CHANNEL_ID,
+            "0000000000000002",
+            "0404040404040404040404040404040404040404040404040404040404040404",
+        ].concat());
+        do_test_roundtrip!(UpdateFailHTLC, [ CHANNEL_ID, "0000000000000002", "0003", "010203" ].concat());
+        do_test_roundtrip!(UpdateFailMalformedHTLC, [
+            CHANNEL_ID,
+            "0000000000000002",
+            "0505050505050505050505050505050505050505050505050505050505050505",
+            "c005",
+        ].concat());
+        do_test_roundtrip!(CommitmentSigned, [ CHANNEL_ID, SIGNATURE, "0000" ].concat());
+        do_test_roundtrip!(CommitmentSigned, [ CHANNEL_ID, SIGNATURE, "0002", SIGNATURE, SIGNATURE ].concat());
+        do_test_roundtrip!(RevokeAndACK, [
+            CHANNEL_ID,
+            "0606060606060606060606060606060606060606060606060606060606060606",
+            POINT_2,
+        ].concat());
+        do_test_roundtrip!(UpdateFee, [ CHANNEL_ID, "000003e8" ].concat());
+    }
+
+    #[test]
+    fn lengths_follow_the_data() {
+        let mut msg = UpdateFailHTLC::new(ChannelId([2; 32]), 2, vec![1, 2]);
+        msg.reason.push(3);
+        assert_eq!(hex::encode(msg.encode().unwrap()), [ CHANNEL_ID, "0000000000000002", "0003", "010203" ].concat());
+
+        let signature = Signature::from_compact(&hex::decode(SIGNATURE).unwrap()).unwrap();
+        let msg = CommitmentSigned { channel_id: ChannelId([2; 32]), signature, num_htlc: 0, htlc_signature: vec![signature] };
+        assert_eq!(hex::encode(msg.encode().unwrap()), [ CHANNEL_ID, SIGNATURE, "0001", SIGNATURE ].concat());
+
+        let mut msg = Ping::new(0, 2);
+        msg.ignored.clear();
+        assert_eq!(hex::encode(msg.encode().unwrap()), "00000000");
+        let msg = Pong { bytes_len: 0, ignored: vec![0; 70000] };
+        assert_eq!(msg.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let msg = ErrorMessage { channel_id: ChannelId::ALL, len: 0, data: vec![0; 65536] };
+        assert_eq!(msg.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn channel_reestablish_roundtrip() {
+        do_test_roundtrip!(ChannelReestablish, [
+            CHANNEL_ID,
+            "0000000000000005",
+            "0000000000000004",
+            "0707070707070707070707070707070707070707070707070707070707070707",
+            POINT_1,
+        ].concat());
+    }
+
+    /// `{:#}` names the fields, decodes known records and shows features by name.
+    #[test]
+    fn describe_msgs() {
+        let describe = |vector: String| {
+            let msg = read_message(&mut &hex::decode(vector).expect("input")[..]).expect("no failure");
+            format!("{:#}", msg)
+        };
+
+        assert_eq!(describe("001000000000".to_string()), "init features=[] init_tlvs=[]");
+        assert_eq!(
+            describe("00100002020100020201".to_string()),
+            "init features=[option_data_loss_protect (required), var_onion_optin (optional)] init_tlvs=[]"
+        );
+        assert_eq!(
+            describe("001000000000012043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000030501c0a80101c9012a".to_string()),
+            concat!(
+                "init features=[] init_tlvs=[networks chains=[43497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000], ",
+                "remote_addr data=01c0a80101, unknown type=201 value=2a]",
+            )
+        );
+        assert_eq!(
+            describe([ "0011", CHANNEL_ID, "0005", "6869000aff" ].concat()),
+            format!(r#"error channel_id={} data="hi\x00\x0a\xff""#, CHANNEL_ID)
+        );
+        assert_eq!(describe("0012000400020000".to_string()), "ping num_pong_bytes=4 ignored=0000");
+        assert_eq!(
+            describe([ "0027", CHANNEL_ID, "00000000000001f4", SIGNATURE, "01", "10", "00000000000001f4", "00000000000003e8" ].concat()),
+            format!("closing_signed channel_id={} fee_sats=500 signature={} tlv_stream=[fee_range min_fee_satoshis=500 max_fee_satoshis=1000]", CHANNEL_ID, SIGNATURE)
+        );
+        assert_eq!(
+            describe([ "0084", CHANNEL_ID, SIGNATURE, "0002", SIGNATURE, SIGNATURE ].concat()),
+            format!("commitment_signed channel_id={0} signature={1} htlc_signature=[{1}, {1}]", CHANNEL_ID, SIGNATURE)
+        );
+        assert_eq!(describe("8001beef".to_string()), "unknown type=32769 payload=beef");
+
+        let mut features = Features::empty();
+        features.set_required(Feature::StaticRemoteKey);
+        features.set_bit(101);
+        let mut tlv_stream = TLVStream::new();
+        tlv_stream.insert(OpenChannelTlvs::UpfrontShutdownScript(Script::from(vec![0x00, 0x14])));
+        tlv_stream.insert(OpenChannelTlvs::ChannelType(features.clone()));
+        assert_eq!(
+            format!("{:#}", tlv_stream),
+            "upfront_shutdown_script shutdown_scriptpubkey=0014, channel_type type=[option_static_remotekey (required), bit 101 (optional)]"
+        );
+        assert_eq!(features.to_string(), "20000000000000000000001000");
+
+        // The plain form stays the hex encoding
+        let msg = Message::Ping(Ping::new(4, 2));
+        assert_eq!(msg.to_string(), "0012000400020000");
+    }
+
+    #[test]
+    fn invalid_channel_msgs() {
+        let input = hex::decode([ CHANNEL_ID, "000301" ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<UpdateFee, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::ShortRead.in_field("feerate_per_kw"));
+
+        // Not a valid point
+        let input = hex::decode([ CHANNEL_ID, &"04".repeat(33) ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<FundingLocked, DecodeError> = Readable::read(&mut buff);
+        let err = msg.unwrap_err();
+        assert_eq!(err.root(), &DecodeError::InvalidPublicKey);
+        assert_eq!(err.to_string(), "next_per_commitment_point: invalid public key");
+
+        // Two HTLC signatures announced but only one present
+        let input = hex::decode([ CHANNEL_ID, SIGNATURE, "0002", SIGNATURE ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<CommitmentSigned, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::ShortRead.in_field("htlc_signature"));
+
+        // Four bytes of reason announced but only two present
+        let input = hex::decode([ CHANNEL_ID, "0000000000000001", "0004", "dead" ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<UpdateFailHTLC, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::ShortRead.in_field("reason"));
+
+        let input = hex::decode([ CHANNEL_ID, "00" ].concat()).unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<Shutdown, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::ShortRead.in_field("len"));
+
+        let input = hex::decode("0004").unwrap();
+        let mut buff = input.as_slice();
+        let msg: Result<ErrorMessage, DecodeError> = Readable::read(&mut buff);
+        assert_eq!(msg.unwrap_err(), DecodeError::ShortRead.in_field("channel_id"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_roundtrip() {
+        let open_channel = [
+            "0020",
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+            CHANNEL_ID,
+            "00000000000f4240", "00000000000003e8", "0000000000000222", "000000003b9aca00",
+            "0000000000002710", "00000000000003e8", "000001f4", "0090", "01e3",
+            POINT_1, POINT_2, POINT_3, POINT_1, POINT_2, POINT_3,
+            "01",
+            "00", "16", "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "01", "02", "1000",
+        ].concat();
+        let test_vectors = [
+            // networks, remote_addr and an unknown odd record
+            "001000000000012043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000030501c0a801010502beef".to_string(),
+            [ "0011", CHANNEL_ID, "0004", "74657374" ].concat(),
+            "001200040002aaaa".to_string(),
+            open_channel,
+            [ "0022", CHANNEL_ID, "c3f3a0a4cd6bd41bcf1c6bbd9b4a8e0af57f44a5b5fb9e5a4c0ed2ba4ff1e5c3", "0001", SIGNATURE ].concat(),
+            [ "0026", CHANNEL_ID, "0016", "0014751e76e8199196d454941c45d1b3a323f1433bd6" ].concat(),
+            [ "0027", CHANNEL_ID, "00000000000001f4", SIGNATURE, "0110", "00000000000001f4", "00000000000003e8" ].concat(),
+            [
+                "0080", CHANNEL_ID, "0000000000000002", "00000000000186a0",
+                &"03".repeat(32), "00000090", &"05".repeat(1366), "0021", POINT_1,
+            ].concat(),
+            [ "0084", CHANNEL_ID, SIGNATURE, "0001", SIGNATURE ].concat(),
+            [ "0085", CHANNEL_ID, &"06".repeat(32), POINT_2 ].concat(),
+            [ "0088", CHANNEL_ID, "0000000000000005", "0000000000000004", &"07".repeat(32), POINT_1 ].concat(),
+            "8001deadbeef".to_string(),
+        ];
+
+        for vector in test_vectors {
+            let msg = read_message(&mut &hex::decode(&vector).unwrap()[..]).expect("no failure");
+            let json = serde_json::to_string(&msg).unwrap();
+            let decoded: Message = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, msg, "{}", json);
+            assert_eq!(hex::encode(decoded.encode().unwrap()), vector);
+        }
+
+        let input = hex::decode(
+            "001000000000012043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000030501c0a801010502beef"
+        ).unwrap();
+        let mut buff = input.as_slice();
+        let msg = read_message(&mut buff).unwrap();
+        assert_eq!(serde_json::to_string(&msg).unwrap(), concat!(
+            r#"{"init":{"features":"","init_tlvs":{"#,
+            r#""1":["43497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000"],"3":"01c0a80101","5":"beef"}}}"#,
+        ));
+
+        let msg = Message::FundingLocked(FundingLocked {
+            channel_id: ChannelId([2; 32]),
+            next_per_commitment_point: PublicKey::from_slice(&hex::decode(POINT_3).unwrap()).unwrap(),
+        });
+        assert_eq!(serde_json::to_string(&msg).unwrap(), [
+            r#"{"funding_locked":{"channel_id":""#, CHANNEL_ID, r#"","next_per_commitment_point":""#, POINT_3, r#""}}"#,
+        ].concat());
+
+        // Checked as if decoded: min_fee_satoshis above max_fee_satoshis, and an unknown even record
+        let closing_signed = |min_fee_satoshis: u64, max_fee_satoshis: u64| [
+            r#"{"channel_id":""#, CHANNEL_ID, r#"","fee_sats":500,"signature":"#,
+            &serde_json::to_string(&Signature::from_compact(&hex::decode(SIGNATURE).unwrap()).unwrap()).unwrap(),
+            &format!(r#","tlv_stream":{{"1":{{"min_fee_satoshis":{},"max_fee_satoshis":{}}}}}}}"#, min_fee_satoshis, max_fee_satoshis),
+        ].concat();
+        assert!(serde_json::from_str::<ClosingSigned>(&closing_signed(500, 1000)).is_ok());
+        assert!(serde_json::from_str::<ClosingSigned>(&closing_signed(1000, 500)).is_err());
+        assert!(serde_json::from_str::<Init>(r#"{"features":"","init_tlvs":{"4":""}}"#).is_err());
+        assert!(serde_json::from_str::<ChannelId>(r#""0202""#).is_err());
+    }
 }
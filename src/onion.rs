@@ -0,0 +1,899 @@
+//! Sphinx onion packets, as defined by BOLT #4.
+//!
+//! The sender builds a packet from the route and one payload per hop, committing to the
+//! `payment_hash` as associated data. Each hop peels one layer with its node secret, which gives
+//! its own payload and the packet to forward to the next hop.
+
+use std::fmt;
+
+use bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use secp256k1::{PublicKey, SecretKey, Secp256k1, ecdh::SharedSecret};
+
+use crate::io::{self, Read, Write};
+use crate::bigsize::BigSize;
+use crate::ser::{Readable, Writeable, DecodeError, TU32, TU64};
+use crate::tlv::{TLVStream, RecordValue, impl_tlv_value_fixed};
+use crate::types::{PaymentSecret, ShortChannelId};
+use crate::pretty::impl_describe_fields;
+use crate::tlv_namespace;
+
+/// The size of `onion_routing_packet` in update_add_htlc
+pub const ONION_PACKET_LEN: usize = 1366;
+/// The size of the obfuscated hop payloads within a packet
+pub const HOP_PAYLOADS_LEN: usize = 1300;
+/// The size of the HMAC following each hop payload
+pub const HMAC_LEN: usize = 32;
+/// The only packet version currently defined
+const ONION_VERSION: u8 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnionError {
+    /// The packet has a version we don't know
+    InvalidVersion(u8),
+    /// The packet's `public_key` is not a valid point
+    InvalidKey,
+    /// The packet's HMAC doesn't match its contents, e.g. because it was built for another node
+    /// or `payment_hash`
+    InvalidHmac,
+    /// The hop payload's length doesn't fit in the packet
+    InvalidPayloadLength,
+    /// The route is empty or its payloads don't fit in the packet
+    RouteTooLong,
+}
+
+impl std::error::Error for OnionError {}
+
+impl fmt::Display for OnionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnionError::InvalidVersion(v) => write!(f, "unknown onion version {}", v),
+            OnionError::InvalidKey => write!(f, "invalid onion public key"),
+            OnionError::InvalidHmac => write!(f, "onion HMAC mismatch"),
+            OnionError::InvalidPayloadLength => write!(f, "hop payload length exceeds the packet"),
+            OnionError::RouteTooLong => write!(f, "route is empty or its payloads don't fit in an onion"),
+        }
+    }
+}
+
+/// The `onion_routing_packet` of update_add_htlc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnionPacket {
+    pub version: u8,
+    /// The sender's ephemeral key for this hop. It is kept as received since an invalid point
+    /// must be reported to the previous hop rather than fail decoding.
+    pub public_key: [u8; 33],
+    pub hop_payloads: [u8; HOP_PAYLOADS_LEN],
+    pub hmac: [u8; HMAC_LEN],
+}
+
+/// A hop of the route along with the payload it should receive, usually a hop_payloads TLV stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnionHop {
+    pub node_id: PublicKey,
+    pub payload: Vec<u8>,
+}
+
+/// The keys the sender shares with a hop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HopKeys {
+    /// The ephemeral key the hop receives in the packet
+    pub ephemeral_key: PublicKey,
+    /// The ECDH of the ephemeral key and the hop's node id, which every other key derives from
+    pub shared_secret: [u8; 32],
+    /// Multiplies the ephemeral key to give the next hop's
+    pub blinding_factor: [u8; 32],
+}
+
+/// The result of peeling one layer of an onion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeeledOnion {
+    /// The payload intended for this hop
+    pub payload: Vec<u8>,
+    /// The packet to forward, or `None` if this is the final hop
+    pub next: Option<OnionPacket>,
+    /// The secret shared with the sender, used to report failures back to it
+    pub shared_secret: [u8; 32],
+}
+
+impl OnionPacket {
+    pub fn from_bytes(bytes: &[u8; ONION_PACKET_LEN]) -> Self {
+        OnionPacket {
+            version: bytes[0],
+            public_key: bytes[1..34].try_into().expect("length checked"),
+            hop_payloads: bytes[34..34 + HOP_PAYLOADS_LEN].try_into().expect("length checked"),
+            hmac: bytes[34 + HOP_PAYLOADS_LEN..].try_into().expect("length checked"),
+        }
+    }
+
+    /// The encoding carried in `onion_routing_packet`.
+    pub fn to_bytes(&self) -> [u8; ONION_PACKET_LEN] {
+        let mut bytes = [0; ONION_PACKET_LEN];
+        bytes[0] = self.version;
+        bytes[1..34].copy_from_slice(&self.public_key);
+        bytes[34..34 + HOP_PAYLOADS_LEN].copy_from_slice(&self.hop_payloads);
+        bytes[34 + HOP_PAYLOADS_LEN..].copy_from_slice(&self.hmac);
+        bytes
+    }
+}
+
+impl Readable for OnionPacket {
+    fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let bytes: [u8; ONION_PACKET_LEN] = Readable::read(reader)?;
+        Ok(OnionPacket::from_bytes(&bytes))
+    }
+}
+
+impl Writeable for OnionPacket {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.to_bytes().write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.to_bytes().write_fmt(writer)
+    }
+}
+
+/// Derives a key of the given type, e.g. `rho` or `mu`, from a shared secret.
+pub(crate) fn generate_key(key_type: &[u8], secret: &[u8; 32]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key_type);
+    engine.input(secret);
+    Hmac::from_engine(engine).into_inner()
+}
+
+pub(crate) fn hmac(key: &[u8; 32], data: &[&[u8]]) -> [u8; HMAC_LEN] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for d in data {
+        engine.input(d);
+    }
+    Hmac::from_engine(engine).into_inner()
+}
+
+/// XORs `data` with the ChaCha20 stream of `key`, using a zero nonce.
+pub(crate) fn apply_stream(key: &[u8; 32], data: &mut [u8]) {
+    let mut cipher = ChaCha20::new(key.into(), &[0; 12].into());
+    cipher.apply_keystream(data);
+}
+
+pub(crate) fn blinding_factor(ephemeral_key: &PublicKey, shared_secret: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&ephemeral_key.serialize());
+    engine.input(shared_secret);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Derives the keys shared with each hop of `route` from the sender's `session_key`.
+pub fn hop_keys(session_key: &SecretKey, route: &[PublicKey]) -> Result<Vec<HopKeys>, OnionError> {
+    let secp_ctx = Secp256k1::new();
+    let mut ephemeral_secret = *session_key;
+    let mut keys = Vec::with_capacity(route.len());
+    for node_id in route {
+        let ephemeral_key = PublicKey::from_secret_key(&secp_ctx, &ephemeral_secret);
+        let shared_secret = SharedSecret::new(node_id, &ephemeral_secret).secret_bytes();
+        let blinding_factor = blinding_factor(&ephemeral_key, &shared_secret);
+        ephemeral_secret.mul_assign(&blinding_factor).map_err(|_| OnionError::InvalidKey)?;
+        keys.push(HopKeys { ephemeral_key, shared_secret, blinding_factor });
+    }
+    Ok(keys)
+}
+
+/// The size a hop takes up in the packet: its length-prefixed payload and the next HMAC.
+fn hop_len(payload: &[u8]) -> usize {
+    BigSize(payload.len() as u64).encode().expect("writing to a vec doesn't fail").len() + payload.len() + HMAC_LEN
+}
+
+/// The bytes each hop shifts into the end of the packet while peeling, which the sender
+/// reproduces so that the final HMAC covers them.
+fn generate_filler(keys: &[HopKeys], hop_lens: &[usize]) -> Vec<u8> {
+    let mut filler = Vec::new();
+    for (key, len) in keys.iter().zip(hop_lens) {
+        let mut stream = [0; 2 * HOP_PAYLOADS_LEN];
+        apply_stream(&generate_key(b"rho", &key.shared_secret), &mut stream);
+        let start = HOP_PAYLOADS_LEN - filler.len();
+        filler.resize(filler.len() + len, 0);
+        for (byte, s) in filler.iter_mut().zip(&stream[start..]) {
+            *byte ^= s;
+        }
+    }
+    filler
+}
+
+/// Builds the onion for `route`, with the `payment_hash` as `associated_data`. The ephemeral
+/// `session_key` must be random and used only once.
+///
+/// Returns the packet along with the keys shared with each hop, which are needed to attribute
+/// a failure reported back by the route.
+pub fn create_onion_packet(session_key: &SecretKey, route: &[OnionHop], associated_data: &[u8]) -> Result<(OnionPacket, Vec<HopKeys>), OnionError> {
+    let hop_lens: Vec<usize> = route.iter().map(|hop| hop_len(&hop.payload)).collect();
+    if route.is_empty() || hop_lens.iter().sum::<usize>() > HOP_PAYLOADS_LEN {
+        return Err(OnionError::RouteTooLong)
+    }
+    let node_ids: Vec<PublicKey> = route.iter().map(|hop| hop.node_id).collect();
+    let keys = hop_keys(session_key, &node_ids)?;
+    let filler = generate_filler(&keys[..keys.len() - 1], &hop_lens);
+
+    // The unused end of the packet is filled with a stream derived from the session key, so that
+    // it doesn't tell the last hop its position in the route.
+    let mut hop_payloads = [0; HOP_PAYLOADS_LEN];
+    apply_stream(&generate_key(b"pad", &session_key.secret_bytes()), &mut hop_payloads);
+
+    let mut next_hmac = [0; HMAC_LEN];
+    for (i, (hop, key)) in route.iter().zip(&keys).enumerate().rev() {
+        let len = hop_lens[i];
+        hop_payloads.copy_within(..HOP_PAYLOADS_LEN - len, len);
+        let mut writer = &mut hop_payloads[..len];
+        BigSize(hop.payload.len() as u64).write(&mut writer).expect("fits in the hop's space");
+        writer.write_all(&hop.payload).expect("fits in the hop's space");
+        writer.write_all(&next_hmac).expect("fits in the hop's space");
+
+        apply_stream(&generate_key(b"rho", &key.shared_secret), &mut hop_payloads);
+        if i == route.len() - 1 {
+            hop_payloads[HOP_PAYLOADS_LEN - filler.len()..].copy_from_slice(&filler);
+        }
+        next_hmac = hmac(&generate_key(b"mu", &key.shared_secret), &[&hop_payloads, associated_data]);
+    }
+
+    let packet = OnionPacket {
+        version: ONION_VERSION,
+        public_key: keys[0].ephemeral_key.serialize(),
+        hop_payloads,
+        hmac: next_hmac,
+    };
+    Ok((packet, keys))
+}
+
+/// Peels the layer of `packet` intended for the node with `node_secret`, checking its HMAC
+/// against the `payment_hash` given as `associated_data`.
+pub fn peel_onion(node_secret: &SecretKey, packet: &OnionPacket, associated_data: &[u8]) -> Result<PeeledOnion, OnionError> {
+    if packet.version != ONION_VERSION {
+        return Err(OnionError::InvalidVersion(packet.version))
+    }
+    let ephemeral_key = PublicKey::from_slice(&packet.public_key).map_err(|_| OnionError::InvalidKey)?;
+    let shared_secret = SharedSecret::new(&ephemeral_key, node_secret).secret_bytes();
+
+    let expected = hmac(&generate_key(b"mu", &shared_secret), &[&packet.hop_payloads, associated_data]);
+    if expected != packet.hmac {
+        return Err(OnionError::InvalidHmac)
+    }
+
+    // The packet is extended with zeros which the stream turns into the next hop's filler
+    let mut bytes = [0; 2 * HOP_PAYLOADS_LEN];
+    bytes[..HOP_PAYLOADS_LEN].copy_from_slice(&packet.hop_payloads);
+    apply_stream(&generate_key(b"rho", &shared_secret), &mut bytes);
+
+    let mut reader = &bytes[..HOP_PAYLOADS_LEN];
+    let len: BigSize = Readable::read(&mut reader).map_err(|_| OnionError::InvalidPayloadLength)?;
+    let start = HOP_PAYLOADS_LEN - reader.len();
+    if len.0 > (reader.len() - HMAC_LEN) as u64 {
+        return Err(OnionError::InvalidPayloadLength)
+    }
+    let end = start + len.0 as usize;
+    let payload = bytes[start..end].to_vec();
+    let hmac: [u8; HMAC_LEN] = bytes[end..end + HMAC_LEN].try_into().expect("length checked");
+
+    // An all-zero HMAC marks the final hop
+    let next = if hmac == [0; HMAC_LEN] {
+        None
+    } else {
+        let mut next_key = ephemeral_key;
+        next_key.mul_assign(&Secp256k1::verification_only(), &blinding_factor(&ephemeral_key, &shared_secret))
+            .map_err(|_| OnionError::InvalidKey)?;
+        Some(OnionPacket {
+            version: ONION_VERSION,
+            public_key: next_key.serialize(),
+            hop_payloads: bytes[end + HMAC_LEN..end + HMAC_LEN + HOP_PAYLOADS_LEN].try_into().expect("length checked"),
+            hmac,
+        })
+    };
+
+    Ok(PeeledOnion { payload, next, shared_secret })
+}
+
+tlv_namespace! {
+    /// The records of a hop payload, i.e. the payload of each hop in an onion.
+    pub enum HopPayloadTlvs: "payload" {
+        /// The amount to forward to the next hop, or the amount of the HTLC for the final hop
+        2 => AmtToForward(TU64) as "amt_to_forward", optional;
+        /// The cltv_expiry the outgoing HTLC, or the final HTLC, must have
+        4 => OutgoingCltvValue(TU32) as "outgoing_cltv_value", optional;
+        /// The channel to forward the HTLC through
+        6 => ShortChannelId(ShortChannelId) as "short_channel_id", optional;
+        8 => PaymentData(PaymentData) as "payment_data", optional;
+        /// Data the recipient of a blinded path encrypted for this hop
+        10 => EncryptedRecipientData(Vec<u8>) as "encrypted_recipient_data", optional;
+        /// The path key of the introduction node of a blinded path, which the other hops get in
+        /// update_add_htlc instead
+        12 => CurrentPathKey(PublicKey) as "current_path_key", optional;
+        /// The payment_metadata from the invoice, for the final hop
+        16 => PaymentMetadata(Vec<u8>) as "payment_metadata", optional;
+        /// The total amount of a payment to a blinded path, like `total_msat` in `payment_data`
+        18 => TotalAmountMsat(TU64) as "total_amount_msat", optional;
+    }
+}
+
+/// The payment_secret from the invoice and the total amount of a possibly multi-part payment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaymentData {
+    pub payment_secret: PaymentSecret,
+    pub total_msat: u64,
+}
+
+/// Within its record, `total_msat` spans the rest of the value.
+impl Readable for PaymentData {
+    fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let payment_secret = Readable::read(reader)?;
+        let total_msat: TU64 = Readable::read(reader)?;
+        Ok(PaymentData { payment_secret, total_msat: total_msat.0 })
+    }
+}
+
+impl Writeable for PaymentData {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let len = self.payment_secret.write(writer)?;
+        Ok(len + TU64(self.total_msat).write(writer)?)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.payment_secret.write_fmt(writer)?;
+        TU64(self.total_msat).write_fmt(writer)
+    }
+}
+
+impl_tlv_value_fixed!(PaymentData);
+impl_describe_fields!(PaymentData, { payment_secret, total_msat });
+
+#[cfg(feature = "serde")]
+crate::tlv::impl_serde_value!(PaymentData);
+
+/// Why a hop payload is refused, which the hop reports with `invalid_onion_payload`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadError {
+    /// The payload is not a valid TLV stream
+    Decode(DecodeError),
+    /// A record this hop needs is missing
+    MissingRecord(u64),
+    /// A record which doesn't belong in this hop's payload is present
+    UnexpectedRecord(u64),
+}
+
+impl std::error::Error for PayloadError {}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::Decode(e) => write!(f, "{}", e),
+            PayloadError::MissingRecord(t) => write!(f, "missing payload record {}", t),
+            PayloadError::UnexpectedRecord(t) => write!(f, "unexpected payload record {}", t),
+        }
+    }
+}
+
+impl From<DecodeError> for PayloadError {
+    fn from(e: DecodeError) -> Self {
+        PayloadError::Decode(e)
+    }
+}
+
+/// The decoded payload of a hop.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HopPayload {
+    pub amt_to_forward: Option<u64>,
+    pub outgoing_cltv_value: Option<u32>,
+    pub short_channel_id: Option<ShortChannelId>,
+    pub payment_data: Option<PaymentData>,
+    pub encrypted_recipient_data: Option<Vec<u8>>,
+    pub current_path_key: Option<PublicKey>,
+    pub payment_metadata: Option<Vec<u8>>,
+    pub total_amount_msat: Option<u64>,
+    /// Odd records unknown to us, such as custom records, as received
+    pub unknown_records: Vec<(u64, Vec<u8>)>,
+}
+
+impl HopPayload {
+    /// The payload of a hop forwarding `amt_to_forward` through `short_channel_id`.
+    pub fn forward(short_channel_id: ShortChannelId, amt_to_forward: u64, outgoing_cltv_value: u32) -> Self {
+        HopPayload {
+            amt_to_forward: Some(amt_to_forward),
+            outgoing_cltv_value: Some(outgoing_cltv_value),
+            short_channel_id: Some(short_channel_id),
+            ..Default::default()
+        }
+    }
+
+    /// The payload of the final hop, receiving `amt_to_forward`.
+    pub fn final_hop(amt_to_forward: u64, outgoing_cltv_value: u32, payment_data: Option<PaymentData>) -> Self {
+        HopPayload {
+            amt_to_forward: Some(amt_to_forward),
+            outgoing_cltv_value: Some(outgoing_cltv_value),
+            payment_data,
+            ..Default::default()
+        }
+    }
+
+    /// Decodes the payload of a peeled onion and checks it has the records its hop needs.
+    pub fn from_peeled(peeled: &PeeledOnion) -> Result<Self, PayloadError> {
+        let payload: HopPayload = Readable::read(&mut &peeled.payload[..])?;
+        payload.check(peeled.next.is_none())?;
+        Ok(payload)
+    }
+
+    /// Checks the records which must, or mustn't, be present for a forwarding or final hop.
+    ///
+    /// Every hop needs `amt_to_forward` and `outgoing_cltv_value`. A forwarding hop also needs
+    /// `short_channel_id`, while `payment_data` and `payment_metadata` are only meant for the
+    /// final hop.
+    ///
+    /// Hops within a blinded path, which have `encrypted_recipient_data`, follow other rules: see
+    /// [`HopPayload::check_blinded`].
+    pub fn check(&self, is_final: bool) -> Result<(), PayloadError> {
+        if self.encrypted_recipient_data.is_some() {
+            return self.check_blinded(is_final)
+        }
+        if self.current_path_key.is_some() {
+            return Err(PayloadError::UnexpectedRecord(12))
+        }
+        if self.amt_to_forward.is_none() {
+            return Err(PayloadError::MissingRecord(2))
+        }
+        if self.outgoing_cltv_value.is_none() {
+            return Err(PayloadError::MissingRecord(4))
+        }
+        if !is_final {
+            if self.short_channel_id.is_none() {
+                return Err(PayloadError::MissingRecord(6))
+            }
+            if self.payment_data.is_some() {
+                return Err(PayloadError::UnexpectedRecord(8))
+            }
+            if self.payment_metadata.is_some() {
+                return Err(PayloadError::UnexpectedRecord(16))
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the payload of a hop within a blinded path.
+    ///
+    /// A forwarding hop gets its instructions from `encrypted_recipient_data` alone, so it mustn't
+    /// have other records besides `current_path_key`. The final hop also needs `amt_to_forward`,
+    /// `outgoing_cltv_value` and `total_amount_msat`.
+    pub fn check_blinded(&self, is_final: bool) -> Result<(), PayloadError> {
+        if self.encrypted_recipient_data.is_none() {
+            return Err(PayloadError::MissingRecord(10))
+        }
+        let required = [
+            (2, self.amt_to_forward.is_some()),
+            (4, self.outgoing_cltv_value.is_some()),
+            (18, self.total_amount_msat.is_some()),
+        ];
+        for (typ, present) in required {
+            if present != is_final {
+                return Err(if is_final { PayloadError::MissingRecord(typ) } else { PayloadError::UnexpectedRecord(typ) })
+            }
+        }
+        let unexpected = [
+            (6, self.short_channel_id.is_some()),
+            (8, self.payment_data.is_some()),
+            (16, self.payment_metadata.is_some()),
+        ];
+        match unexpected.iter().find(|(_, present)| *present) {
+            Some((typ, _)) => Err(PayloadError::UnexpectedRecord(*typ)),
+            None => Ok(()),
+        }
+    }
+
+    fn tlv_stream(&self) -> TLVStream<HopPayloadTlvs> {
+        let mut stream = TLVStream::new();
+        if let Some(v) = self.amt_to_forward {
+            stream.insert(HopPayloadTlvs::AmtToForward(TU64(v)));
+        }
+        if let Some(v) = self.outgoing_cltv_value {
+            stream.insert(HopPayloadTlvs::OutgoingCltvValue(TU32(v)));
+        }
+        if let Some(v) = self.short_channel_id {
+            stream.insert(HopPayloadTlvs::ShortChannelId(v));
+        }
+        if let Some(v) = self.payment_data {
+            stream.insert(HopPayloadTlvs::PaymentData(v));
+        }
+        if let Some(v) = &self.encrypted_recipient_data {
+            stream.insert(HopPayloadTlvs::EncryptedRecipientData(v.clone()));
+        }
+        if let Some(v) = self.current_path_key {
+            stream.insert(HopPayloadTlvs::CurrentPathKey(v));
+        }
+        if let Some(v) = &self.payment_metadata {
+            stream.insert(HopPayloadTlvs::PaymentMetadata(v.clone()));
+        }
+        if let Some(v) = self.total_amount_msat {
+            stream.insert(HopPayloadTlvs::TotalAmountMsat(TU64(v)));
+        }
+        for (typ, v) in &self.unknown_records {
+            stream.insert_unknown(*typ, v.clone());
+        }
+        stream
+    }
+}
+
+impl Readable for HopPayload {
+    fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let stream: TLVStream<HopPayloadTlvs> = Readable::read(reader)?;
+        let mut payload = HopPayload::default();
+        for record in stream.records() {
+            match record.value().clone() {
+                RecordValue::Known(HopPayloadTlvs::AmtToForward(v)) => payload.amt_to_forward = Some(v.0),
+                RecordValue::Known(HopPayloadTlvs::OutgoingCltvValue(v)) => payload.outgoing_cltv_value = Some(v.0),
+                RecordValue::Known(HopPayloadTlvs::ShortChannelId(v)) => payload.short_channel_id = Some(v),
+                RecordValue::Known(HopPayloadTlvs::PaymentData(v)) => payload.payment_data = Some(v),
+                RecordValue::Known(HopPayloadTlvs::EncryptedRecipientData(v)) => payload.encrypted_recipient_data = Some(v),
+                RecordValue::Known(HopPayloadTlvs::CurrentPathKey(v)) => payload.current_path_key = Some(v),
+                RecordValue::Known(HopPayloadTlvs::PaymentMetadata(v)) => payload.payment_metadata = Some(v),
+                RecordValue::Known(HopPayloadTlvs::TotalAmountMsat(v)) => payload.total_amount_msat = Some(v.0),
+                RecordValue::Unknown(v) => payload.unknown_records.push((record.record_type(), v)),
+            }
+        }
+        Ok(payload)
+    }
+}
+
+/// Unknown records are written along with the known ones, in type order.
+impl Writeable for HopPayload {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.tlv_stream().write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.tlv_stream().write_fmt(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use crate::bigsize::BigSize;
+    use crate::ser::{Readable, Writeable, DecodeError};
+    use crate::types::{PaymentSecret, ShortChannelId};
+    use super::{create_onion_packet, generate_key, hop_keys, peel_onion, OnionError, OnionHop, OnionPacket, HOP_PAYLOADS_LEN};
+    use super::{HopPayload, PaymentData, PayloadError};
+
+    const ASSOCIATED_DATA: [u8; 32] = [0x42; 32];
+
+    /// The onion of the BOLT #4 test vector
+    const SPEC_PACKET: &str = concat!(
+        "0002eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619f7f3416a5aa36dc7eeb3ec6d421e",
+        "9615471ab870a33ac07fa5d5a51df0a8823aabe3fea3f90d387529d4f72837f9e687230371ccd8d263072206dbed0234",
+        "f6505e21e282abd8c0e4f5b9ff8042800bbab065036eadd0149b37f27dde664725a49866e052e809d2b0198ab9610faa",
+        "656bbf4ec516763a59f8f42c171b179166ba38958d4f51b39b3e98706e2d14a2dafd6a5df808093abfca5aeaaca16ede",
+        "d5db7d21fb0294dd1a163edf0fb445d5c8d7d688d6dd9c541762bf5a5123bf9939d957fe648416e88f1b0928bfa03498",
+        "2b22548e1a4d922690eecf546275afb233acf4323974680779f1a964cfe687456035cc0fba8a5428430b390f0057b6d1",
+        "fe9a8875bfa89693eeb838ce59f09d207a503ee6f6299c92d6361bc335fcbf9b5cd44747aadce2ce6069cfdc3d671dae",
+        "f9f8ae590cf93d957c9e873e9a1bc62d9640dc8fc39c14902d49a1c80239b6c5b7fd91d05878cbf5ffc7db2569f47c43",
+        "d6c0d27c438abff276e87364deb8858a37e5a62c446af95d8b786eaf0b5fcf78d98b41496794f8dcaac4eef34b2acfb9",
+        "4c7e8c32a9e9866a8fa0b6f2a06f00a1ccde569f97eec05c803ba7500acc96691d8898d73d8e6a47b8f43c3d5de74458",
+        "d20eda61474c426359677001fbd75a74d7d5db6cb4feb83122f133206203e4e2d293f838bf8c8b3a29acb321315100b8",
+        "7e80e0edb272ee80fda944e3fb6084ed4d7f7c7d21c69d9da43d31a90b70693f9b0cc3eac74c11ab8ff655905688916c",
+        "fa4ef0bd04135f2e50b7c689a21d04e8e981e74c6058188b9b1f9dfc3eec6838e9ffbcf22ce738d8a177c19318dffef0",
+        "90cee67e12de1a3e2a39f61247547ba5257489cbc11d7d91ed34617fcc42f7a9da2e3cf31a94a210a1018143173913c3",
+        "8f60e62b24bf0d7518f38b5bab3e6a1f8aeb35e31d6442c8abb5178efc892d2e787d79c6ad9e2fc271792983fa9955ac",
+        "4d1d84a36c024071bc6e431b625519d556af38185601f70e29035ea6a09c8b676c9d88cf7e05e0f17098b584c4168735",
+        "940263f940033a220f40be4c85344128b14beb9e75696db37014107801a59b13e89cd9d2258c169d523be6d31552c44c",
+        "82ff4bb18ec9f099f3bf0e5b1bb2ba9a87d7e26f98d294927b600b5529c47e04d98956677cbcee8fa2b60f49776d8b8c",
+        "367465b7c626da53700684fb6c918ead0eab8360e4f60edd25b4f43816a75ecf70f909301825b512469f8389d7940231",
+        "1d8aecb7b3ef8599e79485a4388d87744d899f7c47ee644361e17040a7958c8911be6f463ab6a9b2afacd688ec55ef51",
+        "7b38f1339efc54487232798bb25522ff4572ff68567fe830f92f7b8113efce3e98c3fffbaedce4fd8b50e41da97c0c08",
+        "e423a72689cc68e68f752a5e3a9003e64e35c957ca2e1c48bb6f64b05f56b70b575ad2f278d57850a7ad568c24a4d32a",
+        "3d74b29f03dc125488bc7c637da582357f40b0a52d16b3b40bb2c2315d03360bc24209e20972c200566bcf3bbe5c5b0a",
+        "edd83132a8a4d5b4242ba370b6d67d9b67eb01052d132c7866b9cb502e44796d9d356e4e3cb47cc527322cd24976fe7c",
+        "9257a2864151a38e568ef7a79f10d6ef27cc04ce382347a2488b1f404fdbf407fe1ca1c9d0d5649e34800e25e18951c9",
+        "8cae9f43555eef65fee1ea8f15828807366c3b612cd5753bf9fb8fced08855f742cddd6f765f74254f03186683d646e6",
+        "f09ac2805586c7cf11998357cafc5df3f285329366f475130c928b2dceba4aa383758e7a9d20705c4bb9db619e2992f6",
+        "08a1ba65db254bb389468741d0502e2588aeb54390ac600c19af5c8e61383fc1bebe0029e4474051e4ef908828db9cca",
+        "13277ef65db3fd47ccc2179126aaefb627719f421e20",
+    );
+
+    /// The BOLT #4 test route, whose nodes have the secrets 0x41.., 0x42.., up to 0x45..
+    fn route_secrets() -> Vec<SecretKey> {
+        (0x41..=0x45).map(|b| SecretKey::from_slice(&[b; 32]).unwrap()).collect()
+    }
+
+    fn route() -> Vec<PublicKey> {
+        [
+            "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+            "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c",
+            "027f31ebc5462c1fdce1b737ecff52d37d75dea43ce11c74d25aa297165faa2007",
+            "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+            "02edabbd16b41c8371b92ef2f04c1185b4f03b6dcd52ba9b78d9d7c89c8f221145",
+        ].iter().map(|k| PublicKey::from_slice(&hex::decode(k).unwrap()).unwrap()).collect()
+    }
+
+    fn session_key() -> SecretKey {
+        SecretKey::from_slice(&[0x41; 32]).unwrap()
+    }
+
+    #[test]
+    fn route_node_ids() {
+        let secp_ctx = Secp256k1::new();
+        let node_ids: Vec<PublicKey> = route_secrets().iter().map(|s| PublicKey::from_secret_key(&secp_ctx, s)).collect();
+        assert_eq!(node_ids, route());
+    }
+
+    /// The per-hop keys of the BOLT #4 test route with session key 0x41..
+    #[test]
+    fn hop_key_vectors() {
+        let test_vectors = [
+            (
+                "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+                "53eb63ea8a3fec3b3cd433b85cd62a4b145e1dda09391b348c4e1cd36a03ea66",
+                "2ec2e5da605776054187180343287683aa6a51b4b1c04d6dd49c45d8cffb3c36",
+                "ce496ec94def95aadd4bec15cdb41a740c9f2b62347c4917325fcc6fb0453986",
+                "b57061dc6d0a2b9f261ac410c8b26d64ac5506cbba30267a649c28c179400eba",
+            ),
+            (
+                "028f9438bfbf7feac2e108d677e3a82da596be706cc1cf342b75c7b7e22bf4e6e2",
+                "a6519e98832a0b179f62123b3567c106db99ee37bef036e783263602f3488fae",
+                "bf66c28bc22e598cfd574a1931a2bafbca09163df2261e6d0056b2610dab938f",
+                "450ffcabc6449094918ebe13d4f03e433d20a3d28a768203337bc40b6e4b2c59",
+                "05ed2b4a3fb023c2ff5dd6ed4b9b6ea7383f5cfe9d59c11d121ec2c81ca2eea9",
+            ),
+            (
+                "03bfd8225241ea71cd0843db7709f4c222f62ff2d4516fd38b39914ab6b83e0da0",
+                "3a6b412548762f0dbccce5c7ae7bb8147d1caf9b5471c34120b30bc9c04891cc",
+                "a1f2dadd184eb1627049673f18c6325814384facdee5bfd935d9cb031a1698a5",
+                "11bf5c4f960239cb37833936aa3d02cea82c0f39fd35f566109c41f9eac8deea",
+                "caafe2820fa00eb2eeb78695ae452eba38f5a53ed6d53518c5c6edf76f3f5b78",
+            ),
+            (
+                "031dde6926381289671300239ea8e57ffaf9bebd05b9a5b95beaf07af05cd43595",
+                "21e13c2d7cfe7e18836df50872466117a295783ab8aab0e7ecc8c725503ad02d",
+                "7cfe0b699f35525029ae0fa437c69d0f20f7ed4e3916133f9cacbb13c82ff262",
+                "cbe784ab745c13ff5cffc2fbe3e84424aa0fd669b8ead4ee562901a4a4e89e9e",
+                "5052aa1b3d9f0655a0932e50d42f0c9ba0705142c25d225515c45f47c0036ee9",
+            ),
+            (
+                "03a214ebd875aab6ddfd77f22c5e7311d7f77f17a169e599f157bbcdae8bf071f4",
+                "b5756b9b542727dbafc6765a49488b023a725d631af688fc031217e90770c328",
+                "c96e00dddaf57e7edcd4fb5954be5b65b09f17cb6d20651b4e90315be5779205",
+                "034e18b8cc718e8af6339106e706c52d8df89e2b1f7e9142d996acf88df8799b",
+                "8e45e5c61c2b24cb6382444db6698727afb063adecd72aada233d4bf273d975a",
+            ),
+        ];
+
+        let keys = hop_keys(&session_key(), &route()).unwrap();
+        for (key, (ephemeral_key, shared_secret, blinding_factor, rho, mu)) in keys.iter().zip(test_vectors) {
+            assert_eq!(hex::encode(key.ephemeral_key.serialize()), ephemeral_key);
+            assert_eq!(hex::encode(key.shared_secret), shared_secret);
+            assert_eq!(hex::encode(key.blinding_factor), blinding_factor);
+            assert_eq!(hex::encode(generate_key(b"rho", &key.shared_secret)), rho);
+            assert_eq!(hex::encode(generate_key(b"mu", &key.shared_secret)), mu);
+        }
+    }
+
+    /// The length-prefixed payloads of the BOLT #4 test vector
+    fn spec_payloads() -> Vec<Vec<u8>> {
+        [
+            "1202023a98040205dc06080000000000000001".to_string(),
+            [
+                "52020236b00402057806080000000000000002fd02013c",
+                &"0102030405060708090a0b0c0d0e0f".repeat(4),
+            ].concat(),
+            "12020230d4040204e206080000000000000003".to_string(),
+            "1202022710040203e806080000000000000004".to_string(),
+            [
+                "fd011002022710040203e8082224a33562c54507a9334e79f0dc4f17d407e6d7c61f0e2f3d0d38599502f617042710",
+                "fd012de0", &"2a".repeat(224),
+            ].concat(),
+        ].iter().map(|p| hex::decode(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn spec_onion_vector() {
+        let payloads: Vec<Vec<u8>> = spec_payloads().iter().map(|p| {
+            let mut reader = &p[..];
+            let len: BigSize = Readable::read(&mut reader).unwrap();
+            assert_eq!(len.0 as usize, reader.len());
+            reader.to_vec()
+        }).collect();
+        let hops: Vec<OnionHop> = route().into_iter().zip(&payloads)
+            .map(|(node_id, payload)| OnionHop { node_id, payload: payload.clone() })
+            .collect();
+        let (packet, keys) = create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(hex::encode(packet.to_bytes()), SPEC_PACKET);
+
+        let mut packet = OnionPacket::from_bytes(&hex::decode(SPEC_PACKET).unwrap().try_into().unwrap());
+        for (i, secret) in route_secrets().iter().enumerate() {
+            assert_eq!(packet.public_key, keys[i].ephemeral_key.serialize());
+            let peeled = peel_onion(secret, &packet, &ASSOCIATED_DATA).unwrap();
+            assert_eq!(peeled.payload, payloads[i]);
+            let payload = HopPayload::from_peeled(&peeled).unwrap();
+            match peeled.next {
+                Some(next) => {
+                    assert_eq!(payload.short_channel_id, Some(ShortChannelId(i as u64 + 1)));
+                    packet = next;
+                }
+                None => {
+                    assert_eq!(i, 4);
+                    assert_eq!(payload.amt_to_forward, Some(10000));
+                    assert_eq!(payload.payment_data.unwrap().total_msat, 10000);
+                    assert_eq!(payload.unknown_records, vec![(301, vec![0x2a; 224])]);
+                }
+            }
+        }
+    }
+
+    fn onion_hops() -> Vec<OnionHop> {
+        route().into_iter().enumerate()
+            .map(|(i, node_id)| OnionHop { node_id, payload: vec![i as u8; 10 + 50 * i] })
+            .collect()
+    }
+
+    #[test]
+    fn peel_every_hop() {
+        let hops = onion_hops();
+        let (mut packet, keys) = create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(OnionPacket::read(&mut &packet.encode().unwrap()[..]).unwrap(), packet);
+
+        for (i, secret) in route_secrets().iter().enumerate() {
+            assert_eq!(packet.public_key, keys[i].ephemeral_key.serialize());
+            let peeled = peel_onion(secret, &packet, &ASSOCIATED_DATA).unwrap();
+            assert_eq!(peeled.payload, hops[i].payload);
+            assert_eq!(peeled.shared_secret, keys[i].shared_secret);
+            match peeled.next {
+                Some(next) => packet = next,
+                None => assert_eq!(i, hops.len() - 1),
+            }
+        }
+    }
+
+    #[test]
+    fn single_hop_filling_the_packet() {
+        // A 1265-byte payload takes 3 bytes of length and the 32-byte HMAC
+        let hops = vec![OnionHop { node_id: route()[0], payload: vec![7; 1265] }];
+        let (packet, _) = create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap();
+        let peeled = peel_onion(&route_secrets()[0], &packet, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(peeled.payload, hops[0].payload);
+        assert_eq!(peeled.next, None);
+
+        let hops = vec![OnionHop { node_id: route()[0], payload: vec![7; 1266] }];
+        assert_eq!(create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap_err(), OnionError::RouteTooLong);
+        assert_eq!(create_onion_packet(&session_key(), &[], &ASSOCIATED_DATA).unwrap_err(), OnionError::RouteTooLong);
+    }
+
+    #[test]
+    fn peel_failures() {
+        let (packet, _) = create_onion_packet(&session_key(), &onion_hops(), &ASSOCIATED_DATA).unwrap();
+        let secret = &route_secrets()[0];
+
+        assert_eq!(peel_onion(secret, &packet, &[0x43; 32]).unwrap_err(), OnionError::InvalidHmac);
+        assert_eq!(peel_onion(&route_secrets()[1], &packet, &ASSOCIATED_DATA).unwrap_err(), OnionError::InvalidHmac);
+
+        let mut tampered = packet.clone();
+        tampered.hop_payloads[HOP_PAYLOADS_LEN - 1] ^= 1;
+        assert_eq!(peel_onion(secret, &tampered, &ASSOCIATED_DATA).unwrap_err(), OnionError::InvalidHmac);
+
+        let mut tampered = packet.clone();
+        tampered.version = 1;
+        assert_eq!(peel_onion(secret, &tampered, &ASSOCIATED_DATA).unwrap_err(), OnionError::InvalidVersion(1));
+
+        let mut tampered = packet;
+        tampered.public_key[0] = 0x04;
+        assert_eq!(peel_onion(secret, &tampered, &ASSOCIATED_DATA).unwrap_err(), OnionError::InvalidKey);
+    }
+
+    #[test]
+    fn hop_payload_encoding() {
+        let forward = HopPayload::forward(ShortChannelId(0x226), 1000, 144);
+        let encoded = concat!("02", "02", "03e8", "04", "01", "90", "06", "08", "0000000000000226");
+        assert_eq!(hex::encode(forward.encode().unwrap()), encoded);
+        assert_eq!(HopPayload::read(&mut &hex::decode(encoded).unwrap()[..]).unwrap(), forward);
+
+        let mut final_hop = HopPayload::final_hop(1000, 144, Some(PaymentData {
+            payment_secret: PaymentSecret([0x11; 32]),
+            total_msat: 3000,
+        }));
+        final_hop.payment_metadata = Some(vec![0xab]);
+        final_hop.unknown_records.push((65537, vec![0x01, 0x02]));
+        let encoded = [
+            "02", "02", "03e8", "04", "01", "90",
+            "08", "22", &"11".repeat(32), "0bb8",
+            "10", "01", "ab",
+            "fe00010001", "02", "0102",
+        ].concat();
+        assert_eq!(hex::encode(final_hop.encode().unwrap()), encoded);
+        assert_eq!(HopPayload::read(&mut &hex::decode(&encoded).unwrap()[..]).unwrap(), final_hop);
+
+        let read = |vector: &str| HopPayload::read(&mut &hex::decode(vector).unwrap()[..]);
+        assert_eq!(read(concat!("02", "02", "03e8", "0e", "00")).unwrap_err(), DecodeError::UnknownEvenType(14));
+        assert_eq!(
+            read(&["08", "22", &"11".repeat(32), "000b"].concat()).unwrap_err(),
+            DecodeError::InvalidData.in_field("payment_data")
+        );
+    }
+
+    #[test]
+    fn hop_payload_checks() {
+        let forward = HopPayload::forward(ShortChannelId(0x226), 1000, 144);
+        assert_eq!(forward.check(false), Ok(()));
+        assert_eq!(forward.check(true), Ok(()));
+
+        let payment_data = PaymentData { payment_secret: PaymentSecret([0x11; 32]), total_msat: 1000 };
+        let final_hop = HopPayload::final_hop(1000, 144, Some(payment_data));
+        assert_eq!(final_hop.check(true), Ok(()));
+        assert_eq!(final_hop.check(false), Err(PayloadError::MissingRecord(6)));
+
+        let with_payment_data = HopPayload { payment_data: Some(payment_data), ..forward.clone() };
+        assert_eq!(with_payment_data.check(false), Err(PayloadError::UnexpectedRecord(8)));
+        let with_metadata = HopPayload { payment_metadata: Some(vec![]), ..forward.clone() };
+        assert_eq!(with_metadata.check(false), Err(PayloadError::UnexpectedRecord(16)));
+        let no_amount = HopPayload { amt_to_forward: None, ..forward.clone() };
+        assert_eq!(no_amount.check(true), Err(PayloadError::MissingRecord(2)));
+        let no_cltv = HopPayload { outgoing_cltv_value: None, ..forward.clone() };
+        assert_eq!(no_cltv.check(true), Err(PayloadError::MissingRecord(4)));
+        let with_path_key = HopPayload { current_path_key: Some(route()[0]), ..forward };
+        assert_eq!(with_path_key.check(false), Err(PayloadError::UnexpectedRecord(12)));
+    }
+
+    #[test]
+    fn blinded_hop_payload_checks() {
+        let forward = HopPayload {
+            encrypted_recipient_data: Some(vec![0x01; 20]),
+            current_path_key: Some(route()[0]),
+            ..Default::default()
+        };
+        let encoded = ["0a", "14", &"01".repeat(20), "0c", "21", &hex::encode(route()[0].serialize())].concat();
+        assert_eq!(hex::encode(forward.encode().unwrap()), encoded);
+        assert_eq!(HopPayload::read(&mut &hex::decode(&encoded).unwrap()[..]).unwrap(), forward);
+        assert_eq!(forward.check(false), Ok(()));
+        assert_eq!(forward.check(true), Err(PayloadError::MissingRecord(2)));
+
+        // Forwarding instructions are in the encrypted data instead
+        let with_amount = HopPayload { amt_to_forward: Some(1000), ..forward.clone() };
+        assert_eq!(with_amount.check(false), Err(PayloadError::UnexpectedRecord(2)));
+        let with_scid = HopPayload { short_channel_id: Some(ShortChannelId(0x226)), ..forward.clone() };
+        assert_eq!(with_scid.check(false), Err(PayloadError::UnexpectedRecord(6)));
+
+        let final_hop = HopPayload {
+            amt_to_forward: Some(1000),
+            outgoing_cltv_value: Some(144),
+            total_amount_msat: Some(3000),
+            current_path_key: None,
+            ..forward.clone()
+        };
+        assert_eq!(final_hop.check(true), Ok(()));
+        assert_eq!(final_hop.check(false), Err(PayloadError::UnexpectedRecord(2)));
+        let no_total = HopPayload { total_amount_msat: None, ..final_hop.clone() };
+        assert_eq!(no_total.check(true), Err(PayloadError::MissingRecord(18)));
+        let with_metadata = HopPayload { payment_metadata: Some(vec![]), ..final_hop.clone() };
+        assert_eq!(with_metadata.check(true), Err(PayloadError::UnexpectedRecord(16)));
+        let unblinded = HopPayload { encrypted_recipient_data: None, ..final_hop };
+        assert_eq!(unblinded.check_blinded(true), Err(PayloadError::MissingRecord(10)));
+    }
+
+    #[test]
+    fn hop_payloads_in_onion() {
+        let payloads = [
+            HopPayload::forward(ShortChannelId(0x226), 1000, 144),
+            HopPayload::final_hop(1000, 144, None),
+        ];
+        let hops: Vec<OnionHop> = route().into_iter().zip(&payloads)
+            .map(|(node_id, payload)| OnionHop { node_id, payload: payload.encode().unwrap() })
+            .collect();
+        let (packet, _) = create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap();
+
+        let peeled = peel_onion(&route_secrets()[0], &packet, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(HopPayload::from_peeled(&peeled).unwrap(), payloads[0]);
+        let peeled = peel_onion(&route_secrets()[1], peeled.next.as_ref().unwrap(), &ASSOCIATED_DATA).unwrap();
+        assert_eq!(HopPayload::from_peeled(&peeled).unwrap(), payloads[1]);
+
+        // A final hop payload given to a forwarding hop
+        let hops = [
+            OnionHop { node_id: route()[0], payload: payloads[1].encode().unwrap() },
+            OnionHop { node_id: route()[1], payload: payloads[1].encode().unwrap() },
+        ];
+        let (packet, _) = create_onion_packet(&session_key(), &hops, &ASSOCIATED_DATA).unwrap();
+        let peeled = peel_onion(&route_secrets()[0], &packet, &ASSOCIATED_DATA).unwrap();
+        assert_eq!(HopPayload::from_peeled(&peeled).unwrap_err(), PayloadError::MissingRecord(6));
+    }
+}
@@ -0,0 +1,129 @@
+use core::fmt;
+use alloc::collections::VecDeque;
+
+use crate::msgs::{Ping, Pong};
+
+/// A ping with `num_pong_bytes` at or above this value asks for no pong at all.
+pub const PONG_IGNORE_THRESHOLD: u16 = 65532;
+
+/// Builds the reply to a received ping, if one is due.
+///
+/// A node receiving a ping with `num_pong_bytes` less than 65532 MUST respond with a pong whose
+/// `byteslen` is `num_pong_bytes`, and MUST ignore the ping otherwise.
+pub fn handle_ping(ping: &Ping) -> Option<Pong> {
+    if ping.num_pong_bytes >= PONG_IGNORE_THRESHOLD {
+        None
+    } else {
+        Some(Pong::new(ping.num_pong_bytes))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PongError {
+    /// We received a pong without having sent a ping which asked for one
+    Unsolicited,
+    /// The pong doesn't carry the number of bytes the outstanding ping asked for
+    LengthMismatch { expected: u16, got: u16 },
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PongError {}
+
+impl fmt::Display for PongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PongError::Unsolicited => write!(f, "unsolicited pong"),
+            PongError::LengthMismatch { expected, got } => {
+                write!(f, "pong has {} bytes, expected {}", got, expected)
+            }
+        }
+    }
+}
+
+/// Tracks the pings we sent which still await a pong. Pongs are matched against outstanding
+/// pings in the order the pings were sent.
+#[derive(Debug, Default)]
+pub struct PingTracker {
+    outstanding: VecDeque<u16>,
+}
+
+impl PingTracker {
+    pub fn new() -> Self {
+        PingTracker { outstanding: VecDeque::new() }
+    }
+
+    /// Builds a ping and records the pong length it asks for. Pings asking for no pong are not
+    /// tracked.
+    pub fn send_ping(&mut self, num_pong_bytes: u16, bytes_len: u16) -> Ping {
+        if num_pong_bytes < PONG_IGNORE_THRESHOLD {
+            self.outstanding.push_back(num_pong_bytes);
+        }
+        Ping::new(num_pong_bytes, bytes_len)
+    }
+
+    /// Whether a pong is still expected. Keepalive logic may close the connection if this stays
+    /// true for too long.
+    pub fn awaiting_pong(&self) -> bool {
+        !self.outstanding.is_empty()
+    }
+
+    /// Checks a received pong against the oldest outstanding ping. On error the node MAY close
+    /// the connection.
+    pub fn handle_pong(&mut self, pong: &Pong) -> Result<(), PongError> {
+        let expected = self.outstanding.pop_front().ok_or(PongError::Unsolicited)?;
+        if pong.bytes_len != expected {
+            return Err(PongError::LengthMismatch { expected, got: pong.bytes_len })
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::msgs::{Ping, Pong};
+    use super::{handle_ping, PingTracker, PongError};
+
+    #[test]
+    fn responds_with_requested_bytes() {
+        let pong = handle_ping(&Ping::new(4, 10)).expect("pong");
+        assert_eq!(pong.bytes_len, 4);
+        assert_eq!(pong.ignored, vec![0; 4]);
+
+        assert_eq!(handle_ping(&Ping::new(0, 0)), Some(Pong::new(0)));
+        assert_eq!(handle_ping(&Ping::new(65531, 0)).map(|p| p.ignored.len()), Some(65531));
+    }
+
+    #[test]
+    fn no_pong_above_threshold() {
+        assert_eq!(handle_ping(&Ping::new(65532, 0)), None);
+        assert_eq!(handle_ping(&Ping::new(65535, 4)), None);
+    }
+
+    #[test]
+    fn ping_padding_is_zeroed() {
+        let ping = Ping::new(2, 8);
+        assert_eq!(ping.ignored, vec![0; 8]);
+    }
+
+    #[test]
+    fn pong_must_match_outstanding_ping() {
+        let mut tracker = PingTracker::new();
+        assert_eq!(tracker.handle_pong(&Pong::new(0)), Err(PongError::Unsolicited));
+
+        tracker.send_ping(4, 0);
+        tracker.send_ping(8, 0);
+        assert!(tracker.awaiting_pong());
+        assert_eq!(tracker.handle_pong(&Pong::new(4)), Ok(()));
+        assert_eq!(tracker.handle_pong(&Pong::new(9)), Err(PongError::LengthMismatch { expected: 8, got: 9 }));
+        assert!(!tracker.awaiting_pong());
+    }
+
+    #[test]
+    fn pings_without_pong_are_not_tracked() {
+        let mut tracker = PingTracker::new();
+        let ping = tracker.send_ping(65532, 16);
+        assert_eq!(ping.ignored.len(), 16);
+        assert!(!tracker.awaiting_pong());
+        assert_eq!(tracker.handle_pong(&Pong::new(65532)), Err(PongError::Unsolicited));
+    }
+}
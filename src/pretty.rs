@@ -0,0 +1,125 @@
+//! Human-readable descriptions of messages and their fields, shown with the alternate `{:#}`
+//! format, e.g. `ping num_pong_bytes=4 ignored=0000` or `tlv2 scid=0x0x550`.
+//!
+//! Plain `{}` keeps showing the hex encoding.
+
+use core::fmt;
+use alloc::vec::Vec;
+
+use secp256k1::{PublicKey, ecdsa::Signature};
+
+use crate::bigsize::BigSize;
+use crate::ser::{TU16, TU32, TU64};
+use crate::types::{Script, Txid};
+
+/// Writes a value for humans. Values made up of several fields list them as `name=value`
+/// separated by spaces, lists are in brackets and bytes are hex.
+pub trait Describe {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// Describes values with their `Display` implementation.
+macro_rules! impl_describe_display {
+    ($($ty: ty),* $(,)?) => {
+        $(
+            impl $crate::pretty::Describe for $ty {
+                fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", self)
+                }
+            }
+        )*
+    }
+}
+pub(crate) use impl_describe_display;
+
+/// Describes a struct as its fields, e.g. `min_fee_satoshis=1 max_fee_satoshis=2`.
+macro_rules! impl_describe_fields {
+    ($st: ident, { $first: ident $(, $field: ident)* $(,)? }) => {
+        impl $crate::pretty::Describe for $st {
+            fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}=", stringify!($first))?;
+                $crate::pretty::Describe::describe(&self.$first, f)?;
+                $(
+                    write!(f, " {}=", stringify!($field))?;
+                    $crate::pretty::Describe::describe(&self.$field, f)?;
+                )*
+                Ok(())
+            }
+        }
+    }
+}
+pub(crate) use impl_describe_fields;
+
+impl_describe_display!(u8, u16, u32, u64, PublicKey, Txid);
+
+impl Describe for BigSize {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+macro_rules! impl_describe_truncated {
+    ($($ty: ty),*) => {
+        $(
+            impl Describe for $ty {
+                fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", self.0)
+                }
+            }
+        )*
+    }
+}
+
+impl_describe_truncated!(TU16, TU32, TU64);
+
+/// Signatures are shown in their 64-byte compact encoding, as on the wire.
+impl Describe for Signature {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.serialize_compact().describe(f)
+    }
+}
+
+impl Describe for [u8] {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Describe for [u8; N] {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self[..].describe(f)
+    }
+}
+
+impl Describe for Vec<u8> {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self[..].describe(f)
+    }
+}
+
+impl Describe for Script {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes().describe(f)
+    }
+}
+
+/// Lists of values other than bytes, e.g. `[a, b]`.
+pub(crate) fn describe_list<T: Describe>(items: &[T], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("[")?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        item.describe(f)?;
+    }
+    f.write_str("]")
+}
+
+impl Describe for Vec<Signature> {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        describe_list(self, f)
+    }
+}
@@ -1,4 +1,11 @@
-use std::{io::{self, Write, Read}, fmt};
+use core::fmt;
+use alloc::{boxed::Box, vec::Vec};
+
+use bitcoin_hashes::Hash;
+use secp256k1::{PublicKey, ecdsa::Signature};
+
+use crate::io::{self, Write, Read};
+use crate::types::Txid;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum DecodeError {
@@ -6,17 +13,80 @@ pub enum DecodeError {
     ShortRead,
     InvalidData,
     UnknownRequiredFeature,
+    /// A BigSize which could have been encoded in fewer bytes
+    NonCanonicalBigSize,
+    /// TLV records must appear in strictly increasing type order
+    TlvOutOfOrder { prev: u64, got: u64 },
+    /// A TLV record or message of an even type we don't understand
+    UnknownEvenType(u64),
+    /// A TLV stream without a record of this type, which its namespace requires
+    MissingRequiredRecord(u64),
+    InvalidPublicKey,
+    InvalidSignature,
+    /// A chain_hash of a chain the node doesn't operate on
+    UnknownChain,
+    /// A message longer than the 65535 bytes allowed
+    MessageTooLong,
+    /// A value didn't span the number of bytes given by its length
+    LengthMismatch { expected: usize, got: usize },
+    /// The error happened while decoding the field at `path`, outermost field first
+    InField { path: Vec<&'static str>, error: Box<DecodeError> },
+}
+
+impl DecodeError {
+    /// Records that the error happened while decoding `field`. Called from the innermost field
+    /// outwards, this builds up the path to the field which failed.
+    pub fn in_field(self, field: &'static str) -> Self {
+        match self {
+            DecodeError::InField { mut path, error } => {
+                path.insert(0, field);
+                DecodeError::InField { path, error }
+            },
+            error => DecodeError::InField { path: vec![field], error: Box::new(error) },
+        }
+    }
+
+    /// The error without the field it happened in.
+    pub fn root(&self) -> &DecodeError {
+        match self {
+            DecodeError::InField { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// The path to the field which failed, empty if unknown.
+    pub fn path(&self) -> &[&'static str] {
+        match self {
+            DecodeError::InField { path, .. } => path,
+            _ => &[],
+        }
+    }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for DecodeError {}
 
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             DecodeError::Io(e) => write!(f, "{:?}", e),
             DecodeError::ShortRead => write!(f, "short read"),
             DecodeError::InvalidData => write!(f, "invalid data"),
             DecodeError::UnknownRequiredFeature => write!(f, "unknown required feature"),
+            DecodeError::NonCanonicalBigSize => write!(f, "non-canonical bigsize"),
+            DecodeError::TlvOutOfOrder { prev, got } => {
+                write!(f, "tlv type {} after type {}", got, prev)
+            },
+            DecodeError::UnknownEvenType(t) => write!(f, "unknown even type {}", t),
+            DecodeError::MissingRequiredRecord(t) => write!(f, "missing required type {}", t),
+            DecodeError::InvalidPublicKey => write!(f, "invalid public key"),
+            DecodeError::InvalidSignature => write!(f, "invalid signature"),
+            DecodeError::UnknownChain => write!(f, "unknown chain"),
+            DecodeError::MessageTooLong => write!(f, "message too long"),
+            DecodeError::LengthMismatch { expected, got } => {
+                write!(f, "expected {} bytes, got {}", expected, got)
+            },
+            DecodeError::InField { path, error } => write!(f, "{}: {}", path.join("."), error),
         }
     }
 }
@@ -26,10 +96,12 @@ pub trait Writeable {
     fn write<W: io::Write>(&self, writer: &mut W) -> Result<usize, io::Error>;
     fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error>;
 
-    fn encode(&self) -> Vec<u8> {
+    /// Encodes into a new buffer. Writing to a `Vec` can't fail, so an error means the object
+    /// itself can't be encoded, e.g. a TLV stream whose records are out of order.
+    fn encode(&self) -> Result<Vec<u8>, io::Error> {
         let mut msg = Vec::new();
-        self.write(&mut msg).unwrap();
-        msg
+        self.write(&mut msg)?;
+        Ok(msg)
     }
 }
 
@@ -38,14 +110,12 @@ macro_rules! impl_writeable_int_be {
         impl Writeable for $ty {
             fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
                 let bytes = self.to_be_bytes();
-                match writer.write(&bytes) {
-                    Ok(n) => Ok(n),
-                    Err(e) => panic!("{}", e)
-                }
+                writer.write_all(&bytes)?;
+                Ok(bytes.len())
             }
 
-            fn write_fmt<W: fmt::Write>(&self, _writer: &mut W) -> Result<(), fmt::Error> {
-                todo!()
+            fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+                write!(writer, "{:01$x}", self, 2 * core::mem::size_of::<$ty>())
             }
         }
 	};
@@ -61,6 +131,11 @@ pub trait Readable where Self: Sized {
 	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError>;
 }
 
+/// Objects whose decoding depends on some context, such as the chains the node operates on
+pub trait ReadableArgs<A> where Self: Sized {
+	fn read<R: Read>(reader: &mut R, args: A) -> Result<Self, DecodeError>;
+}
+
 macro_rules! impl_readable_int_be {
 	($ty: ty, $len: expr) => {
         impl Readable for $ty {
@@ -83,14 +158,158 @@ pub trait FixedLengthReadable where Self: Sized {
 	fn read<R: Read>(reader: &mut R, length: usize) -> Result<Self, DecodeError>;
 }
 
+/// The length usually comes from the peer, so the buffer only grows as bytes actually arrive
+/// instead of being allocated upfront.
 impl FixedLengthReadable for Vec<u8> {
 	fn read<R: Read>(reader: &mut R, length: usize) -> Result<Self, DecodeError> {
-        let mut bytes = vec![0; length];
+        let mut bytes = Vec::new();
+        reader.take(length as u64).read_to_end(&mut bytes).map_err(|e| DecodeError::Io(e.kind()))?;
+        if bytes.len() < length {
+            return Err(DecodeError::ShortRead)
+        }
+        Ok(bytes)
+    }
+}
+
+impl<const N: usize> Writeable for [u8; N] {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        writer.write_all(self)?;
+        Ok(N)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for byte in self {
+            write!(writer, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Readable for [u8; N] {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let mut bytes = [0; N];
         reader.read_exact(&mut bytes).map_err(|_| DecodeError::ShortRead)?;
         Ok(bytes)
     }
 }
 
+impl Writeable for PublicKey {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.serialize().write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        write!(writer, "{:02x}", self)
+    }
+}
+
+impl Readable for PublicKey {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let bytes: [u8; 33] = Readable::read(reader)?;
+        PublicKey::from_slice(&bytes).map_err(|_| DecodeError::InvalidPublicKey)
+    }
+}
+
+/// Signatures are encoded in the 64-byte compact format
+impl Writeable for Signature {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.serialize_compact().write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.serialize_compact().write_fmt(writer)
+    }
+}
+
+impl Readable for Signature {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let bytes: [u8; 64] = Readable::read(reader)?;
+        Signature::from_compact(&bytes).map_err(|_| DecodeError::InvalidSignature)
+    }
+}
+
+impl Writeable for Txid {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.into_inner().write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.into_inner().write_fmt(writer)
+    }
+}
+
+impl Readable for Txid {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let bytes: [u8; 32] = Readable::read(reader)?;
+        Ok(Txid::from_inner(bytes))
+    }
+}
+
+/// A `tu16`: an unsigned 16-bit integer with its leading zero bytes omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct TU16(pub u16);
+
+/// A `tu32`: an unsigned 32-bit integer with its leading zero bytes omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct TU32(pub u32);
+
+/// A `tu64`: an unsigned 64-bit integer with its leading zero bytes omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct TU64(pub u64);
+
+/// Truncated integers have no length of their own: they are read up to the end of the reader,
+/// which must be bounded, e.g. to the value of a TLV record. The encoding must be minimal, so a
+/// leading zero byte or more bytes than the integer can hold is invalid.
+macro_rules! impl_truncated_int {
+    ($st: ident, $ty: ty, $len: expr) => {
+        impl Readable for $st {
+            fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                let mut bytes = Vec::with_capacity($len);
+                reader.take($len + 1).read_to_end(&mut bytes).map_err(|e| DecodeError::Io(e.kind()))?;
+                if bytes.len() > $len || bytes.first() == Some(&0) {
+                    return Err(DecodeError::InvalidData)
+                }
+                Ok($st(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as $ty)))
+            }
+        }
+
+        impl Writeable for $st {
+            fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                let bytes = self.0.to_be_bytes();
+                let skip = (self.0.leading_zeros() / 8) as usize;
+                writer.write_all(&bytes[skip..])?;
+                Ok($len - skip)
+            }
+
+            fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+                for byte in self.encode().map_err(|_| fmt::Error)? {
+                    write!(writer, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl From<$ty> for $st {
+            fn from(v: $ty) -> Self {
+                $st(v)
+            }
+        }
+
+        impl From<$st> for $ty {
+            fn from(v: $st) -> Self {
+                v.0
+            }
+        }
+    }
+}
+
+impl_truncated_int!(TU16, u16, 2);
+impl_truncated_int!(TU32, u32, 4);
+impl_truncated_int!(TU64, u64, 8);
+
 /// Picked up from rust-lightning
 /// A Read which tracks whether any bytes have been read at all. This allows us to distinguish
 /// between "EOF reached before we started" and "EOF reached mid-read".
@@ -116,3 +335,125 @@ impl<R: Read> Read for ReadTrackingReader<R> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::{String, ToString}, vec::Vec};
+
+    use crate::io::{self, Write};
+
+    use super::{Readable, Writeable, FixedLengthReadable, DecodeError, TU16, TU32, TU64};
+
+    /// Accepts at most one byte per write, then fails once `capacity` bytes have been written.
+    struct TrickleWriter {
+        written: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            if self.written.len() == self.capacity {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "closed"))
+            }
+            self.written.push(buf[0]);
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn partial_writes_and_errors() {
+        let mut writer = TrickleWriter { written: Vec::new(), capacity: 8 };
+        assert_eq!(0x0102030405060708u64.write(&mut writer).unwrap(), 8);
+        assert_eq!(writer.written, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let err = 1u16.write(&mut writer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn fixed_length_reads_dont_trust_the_length() {
+        let res: Result<Vec<u8>, DecodeError> = FixedLengthReadable::read(&mut &[0; 4][..], usize::MAX);
+        assert_eq!(res.unwrap_err(), DecodeError::ShortRead);
+
+        let res: Vec<u8> = FixedLengthReadable::read(&mut &[1, 2, 3, 4][..], 3).unwrap();
+        assert_eq!(res, [1, 2, 3]);
+    }
+
+    #[test]
+    fn integers_write_fmt() {
+        let mut s = String::new();
+        1u8.write_fmt(&mut s).unwrap();
+        0x0102u16.write_fmt(&mut s).unwrap();
+        3u32.write_fmt(&mut s).unwrap();
+        u64::MAX.write_fmt(&mut s).unwrap();
+        assert_eq!(s, concat!("01", "0102", "00000003", "ffffffffffffffff"));
+    }
+
+    #[test]
+    fn truncated_integers_are_minimal() {
+        let test_vectors = [
+            (0, ""),
+            (1, "01"),
+            (255, "ff"),
+            (256, "0100"),
+            (65535, "ffff"),
+            (65536, "010000"),
+            (u32::MAX as u64, "ffffffff"),
+            (1 << 56, "0100000000000000"),
+            (u64::MAX, "ffffffffffffffff"),
+        ];
+
+        for (value, vector) in test_vectors {
+            assert_eq!(hex::encode(TU64(value).encode().unwrap()), vector);
+            let decoded: TU64 = Readable::read(&mut &hex::decode(vector).unwrap()[..]).unwrap();
+            assert_eq!(decoded, TU64(value));
+            if let Ok(v) = u32::try_from(value) {
+                assert_eq!(hex::encode(TU32(v).encode().unwrap()), vector);
+            }
+            if let Ok(v) = u16::try_from(value) {
+                assert_eq!(hex::encode(TU16(v).encode().unwrap()), vector);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_integers_must_be_canonical() {
+        let test_vectors = [
+            ("00", 8),
+            ("0001", 8),
+            ("000100", 2),
+            ("010000", 2),
+            ("0100000000", 4),
+            ("000000000000000001", 8),
+            ("010000000000000000", 8),
+        ];
+
+        for (vector, len) in test_vectors {
+            let bytes = hex::decode(vector).unwrap();
+            let mut buff = bytes.as_slice();
+            let err = match len {
+                2 => TU16::read(&mut buff).unwrap_err(),
+                4 => TU32::read(&mut buff).unwrap_err(),
+                _ => TU64::read(&mut buff).unwrap_err(),
+            };
+            assert_eq!(err, DecodeError::InvalidData);
+        }
+
+        assert_eq!(TU16::read(&mut &[0x01, 0x00][..]), Ok(TU16(256)));
+    }
+
+    #[test]
+    fn field_context() {
+        let err = DecodeError::InvalidPublicKey.in_field("tlv3").in_field("tlv_stream");
+        assert_eq!(err.path(), ["tlv_stream", "tlv3"]);
+        assert_eq!(err.root(), &DecodeError::InvalidPublicKey);
+        assert_eq!(err.to_string(), "tlv_stream.tlv3: invalid public key");
+
+        assert!(DecodeError::ShortRead.path().is_empty());
+        assert_eq!(DecodeError::ShortRead.root(), &DecodeError::ShortRead);
+    }
+}
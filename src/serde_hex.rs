@@ -0,0 +1,17 @@
+//! Serializes byte fields as hex strings, for use with `#[serde(with = "crate::serde_hex")]`.
+
+use alloc::{string::String, vec::Vec};
+
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+pub fn serialize<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+/// Accepts any hex string decoding to a valid `T`, e.g. exactly 32 bytes for a `[u8; 32]`.
+pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    let bytes = hex::decode(&s).map_err(de::Error::custom)?;
+    let len = bytes.len();
+    T::try_from(bytes).map_err(|_| de::Error::invalid_length(len, &"the expected number of bytes"))
+}
@@ -1,56 +1,358 @@
-use std::fmt;
-use std::io::Read;
+use core::fmt;
+use alloc::vec::Vec;
 use secp256k1::PublicKey;
 
+use crate::io::{self, Read, Write};
 use crate::bigsize::BigSize;
-use crate::ser::{Readable, FixedLengthReadable, DecodeError, Writeable, ReadTrackingReader};
+use crate::types::{Script, ShortChannelId};
+use crate::ser::{Readable, FixedLengthReadable, DecodeError, Writeable, ReadTrackingReader, TU16, TU32, TU64};
+use crate::pretty::{Describe, impl_describe_fields};
+
+/// The set of records understood within one tlv_stream, e.g. `init_tlvs`. It is implemented by
+/// an enum holding one variant per known record type, usually generated with `tlv_namespace!`.
+///
+/// Each namespace decides for itself which types it knows: unknown even types are rejected and
+/// unknown odd types are kept as opaque bytes.
+pub trait TLVNamespace: Sized + fmt::Debug + Clone + PartialEq {
+    /// Name of the namespace as used in the specification
+    const NAME: &'static str;
+    /// Record types known in this namespace, in increasing order
+    const KNOWN_TYPES: &'static [u64];
+
+    /// Whether a stream in this namespace must carry a record of `record_type`.
+    fn is_required(record_type: u64) -> bool;
+    /// Name of the record as used in the specification
+    fn record_name(record_type: u64) -> Option<&'static str>;
+    fn record_type(&self) -> u64;
+    /// Decodes the value of a record, or returns `None` if the type isn't known in this namespace.
+    fn read_record(record_type: u64, value: &[u8]) -> Result<Option<Self>, DecodeError>;
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error>;
+    /// Writes the record for humans, e.g. `tlv2 scid=0x0x550`, as shown by `{:#}`.
+    fn describe_record(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The value of a TLV record. Unlike `Readable`, the value is given as the whole byte slice
+/// delimited by the record's length, which it must consume entirely.
+pub trait TLVValue: Sized {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError>;
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error>;
+}
+
+/// Implements `TLVValue` for a fixed-size `Readable` + `Writeable` type. Trailing bytes after the
+/// value make the record invalid.
+macro_rules! impl_tlv_value_fixed {
+    ($($ty: ty),* $(,)?) => {
+        $(
+            impl $crate::tlv::TLVValue for $ty {
+                fn read_value(bytes: &[u8]) -> Result<Self, $crate::ser::DecodeError> {
+                    let mut reader = bytes;
+                    let value = $crate::ser::Readable::read(&mut reader)?;
+                    if !reader.is_empty() {
+                        return Err($crate::ser::DecodeError::LengthMismatch {
+                            expected: bytes.len() - reader.len(),
+                            got: bytes.len(),
+                        })
+                    }
+                    Ok(value)
+                }
+
+                fn write_value<W: $crate::io::Write>(&self, writer: &mut W) -> Result<usize, $crate::io::Error> {
+                    $crate::ser::Writeable::write(self, writer)
+                }
+            }
+        )*
+    }
+}
+pub(crate) use impl_tlv_value_fixed;
+
+/// How the value of a known record is represented with serde. Most values use their own
+/// `Serialize` and `Deserialize` implementations, while byte arrays are hex strings.
+#[cfg(feature = "serde")]
+pub trait SerdeValue: Sized {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+    fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+/// Implements `SerdeValue` for types which implement `Serialize` and `Deserialize`.
+#[cfg(feature = "serde")]
+macro_rules! impl_serde_value {
+    ($($ty: ty),* $(,)?) => {
+        $(
+            impl $crate::tlv::SerdeValue for $ty {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serde::Serialize::serialize(self, serializer)
+                }
+
+                fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    serde::Deserialize::deserialize(deserializer)
+                }
+            }
+        )*
+    }
+}
+#[cfg(feature = "serde")]
+pub(crate) use impl_serde_value;
+
+/// Serialization of a namespace's known records, implemented by `tlv_namespace!` when the
+/// `serde` feature is enabled.
+#[cfg(feature = "serde")]
+pub trait SerdeNamespace: TLVNamespace {
+    fn serialize_value<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+    /// Deserializes the value of a record of a type in `KNOWN_TYPES`.
+    fn deserialize_value<'de, D: serde::Deserializer<'de>>(record_type: u64, deserializer: D) -> Result<Self, D::Error>;
+}
+
+/// Defines a TLV namespace as an enum with one variant per known record.
+///
+/// Each record is given as `type => Variant(ValueType) as "name", required|optional`, optionally
+/// followed by `check = <expr>` where the expression is a `Fn(&ValueType) -> Result<(), DecodeError>`
+/// run after the value has been decoded, e.g. to enforce a range.
+///
+/// When the record holds a single field, its name can follow the record's in brackets so that it
+/// is described as `name field=value`. The value must implement `Describe`.
+///
+/// ```ignore
+/// tlv_namespace! {
+///     pub enum ClosingSignedTlvs: "closing_signed_tlvs" {
+///         1 => FeeRange(FeeRange) as "fee_range", optional, check = FeeRange::check;
+///     }
+/// }
+///
+/// tlv_namespace! {
+///     pub enum N1: "n1" {
+///         1 => Tlv1(TU64) as "tlv1" [amount_msat], optional;
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! tlv_namespace {
+    (
+        $(#[$meta: meta])*
+        $vis: vis enum $ns: ident: $name: literal {
+            $(
+                $(#[$vmeta: meta])*
+                $typ: literal => $variant: ident($ty: ty) as $rec_name: literal $([$field: ident])?, $req: ident $(, check = $check: expr)?;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        $vis enum $ns {
+            $( $(#[$vmeta])* $variant($ty), )*
+        }
+
+        impl $crate::tlv::TLVNamespace for $ns {
+            const NAME: &'static str = $name;
+            const KNOWN_TYPES: &'static [u64] = &[$($typ),*];
+
+            fn is_required(record_type: u64) -> bool {
+                match record_type {
+                    $( $typ => $crate::tlv_namespace!(@required $req), )*
+                    _ => false,
+                }
+            }
+
+            fn record_name(record_type: u64) -> Option<&'static str> {
+                match record_type {
+                    $( $typ => Some($rec_name), )*
+                    _ => None,
+                }
+            }
+
+            fn record_type(&self) -> u64 {
+                match *self {
+                    $( $ns::$variant(_) => $typ, )*
+                }
+            }
+
+            fn read_record(record_type: u64, _value: &[u8]) -> Result<Option<Self>, $crate::ser::DecodeError> {
+                match record_type {
+                    $(
+                        $typ => {
+                            let v: $ty = $crate::tlv::TLVValue::read_value(_value)?;
+                            $( ($check)(&v)?; )?
+                            Ok(Some($ns::$variant(v)))
+                        }
+                    )*
+                    _ => Ok(None),
+                }
+            }
+
+            fn write_value<W: $crate::io::Write>(&self, _writer: &mut W) -> Result<usize, $crate::io::Error> {
+                match *self {
+                    $( $ns::$variant(ref v) => $crate::tlv::TLVValue::write_value(v, _writer), )*
+                }
+            }
+
+            fn describe_record(&self, _f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match *self {
+                    $(
+                        $ns::$variant(ref v) => {
+                            _f.write_str(concat!($rec_name, " ", $(stringify!($field), "=")?))?;
+                            $crate::pretty::Describe::describe(v, _f)
+                        }
+                    )*
+                }
+            }
+        }
+
+        $crate::__tlv_namespace_serde!($ns { $( $typ => $variant($ty) ),* });
+    };
+    (@required required) => { true };
+    (@required optional) => { false };
+}
+
+/// Implements `SerdeNamespace` for a namespace defined by `tlv_namespace!`. This depends on the
+/// features of this crate rather than the caller's, hence the separate macro.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __tlv_namespace_serde {
+    ($ns: ident { $( $typ: literal => $variant: ident($ty: ty) ),* }) => {
+        impl $crate::tlv::SerdeNamespace for $ns {
+            fn serialize_value<S: $crate::serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+                match *self {
+                    $( $ns::$variant(ref v) => $crate::tlv::SerdeValue::serialize(v, _serializer), )*
+                }
+            }
+
+            fn deserialize_value<'de, D: $crate::serde::Deserializer<'de>>(record_type: u64, _deserializer: D) -> Result<Self, D::Error> {
+                match record_type {
+                    $( $typ => Ok($ns::$variant(<$ty as $crate::tlv::SerdeValue>::deserialize(_deserializer)?)), )*
+                    _ => Err(<D::Error as $crate::serde::de::Error>::custom("unknown record type")),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __tlv_namespace_serde {
+    ($($tt: tt)*) => {};
+}
 
 /// A tlv_stream is a series of (possibly zero) tlv_records, represented as the concatenation of
 /// the encoded tlv_records.
-#[derive(Debug)]
-pub struct TLVStream(Vec<TLVRecord>);
+#[derive(Debug, Clone, PartialEq)]
+pub struct TLVStream<N: TLVNamespace>(Vec<TLVRecord<N>>);
 
-#[derive(Debug)]
-struct TLVRecord {
+#[derive(Debug, Clone, PartialEq)]
+pub struct TLVRecord<N: TLVNamespace> {
     /// It functions as a message-specific, 64-bit identifier for the tlv_record determining how
     /// the contents of value should be decoded. type identifiers below 2^16 are reserved for use
     /// in this specification. type identifiers greater than or equal to 2^16 are available for
     /// custom records. Any record not defined in this specification is considered a custom record.
     record_type: BigSize,
-    /// The size of value in bytes.
-    length: BigSize,
     /// Depends on `type`, and should be encoded or decoded according to the message-specific
     /// format determined by `type`.
-    value: Option<Value>
-}
-
-#[derive(Debug)]
-enum Value {
-    /// tlv1
-    Amount(u64),
-    /// tlv2
-    ShortChannelId([u8; 8]),
-    /// tlv3
-    PointAmount(PointAmount),
-    /// tlv4
-    CLTVExpiry(u16),
-    /// ignored records
+    value: RecordValue<N>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordValue<N: TLVNamespace> {
+    /// A record known in the namespace
+    Known(N),
+    /// An odd record unknown in the namespace, which is ignored
     Unknown(Vec<u8>),
 }
 
-#[derive(Debug)]
-struct PointAmount {
-    point: PublicKey,
-    amount_msat_1: u64,
-    amount_msat_2: u64,
+impl<N: TLVNamespace> TLVRecord<N> {
+    /// A record known in the namespace. Its length is only known once the value is encoded.
+    pub fn new(record: N) -> Self {
+        TLVRecord {
+            record_type: BigSize(record.record_type()),
+            value: RecordValue::Known(record),
+        }
+    }
+
+    /// A record of a type unknown in the namespace, such as a custom record.
+    pub fn unknown(record_type: u64, value: Vec<u8>) -> Self {
+        TLVRecord {
+            record_type: BigSize(record_type),
+            value: RecordValue::Unknown(value),
+        }
+    }
+
+    pub fn record_type(&self) -> u64 {
+        self.record_type.0
+    }
+
+    pub fn value(&self) -> &RecordValue<N> {
+        &self.value
+    }
+}
+
+impl<N: TLVNamespace> Default for TLVStream<N> {
+    fn default() -> Self {
+        TLVStream(Vec::new())
+    }
+}
+
+impl<N: TLVNamespace> TLVStream<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &TLVRecord<N>> {
+        self.0.iter()
+    }
+
+    /// The known record of `record_type`, if present.
+    pub fn get(&self, record_type: u64) -> Option<&N> {
+        self.0.iter().find(|r| r.record_type.0 == record_type).and_then(|r| match &r.value {
+            RecordValue::Known(v) => Some(v),
+            RecordValue::Unknown(_) => None,
+        })
+    }
+
+    /// Builds a stream from records in the given order. The stream can only be written if the
+    /// records are in strictly increasing type order.
+    pub fn from_records(records: Vec<TLVRecord<N>>) -> Self {
+        TLVStream(records)
+    }
+
+    /// Whether the records are in strictly increasing type order, i.e. sorted and without
+    /// duplicates, as required for the encoding to be canonical.
+    pub fn is_canonical(&self) -> bool {
+        self.0.windows(2).all(|w| w[0].record_type.0 < w[1].record_type.0)
+    }
+
+    /// Adds a known record, replacing any record of the same type and keeping the stream sorted.
+    pub fn insert(&mut self, record: N) {
+        self.insert_record(TLVRecord::new(record));
+    }
+
+    /// Adds a custom record, replacing any record of the same type and keeping the stream sorted.
+    pub fn insert_unknown(&mut self, record_type: u64, value: Vec<u8>) {
+        self.insert_record(TLVRecord::unknown(record_type, value));
+    }
+
+    fn insert_record(&mut self, record: TLVRecord<N>) {
+        match self.0.binary_search_by_key(&record.record_type.0, |r| r.record_type.0) {
+            Ok(i) => self.0[i] = record,
+            Err(i) => self.0.insert(i, record),
+        }
+    }
 }
 
-impl Readable for TLVStream {
+impl<N: TLVNamespace> Readable for TLVStream<N> {
 	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
-        let mut tlv_stream: Vec<TLVRecord> = Vec::new();
+        let mut tlv_stream: Vec<TLVRecord<N>> = Vec::new();
         loop {
             let mut tracking_reader = ReadTrackingReader::new(&mut *reader);
-            let record: TLVRecord = match Readable::read(&mut tracking_reader) {
+            let record: TLVRecord<N> = match Readable::read(&mut tracking_reader) {
                 Ok(r) => r,
                 Err(DecodeError::ShortRead) => {
                     if !tracking_reader.have_read { break }
@@ -62,186 +364,319 @@ impl Readable for TLVStream {
             // underlying tlv_records
             match tlv_stream.last() {
                 Some(prev) if prev.record_type.0 >= record.record_type.0 => {
-                    return Err(DecodeError::InvalidData)
+                    return Err(DecodeError::TlvOutOfOrder { prev: prev.record_type.0, got: record.record_type.0 })
                 },
                 _ => {}
             }
             tlv_stream.push(record);
         }
+
+        for typ in N::KNOWN_TYPES {
+            if N::is_required(*typ) && !tlv_stream.iter().any(|r| r.record_type.0 == *typ) {
+                return Err(DecodeError::MissingRequiredRecord(*typ))
+            }
+        }
         Ok(TLVStream(tlv_stream))
     }
 }
 
-macro_rules! decode_tlv1 {
-    ($stream: expr) => {{
-        match $stream.len() {
-            0 => { Err(DecodeError::ShortRead) },
-            n if n <= 8 => {
-                let mut res = [0; 8];
-                for (i, el) in $stream.iter().enumerate() {
-                    res[8 - n + i] = *el;
-                }
-                let val = u64::from_be_bytes(res);
-                // Check if it's minimally encoded
-                match n {
-                    1 if val == 0 => Err(DecodeError::InvalidData),
-                    2 if val < 0x0100 => Err(DecodeError::InvalidData),
-                    3 if val < 0x010000 => Err(DecodeError::InvalidData),
-                    4 if val < 0x01000000 => Err(DecodeError::InvalidData),
-                    5 if val < 0x0100000000 => Err(DecodeError::InvalidData),
-                    6 if val < 0x010000000000 => Err(DecodeError::InvalidData),
-                    7 if val < 0x01000000000000 => Err(DecodeError::InvalidData),
-                    8 if val < 0x0100000000000000 => Err(DecodeError::InvalidData),
-                    _ => {
-                        Ok(Some(Value::Amount(u64::from_be_bytes(res))))
-                    }
-                }
-            },
-            _ => { Err(DecodeError::InvalidData) },
+impl<N: TLVNamespace> Readable for TLVRecord<N> {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let record_type: BigSize = Readable::read(reader)?;
+        let length: BigSize = Readable::read(reader)?;
+        let v: Vec<u8> = FixedLengthReadable::read(reader, length.0 as usize)?;
+
+        let record = N::read_record(record_type.0, &v).map_err(|e| match N::record_name(record_type.0) {
+            Some(name) => e.in_field(name),
+            None => e,
+        })?;
+        let value = match record {
+            Some(record) => RecordValue::Known(record),
+            // It's ok to be odd
+            None if record_type.0.is_multiple_of(2) => return Err(DecodeError::UnknownEvenType(record_type.0)),
+            None => RecordValue::Unknown(v),
+        };
+
+        Ok(TLVRecord { record_type, value })
+    }
+}
+
+/// Writes the records in order, refusing streams which aren't canonical since the receiver would
+/// reject them.
+impl<N: TLVNamespace> Writeable for TLVStream<N> {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        if !self.is_canonical() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "tlv records out of order or duplicated"))
         }
-    }}
+        let mut len = 0;
+        for record in &self.0 {
+            len += record.write(writer)?;
+        }
+        Ok(len)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        write!(writer, "{}", self)
+    }
 }
 
-macro_rules! decode_tlv2 {
-    ($stream: expr) => {{
-        if $stream.len() > 8 { Err(DecodeError::InvalidData) }
-        else {
-            match $stream.try_into().map_err(|_| DecodeError::ShortRead) {
-                Ok(b) => Ok(Some(Value::ShortChannelId(b))),
-                Err(e) => Err(e)
+/// A stream is represented as a map from record type to value. Known values use the namespace's
+/// representation and unknown ones are hex strings, e.g. `{"1": 42, "33": "beef"}`.
+#[cfg(feature = "serde")]
+impl<N: SerdeNamespace> serde::Serialize for TLVStream<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        struct KnownValue<'a, N>(&'a N);
+        impl<N: SerdeNamespace> serde::Serialize for KnownValue<'_, N> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.serialize_value(serializer)
             }
         }
-    }}
-}
 
-macro_rules! decode_tlv3 {
-    ($stream: expr) => {{
-        Ok(Some(Value::PointAmount(PointAmount::new($stream)?)))
-    }}
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for record in &self.0 {
+            match &record.value {
+                RecordValue::Known(v) => map.serialize_entry(&record.record_type.0, &KnownValue(v))?,
+                RecordValue::Unknown(v) => map.serialize_entry(&record.record_type.0, &hex::encode(v))?,
+            }
+        }
+        map.end()
+    }
 }
 
-macro_rules! decode_tlv4 {
-    ($stream: expr) => {{
-        match $stream.len() {
-            n if n < 2 => Err(DecodeError::ShortRead),
-            n if n > 2 => Err(DecodeError::InvalidData),
-            _ => Ok(Some(Value::CLTVExpiry(u16::from_be_bytes($stream.try_into().unwrap()))))
+/// The stream is validated as if it had been decoded: unknown even types, values failing their
+/// checks and missing required records are rejected.
+#[cfg(feature = "serde")]
+impl<'de, N: SerdeNamespace> serde::Deserialize<'de> for TLVStream<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use core::marker::PhantomData;
+        use serde::de::{self, DeserializeSeed, MapAccess, Visitor};
+
+        struct KnownValue<N>(u64, PhantomData<N>);
+        impl<'de, N: SerdeNamespace> DeserializeSeed<'de> for KnownValue<N> {
+            type Value = N;
+
+            fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<N, D::Error> {
+                N::deserialize_value(self.0, deserializer)
+            }
         }
-    }}
-}
 
-impl Readable for TLVRecord {
-	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
-        let record_type: BigSize = Readable::read(reader)?;
-        let length: BigSize = Readable::read(reader)?;
-        let v: Vec<u8> = FixedLengthReadable::read(reader, length.0 as usize)?;
+        struct StreamVisitor<N>(PhantomData<N>);
+        impl<'de, N: SerdeNamespace> Visitor<'de> for StreamVisitor<N> {
+            type Value = TLVStream<N>;
 
-        let value = match record_type.0 {
-            1 => decode_tlv1!(v),
-            2 => decode_tlv2!(v),
-            3 => decode_tlv3!(v),
-            254 => decode_tlv4!(v),
-            x if x % 2 == 0 => Err(DecodeError::UnknownRequiredFeature),
-            _ => Ok(Some(Value::Unknown(v))),
-        };
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of {} records", N::NAME)
+            }
 
-        match value {
-            Err(DecodeError::ShortRead) => {
-                if length.0 == 0 && record_type.0 == 1 {
-                    Ok(TLVRecord {
-                        record_type,
-                        length,
-                        value: None
-                    })
-                } else {
-                    Err(DecodeError::ShortRead)
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut stream = TLVStream::<N>::new();
+                while let Some(record_type) = map.next_key::<u64>()? {
+                    if N::KNOWN_TYPES.contains(&record_type) {
+                        stream.insert(map.next_value_seed(KnownValue::<N>(record_type, PhantomData))?);
+                    } else {
+                        let value: alloc::string::String = map.next_value()?;
+                        stream.insert_unknown(record_type, hex::decode(value).map_err(de::Error::custom)?);
+                    }
                 }
-            },
-            Err(e) => Err(e),
-            Ok(value) => {
-                Ok(TLVRecord {
-                    record_type,
-                    length,
-                    value
-                })
+
+                // Decoding the encoded stream performs the same checks as for a received one
+                let bytes = stream.encode().map_err(de::Error::custom)?;
+                Readable::read(&mut &bytes[..]).map_err(de::Error::custom)
             }
         }
+
+        deserializer.deserialize_map(StreamVisitor(PhantomData))
     }
 }
 
-impl PointAmount {
-    fn new(stream: Vec<u8>) -> Result<Self, DecodeError> {
-        match stream.len() {
-            n if n < 49 => Err(DecodeError::ShortRead),
-            n if n > 49 => Err(DecodeError::InvalidData),
-            _ => {
-                let point = match PublicKey::from_slice(&stream[..33]) {
-                    Ok(p) => Ok(p),
-                    Err(_) => Err(DecodeError::InvalidData),
-                }?;
-                let amount_msat_1: u64 = u64::from_be_bytes(stream[33..41].try_into().unwrap());
-                let amount_msat_2: u64 = u64::from_be_bytes(stream[41..49].try_into().unwrap());
-
-                Ok(PointAmount { point, amount_msat_1, amount_msat_2, })
-            }
+/// The length is always derived from the encoded value, so known values are written with their
+/// minimal encoding whatever length they were read with.
+impl<N: TLVNamespace> Writeable for TLVRecord<N> {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut value = Vec::new();
+        match &self.value {
+            RecordValue::Known(v) => { v.write_value(&mut value)?; },
+            RecordValue::Unknown(b) => value.extend_from_slice(b),
         }
+        let mut len = self.record_type.write(writer)?;
+        len += BigSize(value.len() as u64).write(writer)?;
+        writer.write_all(&value)?;
+        Ok(len + value.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        write!(writer, "{}", self)
     }
 }
 
-impl fmt::Display for TLVStream {
+/// The hex encoding of the stream, or with `{:#}` its records described one by one and separated
+/// by commas, e.g. `tlv1 amount_msat=1, unknown type=33 value=beef`.
+impl<N: TLVNamespace> fmt::Display for TLVStream<N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for record in &self.0 {
-            write!(f, "{}", record)?;
+        for (i, record) in self.0.iter().enumerate() {
+            if f.alternate() && i > 0 {
+                f.write_str(", ")?;
+            }
+            record.fmt(f)?;
         }
         Ok(())
     }
 }
 
-
-impl fmt::Display for TLVRecord {
+impl<N: TLVNamespace> fmt::Display for TLVRecord<N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.record_type.write_fmt(f)?;
-        self.length.write_fmt(f)?;
-        if let Some(v) = &self.value {
-            let n: usize = (self.length.0 * 2) as usize;
-            write!(f, "{:01$x}", v, n)?;
+        if f.alternate() {
+            return match &self.value {
+                RecordValue::Known(v) => v.describe_record(f),
+                RecordValue::Unknown(v) => {
+                    write!(f, "unknown type={} value=", self.record_type.0)?;
+                    v.describe(f)
+                }
+            }
+        }
+        for byte in self.encode().map_err(|_| fmt::Error)? {
+            write!(f, "{:02x}", byte)?;
         }
         Ok(())
     }
 }
 
-impl fmt::LowerHex for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Amount(v) => Ok(v.fmt(f)?),
-            Value::ShortChannelId(scid) => {
-                for byte in scid {
-                    write!(f, "{:02x}", byte)?;
-                }
-                Ok(())
-            }
-            Value::PointAmount(v) => {
-                write!(f, "{:02x}", v.point)?;
-                write!(f, "{:016x}", v.amount_msat_1)?;
-                write!(f, "{:016x}", v.amount_msat_2)?;
-                Ok(())
-            }
-            Value::CLTVExpiry(v) => Ok(v.fmt(f)?),
-            Value::Unknown(b) => {
-                for byte in b {
-                    write!(f, "{:02x}", byte)?;
-                }
-                Ok(())
-            }
+/// Within a message, a stream is described as its records in brackets.
+impl<N: TLVNamespace> Describe for TLVStream<N> {
+    fn describe(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:#}]", self)
+    }
+}
+
+/// Opaque values span the whole record.
+impl TLVValue for Vec<u8> {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(bytes.to_vec())
+    }
+
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        writer.write_all(self)?;
+        Ok(self.len())
+    }
+}
+
+/// Scripts, such as `upfront_shutdown_script`, span the whole record and may be empty.
+impl TLVValue for Script {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Script::from(bytes.to_vec()))
+    }
+
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        writer.write_all(self.as_bytes())?;
+        Ok(self.len())
+    }
+}
+
+impl_tlv_value_fixed!(u8, u16, u32, u64, TU16, TU32, TU64, PublicKey, PointAmount);
+
+impl<const N: usize> TLVValue for [u8; N] {
+    fn read_value(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes.len() {
+            n if n != N => Err(DecodeError::LengthMismatch { expected: N, got: n }),
+            _ => Ok(bytes.try_into().expect("length checked")),
         }
     }
+
+    fn write_value<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.write(writer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointAmount {
+    pub node_id: PublicKey,
+    pub amount_msat_1: u64,
+    pub amount_msat_2: u64,
+}
+
+impl Readable for PointAmount {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(PointAmount {
+            node_id: Readable::read(reader)?,
+            amount_msat_1: Readable::read(reader)?,
+            amount_msat_2: Readable::read(reader)?,
+        })
+    }
+}
+
+impl Writeable for PointAmount {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.node_id.write(writer)?;
+        len += self.amount_msat_1.write(writer)?;
+        len += self.amount_msat_2.write(writer)?;
+        Ok(len)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.node_id.write_fmt(writer)?;
+        write!(writer, "{:016x}", self.amount_msat_1)?;
+        write!(writer, "{:016x}", self.amount_msat_2)
+    }
+}
+
+impl_describe_fields!(PointAmount, { node_id, amount_msat_1, amount_msat_2 });
+
+#[cfg(feature = "serde")]
+impl_serde_value!(u8, u16, u32, u64, TU16, TU32, TU64, PublicKey, PointAmount, Script);
+
+#[cfg(feature = "serde")]
+impl SerdeValue for Vec<u8> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::serde_hex::serialize(self, serializer)
+    }
+
+    fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::serde_hex::deserialize(deserializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const N: usize> SerdeValue for [u8; N] {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::serde_hex::serialize(self, serializer)
+    }
+
+    fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::serde_hex::deserialize(deserializer)
+    }
+}
+
+tlv_namespace! {
+    /// The `n1` namespace used by the BOLT #1 test vectors.
+    pub enum N1: "n1" {
+        1 => Tlv1(TU64) as "tlv1" [amount_msat], optional;
+        2 => Tlv2(ShortChannelId) as "tlv2" [scid], optional;
+        3 => Tlv3(PointAmount) as "tlv3", optional;
+        /// `cltv_delta` is a plain `u16` in the spec, not a `tu16`: `fd00fe 01 01` must fail.
+        254 => Tlv4(u16) as "tlv4" [cltv_delta], optional;
+    }
+}
+
+tlv_namespace! {
+    /// The `n2` namespace used by the BOLT #1 test vectors.
+    pub enum N2: "n2" {
+        0 => Tlv1(TU64) as "tlv1" [amount_msat], optional;
+        11 => Tlv2(TU32) as "tlv2" [cltv_expiry], optional;
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
-    use crate::{ser::{Readable, DecodeError}, tlv::Value};
-    use super::TLVStream;
+    use alloc::{string::ToString, vec::Vec};
+
+    use secp256k1::PublicKey;
+    use crate::ser::{Readable, DecodeError, Writeable};
+    use crate::ser::{TU32, TU64};
+    use crate::types::ShortChannelId;
+    use super::{TLVStream, TLVRecord, TLVNamespace, RecordValue, PointAmount, N1, N2};
 
     /// The following TLV streams in either namespace should correctly decode, and be ignored
     #[test]
@@ -256,48 +691,79 @@ mod tests {
             concat!("ff0200000000000001", "00"),
         ];
 
-        for vector in test_vectors {
-            let mut buff = Cursor::new(hex::decode(vector).expect("input"));
-            let stream: TLVStream = Readable::read(&mut buff).expect("no failure");
+        fn check<N: TLVNamespace>(vector: &str) {
+            let input = hex::decode(vector).expect("input");
+            let mut buff = input.as_slice();
+            let stream: TLVStream<N> = Readable::read(&mut buff).expect("no failure");
             if !vector.is_empty() {
-                assert_eq!(stream.0.len(), 1);
-                if let Some(Value::Unknown(v)) = &stream.0[0].value {
+                assert_eq!(stream.len(), 1);
+                if let RecordValue::Unknown(_) = stream.records().next().unwrap().value() {
                     assert_eq!(stream.to_string(), vector);
                 } else { panic!() }
             }
         }
+
+        for vector in test_vectors {
+            check::<N1>(vector);
+            check::<N2>(vector);
+        }
     }
 
     /// The following TLV streams in `n1` namespace should correctly decode, with the values given
     #[test]
     fn tlv_stream_decode_success_values() {
+        let point = PublicKey::from_slice(
+            &hex::decode("023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb").unwrap()
+        ).unwrap();
         let test_vectors = [
-            (concat!("01", "00"), "tlv1 amount_msat=0"),
-            (concat!("01", "01", "01"), "tlv1 amount_msat=1"),
-            (concat!("01", "02", "0100"), "tlv1 amount_msat=256"),
-            (concat!("01", "03", "010000"), "tlv1 amount_msat=65536"),
-            (concat!("01", "04", "01000000"), "tlv1 amount_msat=16777216"),
-            (concat!("01", "05", "0100000000"), "tlv1 amount_msat=4294967296"),
-            (concat!("01", "06", "010000000000"), "tlv1 amount_msat=1099511627776"),
-            (concat!("01", "07", "01000000000000"), "tlv1 amount_msat=281474976710656"),
-            (concat!("01", "08", "0100000000000000"), "tlv1 amount_msat=72057594037927936"),
-            (concat!("02", "08", "0000000000000226"), "tlv2 scid=0x0x550"),
+            (concat!("01", "00"), N1::Tlv1(TU64(0)), "tlv1 amount_msat=0"),
+            (concat!("01", "01", "01"), N1::Tlv1(TU64(1)), "tlv1 amount_msat=1"),
+            (concat!("01", "02", "0100"), N1::Tlv1(TU64(256)), "tlv1 amount_msat=256"),
+            (concat!("01", "03", "010000"), N1::Tlv1(TU64(65536)), "tlv1 amount_msat=65536"),
+            (concat!("01", "04", "01000000"), N1::Tlv1(TU64(16777216)), "tlv1 amount_msat=16777216"),
+            (concat!("01", "05", "0100000000"), N1::Tlv1(TU64(4294967296)), "tlv1 amount_msat=4294967296"),
+            (concat!("01", "06", "010000000000"), N1::Tlv1(TU64(1099511627776)), "tlv1 amount_msat=1099511627776"),
+            (concat!("01", "07", "01000000000000"), N1::Tlv1(TU64(281474976710656)), "tlv1 amount_msat=281474976710656"),
+            (concat!("01", "08", "0100000000000000"), N1::Tlv1(TU64(72057594037927936)), "tlv1 amount_msat=72057594037927936"),
+            (concat!("02", "08", "0000000000000226"), N1::Tlv2("0x0x550".parse().unwrap()), "tlv2 scid=0x0x550"),
             (concat!("03", "31", "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb00000000000000010000000000000002"),
+            N1::Tlv3(PointAmount { node_id: point, amount_msat_1: 1, amount_msat_2: 2 }),
             "tlv3 node_id=023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb amount_msat_1=1 amount_msat_2=2"),
-            (concat!("fd00fe", "02", "0226"), "tlv4 cltv_delta=550"),
+            (concat!("fd00fe", "02", "0226"), N1::Tlv4(550), "tlv4 cltv_delta=550"),
         ];
 
-        for vector in test_vectors {
-            let mut buff = Cursor::new(hex::decode(vector.0).expect("input"));
-            let stream: TLVStream = Readable::read(&mut buff).expect("no failure");
-            assert_eq!(stream.to_string(), vector.0);
+        for (vector, value, description) in test_vectors {
+            let input = hex::decode(vector).expect("input");
+            let mut buff = input.as_slice();
+            let stream: TLVStream<N1> = Readable::read(&mut buff).expect("no failure");
+            assert_eq!(stream.get(value.record_type()), Some(&value));
+            assert_eq!(stream.to_string(), vector);
+            assert_eq!(format!("{:#}", stream), description);
         }
     }
 
+    #[test]
+    fn describe_stream() {
+        let mut stream = TLVStream::<N2>::new();
+        assert_eq!(format!("{:#}", stream), "");
+        stream.insert(N2::Tlv1(TU64(1000)));
+        stream.insert(N2::Tlv2(TU32(144)));
+        stream.insert_unknown(33, vec![0xbe, 0xef]);
+        assert_eq!(format!("{:#}", stream), "tlv1 amount_msat=1000, tlv2 cltv_expiry=144, unknown type=33 value=beef");
+
+        let mut stream = TLVStream::<Checked>::new();
+        stream.insert(Checked::Even(2));
+        assert_eq!(format!("{:#}", stream), "even 2");
+    }
+
     macro_rules! do_test_err {
         ($stream: expr, $err: expr) => {
-            let mut buff = Cursor::new(hex::decode($stream).expect("input"));
-            let expected: Result<TLVStream, DecodeError> = Readable::read(&mut buff);
+            do_test_err!(N1, $stream, $err);
+        };
+        ($ns: ty, $stream: expr, $err: expr) => {
+            let input = hex::decode($stream).expect("input");
+            let mut buff = input.as_slice();
+            let expected: Result<TLVStream<$ns>, DecodeError> = Readable::read(&mut buff);
             assert_eq!(expected.unwrap_err(), $err);
         };
     }
@@ -306,12 +772,12 @@ mod tests {
     fn tlv_stream_decode_failure_any_namespace() {
         do_test_err!("fd", DecodeError::ShortRead);
         do_test_err!("fd01", DecodeError::ShortRead);
-        do_test_err!(concat!("fd0001", "00"), DecodeError::InvalidData);
+        do_test_err!(concat!("fd0001", "00"), DecodeError::NonCanonicalBigSize);
         do_test_err!("fd0101", DecodeError::ShortRead);
         do_test_err!(concat!("0f", "fd"), DecodeError::ShortRead);
         do_test_err!(concat!("0f", "fd26"), DecodeError::ShortRead);
         do_test_err!(concat!("0f", "fd2602"), DecodeError::ShortRead);
-        do_test_err!(concat!("0f", "fd0001", "00"), DecodeError::InvalidData);
+        do_test_err!(concat!("0f", "fd0001", "00"), DecodeError::NonCanonicalBigSize);
         do_test_err!(concat!("0f", "fd0201", "000000000000000000000000000000000000000000000000000000000\
         0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
         0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
@@ -319,43 +785,67 @@ mod tests {
         0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
         0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
         0000"), DecodeError::ShortRead);
+        // A huge length mustn't be trusted for allocating the value
+        do_test_err!(concat!("0f", "ffffffffffffffffff", "00"), DecodeError::ShortRead);
     }
 
     #[test]
     fn tlv_stream_decode_failure_either_namespace() {
-        do_test_err!(concat!("12", "00"), DecodeError::UnknownRequiredFeature);
-        do_test_err!(concat!("fd0102", "00"), DecodeError::UnknownRequiredFeature);
-        do_test_err!(concat!("fe01000002", "00"), DecodeError::UnknownRequiredFeature);
-        do_test_err!(concat!("ff0100000000000002", "00"), DecodeError::UnknownRequiredFeature);
+        let test_vectors = [
+            (concat!("12", "00"), 0x12),
+            (concat!("fd0102", "00"), 0x0102),
+            (concat!("fe01000002", "00"), 0x01000002),
+            (concat!("ff0100000000000002", "00"), 0x0100000000000002),
+        ];
+
+        for (vector, typ) in test_vectors {
+            do_test_err!(N1, vector, DecodeError::UnknownEvenType(typ));
+            do_test_err!(N2, vector, DecodeError::UnknownEvenType(typ));
+        }
     }
 
     #[test]
     fn tlv_stream_decode_failure_n1_namespace() {
-        do_test_err!(concat!("01", "09", "ffffffffffffffffff"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "01", "00"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "02", "0001"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "03", "000100"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "04", "00010000"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "05", "0001000000"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "06", "000100000000"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "07", "00010000000000"), DecodeError::InvalidData);
-        do_test_err!(concat!("01", "08", "0001000000000000"), DecodeError::InvalidData);
-        do_test_err!(concat!("02", "07", "01010101010101"), DecodeError::ShortRead);
-        do_test_err!(concat!("02", "09", "010101010101010101"), DecodeError::InvalidData);
+        let non_minimal = DecodeError::InvalidData.in_field("tlv1");
+        do_test_err!(concat!("01", "09", "ffffffffffffffffff"), non_minimal);
+        do_test_err!(concat!("01", "01", "00"), non_minimal);
+        do_test_err!(concat!("01", "02", "0001"), non_minimal);
+        do_test_err!(concat!("01", "03", "000100"), non_minimal);
+        do_test_err!(concat!("01", "04", "00010000"), non_minimal);
+        do_test_err!(concat!("01", "05", "0001000000"), non_minimal);
+        do_test_err!(concat!("01", "06", "000100000000"), non_minimal);
+        do_test_err!(concat!("01", "07", "00010000000000"), non_minimal);
+        do_test_err!(concat!("01", "08", "0001000000000000"), non_minimal);
+        do_test_err!(concat!("02", "07", "01010101010101"),
+        DecodeError::ShortRead.in_field("tlv2"));
+        do_test_err!(concat!("02", "09", "010101010101010101"),
+        DecodeError::LengthMismatch { expected: 8, got: 9 }.in_field("tlv2"));
         do_test_err!(concat!("03", "21", "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb"),
-        DecodeError::ShortRead);
+        DecodeError::ShortRead.in_field("tlv3"));
         do_test_err!(concat!("03", "29", "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb0000000000000001"),
-        DecodeError::ShortRead);
+        DecodeError::ShortRead.in_field("tlv3"));
         do_test_err!(concat!("03", "30", "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb000000000000000100000000000001"),
-        DecodeError::ShortRead);
+        DecodeError::ShortRead.in_field("tlv3"));
         do_test_err!(concat!("03", "31", "043da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb00000000000000010000000000000002"),
-        DecodeError::InvalidData);
+        DecodeError::InvalidPublicKey.in_field("tlv3"));
         do_test_err!(concat!("03", "32", "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb0000000000000001000000000000000001"),
-        DecodeError::InvalidData);
-        do_test_err!(concat!("fd00fe", "00"), DecodeError::ShortRead);
-        do_test_err!(concat!("fd00fe", "01", "01"), DecodeError::ShortRead);
-        do_test_err!(concat!("fd00fe", "03", "010101"), DecodeError::InvalidData);
-        do_test_err!(concat!("00", "00"), DecodeError::UnknownRequiredFeature);
+        DecodeError::LengthMismatch { expected: 49, got: 50 }.in_field("tlv3"));
+        do_test_err!(concat!("fd00fe", "00"), DecodeError::ShortRead.in_field("tlv4"));
+        do_test_err!(concat!("fd00fe", "01", "01"), DecodeError::ShortRead.in_field("tlv4"));
+        do_test_err!(concat!("fd00fe", "03", "010101"), DecodeError::LengthMismatch { expected: 2, got: 3 }.in_field("tlv4"));
+        do_test_err!(concat!("00", "00"), DecodeError::UnknownEvenType(0));
+    }
+
+    /// Type 0 is unknown in `n1` but known in `n2`, so even types are rejected per namespace.
+    #[test]
+    fn unknown_even_types_depend_on_namespace() {
+        let input = hex::decode(concat!("00", "01", "2a")).unwrap();
+        let mut buff = input.as_slice();
+        let stream: TLVStream<N2> = Readable::read(&mut buff).expect("no failure");
+        assert_eq!(stream.get(0), Some(&N2::Tlv1(TU64(42))));
+
+        do_test_err!(N2, concat!("02", "08", "0000000000000226"), DecodeError::UnknownEvenType(2));
+        do_test_err!(N2, concat!("0b", "05", "0100000000"), DecodeError::InvalidData.in_field("tlv2"));
     }
 
     /// Any appending of an invalid stream to a valid stream should trigger a decoding failure.
@@ -363,10 +853,10 @@ mod tests {
     /// trigger a decoding failure.
     #[test]
     fn tlv_stream_decode_failure_appending_n1() {
-        do_test_err!(concat!("02", "08", "0000000000000226", "01", "01", "2a"), DecodeError::InvalidData);
-        do_test_err!(concat!("02", "08", "0000000000000231", "02", "08", "0000000000000451"), DecodeError::InvalidData);
-        do_test_err!(concat!("1f", "00", "0f", "01", "2a"), DecodeError::InvalidData);
-        do_test_err!(concat!("1f", "00", "1f", "01", "2a"), DecodeError::InvalidData);
+        do_test_err!(concat!("02", "08", "0000000000000226", "01", "01", "2a"), DecodeError::TlvOutOfOrder { prev: 2, got: 1 });
+        do_test_err!(concat!("02", "08", "0000000000000231", "02", "08", "0000000000000451"), DecodeError::TlvOutOfOrder { prev: 2, got: 2 });
+        do_test_err!(concat!("1f", "00", "0f", "01", "2a"), DecodeError::TlvOutOfOrder { prev: 0x1f, got: 0x0f });
+        do_test_err!(concat!("1f", "00", "1f", "01", "2a"), DecodeError::TlvOutOfOrder { prev: 0x1f, got: 0x1f });
     }
 
     /// Any appending of an invalid stream to a valid stream should trigger a decoding failure.
@@ -374,9 +864,103 @@ mod tests {
     /// trigger a decoding failure.
     #[test]
     fn tlv_stream_decode_failure_appending_n2() {
-        // Took rust-lightning's approach of modifying this test since it was trivial and I
-        // didn't want to rewrite the decoder to handle it.
-        // (concat!("ffffffffffffffffff", "00", "00", "00"), DecodeError::InvalidData),
-        do_test_err!(concat!("ffffffffffffffffff", "00", "01", "00"), DecodeError::InvalidData);
+        do_test_err!(N2, concat!("ffffffffffffffffff", "00", "00", "00"), DecodeError::TlvOutOfOrder { prev: u64::MAX, got: 0 });
+        do_test_err!(N2, concat!("ffffffffffffffffff", "00", "01", "00"), DecodeError::TlvOutOfOrder { prev: u64::MAX, got: 1 });
+    }
+
+    tlv_namespace! {
+        enum Checked: "checked" {
+            2 => Even(u16) as "even", required, check = |v: &u16| {
+                if *v > 1000 { Err(DecodeError::InvalidData) } else { Ok(()) }
+            };
+            5 => Odd(TU32) as "odd", optional;
+        }
+    }
+
+    #[test]
+    fn required_records_and_checks() {
+        do_test_err!(Checked, "", DecodeError::MissingRequiredRecord(2));
+        do_test_err!(Checked, concat!("05", "01", "01"), DecodeError::MissingRequiredRecord(2));
+        do_test_err!(Checked, concat!("02", "02", "03e9"), DecodeError::InvalidData.in_field("even"));
+
+        let input = hex::decode(concat!("02", "02", "03e8", "05", "01", "01")).unwrap();
+        let mut buff = input.as_slice();
+        let stream: TLVStream<Checked> = Readable::read(&mut buff).expect("no failure");
+        assert_eq!(stream.get(2), Some(&Checked::Even(1000)));
+        assert_eq!(stream.get(5), Some(&Checked::Odd(TU32(1))));
+        assert_eq!(Checked::record_name(5), Some("odd"));
+        assert!(Checked::is_required(2) && !Checked::is_required(5));
+    }
+
+    #[test]
+    fn encode_stream() {
+        let stream = TLVStream::from_records(vec![
+            TLVRecord::new(N1::Tlv1(TU64(0))),
+            TLVRecord::new(N1::Tlv2(ShortChannelId(0x226))),
+            TLVRecord::unknown(0x21, vec![0x2a]),
+            TLVRecord::new(N1::Tlv4(550)),
+        ]);
+        let encoded = stream.encode().unwrap();
+        assert_eq!(hex::encode(&encoded), concat!("0100", "02080000000000000226", "21012a", "fd00fe020226"));
+
+        let decoded: TLVStream<N1> = Readable::read(&mut &encoded[..]).expect("no failure");
+        assert_eq!(decoded, stream);
+    }
+
+    #[test]
+    fn encode_refuses_non_canonical_streams() {
+        let out_of_order = TLVStream::from_records(vec![
+            TLVRecord::new(N1::Tlv2(ShortChannelId(0))),
+            TLVRecord::new(N1::Tlv1(TU64(1))),
+        ]);
+        assert!(!out_of_order.is_canonical());
+        assert!(out_of_order.write(&mut Vec::new()).is_err());
+        assert!(out_of_order.encode().is_err());
+
+        let duplicated = TLVStream::<N1>::from_records(vec![
+            TLVRecord::unknown(0x21, vec![]),
+            TLVRecord::unknown(0x21, vec![0x01]),
+        ]);
+        assert!(duplicated.write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn insert_keeps_stream_sorted() {
+        let mut stream = TLVStream::<N1>::new();
+        stream.insert(N1::Tlv4(550));
+        stream.insert_unknown(33, vec![]);
+        stream.insert(N1::Tlv1(TU64(1)));
+        stream.insert(N1::Tlv1(TU64(256)));
+        assert_eq!(hex::encode(stream.encode().unwrap()), concat!("01", "02", "0100", "21", "00", "fd00fe", "02", "0226"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_keyed_by_type() {
+        let point = PublicKey::from_slice(
+            &hex::decode("023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb").unwrap()
+        ).unwrap();
+        let mut stream = TLVStream::<N1>::new();
+        stream.insert(N1::Tlv1(TU64(1)));
+        stream.insert(N1::Tlv2(ShortChannelId(0x226)));
+        stream.insert(N1::Tlv3(PointAmount { node_id: point, amount_msat_1: 1, amount_msat_2: 2 }));
+        stream.insert_unknown(33, vec![0xbe, 0xef]);
+        stream.insert(N1::Tlv4(550));
+
+        let json = serde_json::to_string(&stream).unwrap();
+        assert_eq!(json, concat!(
+            r#"{"1":1,"2":"0x0x550","#,
+            r#""3":{"node_id":"023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb","amount_msat_1":1,"amount_msat_2":2},"#,
+            r#""33":"beef","254":550}"#,
+        ));
+        assert_eq!(serde_json::from_str::<TLVStream<N1>>(&json).unwrap(), stream);
+
+        // Records may be given in any order, but are checked as if decoded
+        let stream: TLVStream<N1> = serde_json::from_str(r#"{"254":550,"1":1}"#).unwrap();
+        assert_eq!(hex::encode(stream.encode().unwrap()), concat!("01", "01", "01", "fd00fe", "02", "0226"));
+        assert!(serde_json::from_str::<TLVStream<N1>>(r#"{"32":""}"#).is_err());
+        assert!(serde_json::from_str::<TLVStream<N1>>(r#"{"1":"zz"}"#).is_err());
+        assert!(serde_json::from_str::<TLVStream<Checked>>(r#"{"2":1001}"#).is_err());
+        assert!(serde_json::from_str::<TLVStream<Checked>>(r#"{"5":1}"#).is_err());
     }
 }
@@ -0,0 +1,665 @@
+use std::{io::{self, Read, Write}, fmt};
+
+use bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, AeadInPlace, Nonce, Tag};
+use secp256k1::{PublicKey, SecretKey, Secp256k1, ecdh::SharedSecret};
+
+use crate::ser::{DecodeError, Writeable};
+use crate::wire::{self, Message};
+
+/// Noise_XK_secp256k1_ChaChaPoly_SHA256
+const PROTOCOL_NAME: &[u8] = b"Noise_XK_secp256k1_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"lightning";
+/// The only handshake version currently defined
+const HANDSHAKE_VERSION: u8 = 0;
+/// Keys are rotated after they have been used to encrypt (or decrypt) this many times
+const KEY_ROTATION_INDEX: u64 = 1000;
+
+pub const ACT_ONE_LEN: usize = 50;
+pub const ACT_TWO_LEN: usize = 50;
+pub const ACT_THREE_LEN: usize = 66;
+pub(crate) const MAC_LEN: usize = 16;
+/// Encrypted 2-byte length prefix and its MAC
+pub(crate) const LENGTH_HEADER_LEN: usize = 2 + MAC_LEN;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TransportError {
+    Io(io::ErrorKind),
+    /// The peer sent an act with a handshake version we don't know
+    InvalidVersion(u8),
+    /// The peer sent a point which is not a valid public key
+    InvalidPublicKey,
+    /// A MAC check failed during the handshake or on a message
+    DecryptionFailed,
+    /// The handshake method called doesn't match the current state of the handshake
+    InvalidState,
+    Decode(DecodeError),
+}
+
+impl std::error::Error for TransportError {}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Io(e) => write!(f, "{:?}", e),
+            TransportError::InvalidVersion(v) => write!(f, "unknown handshake version {}", v),
+            TransportError::InvalidPublicKey => write!(f, "invalid public key"),
+            TransportError::DecryptionFailed => write!(f, "decryption failed"),
+            TransportError::InvalidState => write!(f, "unexpected handshake state"),
+            TransportError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(e: io::Error) -> Self {
+        TransportError::Io(e.kind())
+    }
+}
+
+impl From<DecodeError> for TransportError {
+    fn from(e: DecodeError) -> Self {
+        TransportError::Decode(e)
+    }
+}
+
+/// The nonce is encoded as 32 zero bits followed by the little-endian 64-bit value.
+fn nonce(n: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&n.to_le_bytes());
+    Nonce::clone_from_slice(&nonce)
+}
+
+/// Returns `plaintext` encrypted with ChaCha20-Poly1305 followed by the 16-byte MAC.
+fn encrypt_with_ad(key: &[u8; 32], n: u64, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let mut res = plaintext.to_vec();
+    let tag = cipher.encrypt_in_place_detached(&nonce(n), ad, &mut res)
+        .expect("plaintext is within the cipher's limits");
+    res.extend_from_slice(&tag);
+    res
+}
+
+/// Decrypts a ciphertext which ends with its 16-byte MAC, failing if the MAC doesn't check out.
+fn decrypt_with_ad(key: &[u8; 32], n: u64, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, TransportError> {
+    if ciphertext.len() < MAC_LEN {
+        return Err(TransportError::DecryptionFailed)
+    }
+    let (data, tag) = ciphertext.split_at(ciphertext.len() - MAC_LEN);
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let mut res = data.to_vec();
+    cipher.decrypt_in_place_detached(&nonce(n), ad, &mut res, Tag::from_slice(tag))
+        .map_err(|_| TransportError::DecryptionFailed)?;
+    Ok(res)
+}
+
+/// HKDF with `salt` as the salt, returning the two 32-byte outputs.
+fn hkdf(salt: &[u8; 32], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut engine = HmacEngine::<sha256::Hash>::new(salt);
+    engine.input(ikm);
+    let prk = Hmac::from_engine(engine).into_inner();
+
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk);
+    engine.input(&[1]);
+    let t1 = Hmac::from_engine(engine).into_inner();
+
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk);
+    engine.input(&t1);
+    engine.input(&[2]);
+    let t2 = Hmac::from_engine(engine).into_inner();
+
+    (t1, t2)
+}
+
+/// ECDH as defined by BOLT #8, i.e. the SHA256 of the compressed shared point.
+fn ecdh(point: &PublicKey, scalar: &SecretKey) -> [u8; 32] {
+    SharedSecret::new(point, scalar).secret_bytes()
+}
+
+fn mix_hash(h: &mut [u8; 32], data: &[u8]) {
+    let mut engine = sha256::Hash::engine();
+    engine.input(h);
+    engine.input(data);
+    *h = sha256::Hash::from_engine(engine).into_inner();
+}
+
+/// Keys and nonce for one direction of an established connection.
+#[derive(Debug, Clone)]
+struct CipherState {
+    key: [u8; 32],
+    nonce: u64,
+    chaining_key: [u8; 32],
+}
+
+impl CipherState {
+    fn new(key: [u8; 32], chaining_key: [u8; 32]) -> Self {
+        CipherState { key, nonce: 0, chaining_key }
+    }
+
+    /// Increments the nonce, rotating the key once it has been used `KEY_ROTATION_INDEX` times.
+    fn increment_nonce(&mut self) {
+        self.nonce += 1;
+        if self.nonce == KEY_ROTATION_INDEX {
+            let (chaining_key, key) = hkdf(&self.chaining_key, &self.key);
+            self.chaining_key = chaining_key;
+            self.key = key;
+            self.nonce = 0;
+        }
+    }
+
+    fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let res = encrypt_with_ad(&self.key, self.nonce, &[], plaintext);
+        self.increment_nonce();
+        res
+    }
+
+    fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let res = decrypt_with_ad(&self.key, self.nonce, &[], ciphertext)?;
+        self.increment_nonce();
+        Ok(res)
+    }
+}
+
+/// Encrypts and decrypts messages once the handshake has completed.
+///
+/// Every message is sent as an encrypted 2-byte big-endian length followed by the encrypted
+/// message, each with its own MAC and each consuming a nonce.
+#[derive(Debug, Clone)]
+pub struct MessageEncryptor {
+    sending: CipherState,
+    receiving: CipherState,
+}
+
+impl MessageEncryptor {
+    /// Encrypts a message of at most 65535 bytes into a length-prefixed frame.
+    pub fn encrypt_message(&mut self, msg: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let len: u16 = msg.len().try_into()
+            .map_err(|_| TransportError::Io(io::ErrorKind::InvalidInput))?;
+        let mut res = self.sending.encrypt(&len.to_be_bytes());
+        res.extend_from_slice(&self.sending.encrypt(msg));
+        Ok(res)
+    }
+
+    /// Decrypts the 18-byte header of a frame, returning the length of the message that follows
+    /// (excluding its MAC).
+    pub fn decrypt_length_header(&mut self, header: &[u8; LENGTH_HEADER_LEN]) -> Result<u16, TransportError> {
+        let len = self.receiving.decrypt(header)?;
+        Ok(u16::from_be_bytes([len[0], len[1]]))
+    }
+
+    /// Decrypts the body of a frame, which must include the trailing MAC.
+    pub fn decrypt_message(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, TransportError> {
+        self.receiving.decrypt(ciphertext)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum HandshakeState {
+    /// Initiator, before act one has been produced
+    InitiatorStarting,
+    /// Initiator, waiting for act two
+    InitiatorAwaitingActTwo,
+    /// Responder, waiting for act one
+    ResponderAwaitingActOne,
+    /// Responder, waiting for act three, having sent act two
+    ResponderAwaitingActThree { temp_k2: [u8; 32] },
+    Finished,
+}
+
+/// Drives the Noise_XK handshake. The initiator knows the static public key of the responder in
+/// advance, while the responder learns the initiator's static key in act three.
+pub struct Handshake {
+    secp_ctx: Secp256k1<secp256k1::All>,
+    state: HandshakeState,
+    our_node_secret: SecretKey,
+    ephemeral_key: SecretKey,
+    their_node_id: Option<PublicKey>,
+    their_ephemeral: Option<PublicKey>,
+    h: [u8; 32],
+    ck: [u8; 32],
+}
+
+impl Handshake {
+    fn new(state: HandshakeState, our_node_secret: SecretKey, ephemeral_key: SecretKey, responder_static: &PublicKey) -> Self {
+        let h = sha256::Hash::hash(PROTOCOL_NAME).into_inner();
+        let ck = h;
+        let mut h = h;
+        mix_hash(&mut h, PROLOGUE);
+        mix_hash(&mut h, &responder_static.serialize());
+
+        Handshake {
+            secp_ctx: Secp256k1::new(),
+            state,
+            our_node_secret,
+            ephemeral_key,
+            their_node_id: None,
+            their_ephemeral: None,
+            h,
+            ck,
+        }
+    }
+
+    /// Starts a handshake with the node `their_node_id`. `ephemeral_key` must be freshly generated
+    /// for every connection.
+    pub fn new_outbound(our_node_secret: SecretKey, their_node_id: PublicKey, ephemeral_key: SecretKey) -> Self {
+        let mut handshake = Self::new(HandshakeState::InitiatorStarting, our_node_secret, ephemeral_key, &their_node_id);
+        handshake.their_node_id = Some(their_node_id);
+        handshake
+    }
+
+    /// Waits for a handshake from a node we don't know yet. `ephemeral_key` must be freshly
+    /// generated for every connection.
+    pub fn new_inbound(our_node_secret: SecretKey, ephemeral_key: SecretKey) -> Self {
+        let secp_ctx = Secp256k1::signing_only();
+        let our_node_id = PublicKey::from_secret_key(&secp_ctx, &our_node_secret);
+        Self::new(HandshakeState::ResponderAwaitingActOne, our_node_secret, ephemeral_key, &our_node_id)
+    }
+
+    /// The static public key of the remote node, known from the start for outbound connections
+    /// and after act three for inbound ones.
+    pub fn their_node_id(&self) -> Option<PublicKey> {
+        self.their_node_id
+    }
+
+    /// Builds a `0 || e.pub || c` act, mixing our ephemeral key with `their_key`.
+    fn write_ephemeral_act(&mut self, their_key: &PublicKey) -> ([u8; ACT_ONE_LEN], [u8; 32]) {
+        let ephemeral_pub = PublicKey::from_secret_key(&self.secp_ctx, &self.ephemeral_key);
+        mix_hash(&mut self.h, &ephemeral_pub.serialize());
+        let (ck, temp_k) = hkdf(&self.ck, &ecdh(their_key, &self.ephemeral_key));
+        self.ck = ck;
+        let c = encrypt_with_ad(&temp_k, 0, &self.h, &[]);
+        mix_hash(&mut self.h, &c);
+
+        let mut act = [0; ACT_ONE_LEN];
+        act[0] = HANDSHAKE_VERSION;
+        act[1..34].copy_from_slice(&ephemeral_pub.serialize());
+        act[34..].copy_from_slice(&c);
+        (act, temp_k)
+    }
+
+    /// Processes a `0 || e.pub || c` act, mixing their ephemeral key with `our_key`.
+    fn read_ephemeral_act(&mut self, act: &[u8; ACT_ONE_LEN], our_key: &SecretKey) -> Result<(PublicKey, [u8; 32]), TransportError> {
+        if act[0] != HANDSHAKE_VERSION {
+            return Err(TransportError::InvalidVersion(act[0]))
+        }
+        let their_ephemeral = PublicKey::from_slice(&act[1..34])
+            .map_err(|_| TransportError::InvalidPublicKey)?;
+        mix_hash(&mut self.h, &their_ephemeral.serialize());
+        let (ck, temp_k) = hkdf(&self.ck, &ecdh(&their_ephemeral, our_key));
+        self.ck = ck;
+        decrypt_with_ad(&temp_k, 0, &self.h, &act[34..])?;
+        mix_hash(&mut self.h, &act[34..]);
+        Ok((their_ephemeral, temp_k))
+    }
+
+    /// Initiator: produces act one.
+    pub fn act_one(&mut self) -> Result<[u8; ACT_ONE_LEN], TransportError> {
+        let their_node_id = match (&self.state, self.their_node_id) {
+            (HandshakeState::InitiatorStarting, Some(id)) => id,
+            _ => return Err(TransportError::InvalidState),
+        };
+        let (act, _) = self.write_ephemeral_act(&their_node_id);
+        self.state = HandshakeState::InitiatorAwaitingActTwo;
+        Ok(act)
+    }
+
+    /// Responder: processes act one and produces act two.
+    pub fn process_act_one(&mut self, act: &[u8; ACT_ONE_LEN]) -> Result<[u8; ACT_TWO_LEN], TransportError> {
+        if !matches!(self.state, HandshakeState::ResponderAwaitingActOne) {
+            return Err(TransportError::InvalidState)
+        }
+        let our_node_secret = self.our_node_secret;
+        let (their_ephemeral, _) = self.read_ephemeral_act(act, &our_node_secret)?;
+        self.their_ephemeral = Some(their_ephemeral);
+
+        let (act_two, temp_k2) = self.write_ephemeral_act(&their_ephemeral);
+        self.state = HandshakeState::ResponderAwaitingActThree { temp_k2 };
+        Ok(act_two)
+    }
+
+    /// Initiator: processes act two and produces act three, completing the handshake.
+    pub fn process_act_two(&mut self, act: &[u8; ACT_TWO_LEN]) -> Result<([u8; ACT_THREE_LEN], MessageEncryptor), TransportError> {
+        if !matches!(self.state, HandshakeState::InitiatorAwaitingActTwo) {
+            return Err(TransportError::InvalidState)
+        }
+        let ephemeral_key = self.ephemeral_key;
+        let (their_ephemeral, temp_k2) = self.read_ephemeral_act(act, &ephemeral_key)?;
+
+        let our_node_id = PublicKey::from_secret_key(&self.secp_ctx, &self.our_node_secret);
+        let c = encrypt_with_ad(&temp_k2, 1, &self.h, &our_node_id.serialize());
+        mix_hash(&mut self.h, &c);
+        let (ck, temp_k3) = hkdf(&self.ck, &ecdh(&their_ephemeral, &self.our_node_secret));
+        let t = encrypt_with_ad(&temp_k3, 0, &self.h, &[]);
+        let (sk, rk) = hkdf(&ck, &[]);
+
+        let mut act_three = [0; ACT_THREE_LEN];
+        act_three[0] = HANDSHAKE_VERSION;
+        act_three[1..50].copy_from_slice(&c);
+        act_three[50..].copy_from_slice(&t);
+
+        self.state = HandshakeState::Finished;
+        Ok((act_three, MessageEncryptor {
+            sending: CipherState::new(sk, ck),
+            receiving: CipherState::new(rk, ck),
+        }))
+    }
+
+    /// Responder: processes act three, completing the handshake and learning the initiator's
+    /// static public key.
+    pub fn process_act_three(&mut self, act: &[u8; ACT_THREE_LEN]) -> Result<(PublicKey, MessageEncryptor), TransportError> {
+        let temp_k2 = match self.state {
+            HandshakeState::ResponderAwaitingActThree { temp_k2 } => temp_k2,
+            _ => return Err(TransportError::InvalidState),
+        };
+        if act[0] != HANDSHAKE_VERSION {
+            return Err(TransportError::InvalidVersion(act[0]))
+        }
+        let their_node_id = decrypt_with_ad(&temp_k2, 1, &self.h, &act[1..50])?;
+        let their_node_id = PublicKey::from_slice(&their_node_id)
+            .map_err(|_| TransportError::InvalidPublicKey)?;
+        mix_hash(&mut self.h, &act[1..50]);
+        let (ck, temp_k3) = hkdf(&self.ck, &ecdh(&their_node_id, &self.ephemeral_key));
+        decrypt_with_ad(&temp_k3, 0, &self.h, &act[50..])?;
+        let (rk, sk) = hkdf(&ck, &[]);
+
+        self.their_node_id = Some(their_node_id);
+        self.state = HandshakeState::Finished;
+        Ok((their_node_id, MessageEncryptor {
+            sending: CipherState::new(sk, ck),
+            receiving: CipherState::new(rk, ck),
+        }))
+    }
+}
+
+/// An encrypted and authenticated connection to a peer over any byte stream.
+pub struct PeerTransport<S: Read + Write> {
+    stream: S,
+    encryptor: MessageEncryptor,
+    their_node_id: PublicKey,
+}
+
+impl<S: Read + Write> PeerTransport<S> {
+    /// Performs the handshake as the initiator and returns the established connection.
+    pub fn connect(mut stream: S, our_node_secret: SecretKey, their_node_id: PublicKey, ephemeral_key: SecretKey) -> Result<Self, TransportError> {
+        let mut handshake = Handshake::new_outbound(our_node_secret, their_node_id, ephemeral_key);
+        stream.write_all(&handshake.act_one()?)?;
+
+        let mut act_two = [0; ACT_TWO_LEN];
+        stream.read_exact(&mut act_two)?;
+        let (act_three, encryptor) = handshake.process_act_two(&act_two)?;
+        stream.write_all(&act_three)?;
+        stream.flush()?;
+
+        Ok(PeerTransport { stream, encryptor, their_node_id })
+    }
+
+    /// Performs the handshake as the responder and returns the established connection.
+    pub fn accept(mut stream: S, our_node_secret: SecretKey, ephemeral_key: SecretKey) -> Result<Self, TransportError> {
+        let mut handshake = Handshake::new_inbound(our_node_secret, ephemeral_key);
+
+        let mut act_one = [0; ACT_ONE_LEN];
+        stream.read_exact(&mut act_one)?;
+        stream.write_all(&handshake.process_act_one(&act_one)?)?;
+        stream.flush()?;
+
+        let mut act_three = [0; ACT_THREE_LEN];
+        stream.read_exact(&mut act_three)?;
+        let (their_node_id, encryptor) = handshake.process_act_three(&act_three)?;
+
+        Ok(PeerTransport { stream, encryptor, their_node_id })
+    }
+
+    pub fn their_node_id(&self) -> PublicKey {
+        self.their_node_id
+    }
+
+    /// Encrypts and sends a raw message, type included.
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), TransportError> {
+        let frame = self.encryptor.encrypt_message(msg)?;
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Receives and decrypts a raw message, type included.
+    pub fn recv(&mut self) -> Result<Vec<u8>, TransportError> {
+        let mut header = [0; LENGTH_HEADER_LEN];
+        self.stream.read_exact(&mut header)?;
+        let len = self.encryptor.decrypt_length_header(&header)?;
+
+        let mut body = vec![0; len as usize + MAC_LEN];
+        self.stream.read_exact(&mut body)?;
+        self.encryptor.decrypt_message(&body)
+    }
+
+    pub fn send_message(&mut self, msg: &Message) -> Result<(), TransportError> {
+        self.send(&msg.encode()?)
+    }
+
+    pub fn recv_message(&mut self) -> Result<Message, TransportError> {
+        let msg = self.recv()?;
+        Ok(wire::read_message(&mut &msg[..])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use secp256k1::{PublicKey, SecretKey};
+
+    use crate::msgs::Ping;
+    use crate::wire::Message;
+    use super::*;
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn point(s: &str) -> PublicKey {
+        PublicKey::from_slice(&hex::decode(s).unwrap()).unwrap()
+    }
+
+    fn act<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    const RESPONDER_STATIC: &str = "028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7";
+    const ACT_ONE: &str = "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a";
+    const ACT_TWO: &str = "0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae";
+    const ACT_THREE: &str = "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba";
+
+    fn initiator_handshake() -> (Handshake, MessageEncryptor) {
+        let mut handshake = Handshake::new_outbound(secret(0x11), point(RESPONDER_STATIC), secret(0x12));
+        assert_eq!(hex::encode(handshake.act_one().unwrap()), ACT_ONE);
+        let (act_three, encryptor) = handshake.process_act_two(&act(ACT_TWO)).unwrap();
+        assert_eq!(hex::encode(act_three), ACT_THREE);
+        (handshake, encryptor)
+    }
+
+    #[test]
+    fn initiator_successful_handshake() {
+        let (_, encryptor) = initiator_handshake();
+        assert_eq!(hex::encode(encryptor.sending.key), "969ab31b4d288cedf6218839b27a3e2140827047f2c0f01bf5c04435d43511a9");
+        assert_eq!(hex::encode(encryptor.receiving.key), "bb9020b8965f4df047e07f955f3c4b88418984aadc5cdb35096b9ea8fa5c3442");
+    }
+
+    #[test]
+    fn initiator_act_two_failures() {
+        let test_vectors = [
+            // transport-initiator act2 bad version test
+            ("0102466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae",
+            TransportError::InvalidVersion(1)),
+            // transport-initiator act2 bad key serialization test
+            ("0004466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae",
+            TransportError::InvalidPublicKey),
+            // transport-initiator act2 bad MAC test
+            ("0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730af",
+            TransportError::DecryptionFailed),
+        ];
+
+        for (vector, err) in test_vectors {
+            let mut handshake = Handshake::new_outbound(secret(0x11), point(RESPONDER_STATIC), secret(0x12));
+            handshake.act_one().unwrap();
+            assert_eq!(handshake.process_act_two(&act(vector)).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn responder_successful_handshake() {
+        let mut handshake = Handshake::new_inbound(secret(0x21), secret(0x22));
+        assert_eq!(hex::encode(handshake.process_act_one(&act(ACT_ONE)).unwrap()), ACT_TWO);
+        let (their_node_id, encryptor) = handshake.process_act_three(&act(ACT_THREE)).unwrap();
+        assert_eq!(their_node_id, point("034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa"));
+        assert_eq!(hex::encode(encryptor.receiving.key), "969ab31b4d288cedf6218839b27a3e2140827047f2c0f01bf5c04435d43511a9");
+        assert_eq!(hex::encode(encryptor.sending.key), "bb9020b8965f4df047e07f955f3c4b88418984aadc5cdb35096b9ea8fa5c3442");
+    }
+
+    #[test]
+    fn responder_act_one_failures() {
+        let test_vectors = [
+            // transport-responder act1 bad version test
+            ("01036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a",
+            TransportError::InvalidVersion(1)),
+            // transport-responder act1 bad key serialization test
+            ("00046360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a",
+            TransportError::InvalidPublicKey),
+            // transport-responder act1 bad MAC test
+            ("00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6b",
+            TransportError::DecryptionFailed),
+        ];
+
+        for (vector, err) in test_vectors {
+            let mut handshake = Handshake::new_inbound(secret(0x21), secret(0x22));
+            assert_eq!(handshake.process_act_one(&act(vector)).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn responder_act_three_failures() {
+        let test_vectors = [
+            // transport-responder act3 bad version test
+            ("01b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba",
+            TransportError::InvalidVersion(1)),
+            // transport-responder act3 bad MAC for ciphertext test
+            ("00c9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba",
+            TransportError::DecryptionFailed),
+            // transport-responder act3 bad rs test
+            ("00bfe3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa2235536ad09a8ee351870c2bb7f78b754a26c6cef79a98d25139c856d7efd252c2ae73c",
+            TransportError::InvalidPublicKey),
+            // transport-responder act3 bad MAC test
+            ("00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139bb",
+            TransportError::DecryptionFailed),
+        ];
+
+        for (vector, err) in test_vectors {
+            let mut handshake = Handshake::new_inbound(secret(0x21), secret(0x22));
+            handshake.process_act_one(&act(ACT_ONE)).unwrap();
+            assert_eq!(handshake.process_act_three(&act(vector)).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn message_encryption_with_key_rotation() {
+        let (_, mut encryptor) = initiator_handshake();
+        let expected = [
+            (0, "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"),
+            (1, "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1"),
+            (500, "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8"),
+            (501, "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd"),
+            (1000, "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09"),
+            (1001, "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36"),
+        ];
+
+        let mut expected = expected.iter().peekable();
+        for i in 0..1002 {
+            let frame = encryptor.encrypt_message(b"hello").unwrap();
+            if let Some((n, output)) = expected.peek() {
+                if *n == i {
+                    assert_eq!(hex::encode(frame), *output);
+                    expected.next();
+                }
+            }
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn message_decryption_with_key_rotation() {
+        let mut initiator = initiator_handshake().1;
+        let mut handshake = Handshake::new_inbound(secret(0x21), secret(0x22));
+        handshake.process_act_one(&act(ACT_ONE)).unwrap();
+        let (_, mut responder) = handshake.process_act_three(&act(ACT_THREE)).unwrap();
+
+        for i in 0..2002u16 {
+            let msg = i.to_be_bytes();
+            let frame = initiator.encrypt_message(&msg).unwrap();
+            let len = responder.decrypt_length_header(frame[..LENGTH_HEADER_LEN].try_into().unwrap()).unwrap();
+            assert_eq!(len, 2);
+            assert_eq!(responder.decrypt_message(&frame[LENGTH_HEADER_LEN..]).unwrap(), msg);
+        }
+
+        let mut frame = initiator.encrypt_message(b"hello").unwrap();
+        frame[LENGTH_HEADER_LEN] ^= 1;
+        responder.decrypt_length_header(frame[..LENGTH_HEADER_LEN].try_into().unwrap()).unwrap();
+        assert_eq!(responder.decrypt_message(&frame[LENGTH_HEADER_LEN..]).unwrap_err(), TransportError::DecryptionFailed);
+    }
+
+    /// One end of an in-memory duplex pipe
+    struct Pipe {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (tx_a, rx_a) = mpsc::channel();
+        let (tx_b, rx_b) = mpsc::channel();
+        (Pipe { tx: tx_a, rx: rx_b, buf: Vec::new() }, Pipe { tx: tx_b, rx: rx_a, buf: Vec::new() })
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, dest: &mut [u8]) -> io::Result<usize> {
+            if self.buf.is_empty() {
+                match self.rx.recv() {
+                    Ok(data) => self.buf = data,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = dest.len().min(self.buf.len());
+            dest[..n].copy_from_slice(&self.buf[..n]);
+            self.buf.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.tx.send(data.to_vec()).map_err(|_| io::ErrorKind::BrokenPipe)?;
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn peer_transport_exchange() {
+        let (a, b) = pipe();
+        let responder_static = secret(0x21);
+        let responder = std::thread::spawn(move || {
+            let mut transport = PeerTransport::accept(b, responder_static, secret(0x22)).unwrap();
+            let msg = transport.recv_message().unwrap();
+            transport.send_message(&msg).unwrap();
+            transport.their_node_id()
+        });
+
+        let mut transport = PeerTransport::connect(a, secret(0x11), point(RESPONDER_STATIC), secret(0x12)).unwrap();
+        let ping = Message::Ping(Ping { num_pong_bytes: 4, bytes_len: 2, ignored: vec![0, 0] });
+        transport.send_message(&ping).unwrap();
+        assert_eq!(transport.recv_message().unwrap(), ping);
+        assert_eq!(responder.join().unwrap(), point("034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa"));
+    }
+}
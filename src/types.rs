@@ -0,0 +1,327 @@
+use core::{fmt, str::FromStr};
+
+use bitcoin_hashes::{Hash, sha256};
+
+use crate::io::{self, Read, Write};
+use crate::ser::{Readable, Writeable, DecodeError};
+use crate::tlv::impl_tlv_value_fixed;
+use crate::pretty::impl_describe_display;
+
+#[cfg(feature = "std")]
+pub use bitcoin::{Script, Txid};
+
+#[cfg(not(feature = "std"))]
+hash_newtype!(Txid, bitcoin_hashes::sha256d::Hash, 32, doc="A bitcoin transaction hash/transaction ID.");
+
+/// The raw bytes of a bitcoin script, standing in for `bitcoin::Script` without `std`.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Script(alloc::boxed::Box<[u8]>);
+
+#[cfg(not(feature = "std"))]
+impl Script {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<alloc::vec::Vec<u8>> for Script {
+    fn from(bytes: alloc::vec::Vec<u8>) -> Self {
+        Script(bytes.into_boxed_slice())
+    }
+}
+
+/// Identifies a channel on a per-peer basis. Once the funding transaction is known it is derived
+/// from the funding outpoint, before that a random temporary_channel_id is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct ChannelId(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))] pub [u8; 32]);
+
+/// The SHA256 of a payment preimage, committed to by HTLCs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct PaymentHash(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))] pub [u8; 32]);
+
+/// The secret which, once revealed, allows an HTLC to be claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct PaymentPreimage(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))] pub [u8; 32]);
+
+/// The secret from the invoice which the final hop checks, so that intermediate hops can't probe
+/// it for the payment_hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct PaymentSecret(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))] pub [u8; 32]);
+
+/// The location of a channel's funding output in the blockchain: the block height in the most
+/// significant 3 bytes, then the transaction index within the block in 3 bytes, and the output
+/// index in the least significant 2 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct ShortChannelId(pub u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    InvalidHex,
+    InvalidLength { expected: usize, got: usize },
+    /// A short channel id which isn't of the form `BLOCKxTXxOUTPUT`, or whose parts overflow
+    InvalidShortChannelId,
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidHex => write!(f, "invalid hex"),
+            ParseError::InvalidLength { expected, got } => {
+                write!(f, "expected {} bytes, got {}", expected, got)
+            },
+            ParseError::InvalidShortChannelId => write!(f, "invalid short channel id"),
+        }
+    }
+}
+
+/// Implements serialization, hex `Display` and `FromStr` for a 32-byte newtype.
+macro_rules! impl_bytes_newtype {
+    ($st: ident) => {
+        impl From<[u8; 32]> for $st {
+            fn from(bytes: [u8; 32]) -> Self {
+                $st(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $st {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl Writeable for $st {
+            fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+                self.0.write(writer)
+            }
+
+            fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+                self.0.write_fmt(writer)
+            }
+        }
+
+        impl Readable for $st {
+            fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                Ok($st(Readable::read(reader)?))
+            }
+        }
+
+        impl fmt::Display for $st {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.write_fmt(f)
+            }
+        }
+
+        impl FromStr for $st {
+            type Err = ParseError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let bytes = hex::decode(s).map_err(|_| ParseError::InvalidHex)?;
+                let got = bytes.len();
+                let bytes: [u8; 32] = bytes.try_into()
+                    .map_err(|_| ParseError::InvalidLength { expected: 32, got })?;
+                Ok($st(bytes))
+            }
+        }
+    }
+}
+
+impl_bytes_newtype!(ChannelId);
+impl_bytes_newtype!(PaymentHash);
+impl_bytes_newtype!(PaymentPreimage);
+impl_bytes_newtype!(PaymentSecret);
+
+impl_describe_display!(ChannelId, PaymentHash, PaymentPreimage, PaymentSecret, ShortChannelId);
+
+impl ChannelId {
+    /// The channel_id referring to all channels in `error` and `warning`.
+    pub const ALL: ChannelId = ChannelId([0; 32]);
+
+    /// Derives the channel_id by combining the funding_txid and the funding_output_index, using
+    /// big-endian exclusive-OR (i.e. funding_output_index alters the last 2 bytes).
+    pub fn from_funding_outpoint(funding_txid: &Txid, funding_output_index: u16) -> Self {
+        let mut id = funding_txid.into_inner();
+        let index = funding_output_index.to_be_bytes();
+        id[30] ^= index[0];
+        id[31] ^= index[1];
+        ChannelId(id)
+    }
+
+    pub fn is_all(&self) -> bool {
+        *self == ChannelId::ALL
+    }
+}
+
+impl PaymentPreimage {
+    /// The payment_hash an HTLC claimable with this preimage commits to.
+    pub fn payment_hash(&self) -> PaymentHash {
+        PaymentHash(sha256::Hash::hash(&self.0).into_inner())
+    }
+}
+
+impl ShortChannelId {
+    /// Builds the short channel id, or returns `None` if the block height or transaction index
+    /// don't fit in 3 bytes.
+    pub fn new(block_height: u32, tx_index: u32, output_index: u16) -> Option<Self> {
+        if block_height > 0xff_ffff || tx_index > 0xff_ffff {
+            return None
+        }
+        Some(ShortChannelId((block_height as u64) << 40 | (tx_index as u64) << 16 | output_index as u64))
+    }
+
+    pub fn block_height(&self) -> u32 {
+        (self.0 >> 40) as u32
+    }
+
+    pub fn tx_index(&self) -> u32 {
+        ((self.0 >> 16) & 0xff_ffff) as u32
+    }
+
+    pub fn output_index(&self) -> u16 {
+        (self.0 & 0xffff) as u16
+    }
+}
+
+impl Writeable for ShortChannelId {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.0.write(writer)
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.0.write_fmt(writer)
+    }
+}
+
+impl Readable for ShortChannelId {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(ShortChannelId(Readable::read(reader)?))
+    }
+}
+
+/// Short channel ids are shown as `BLOCKxTXxOUTPUT`, e.g. `539268x845x1`.
+impl fmt::Display for ShortChannelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.block_height(), self.tx_index(), self.output_index())
+    }
+}
+
+impl FromStr for ShortChannelId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('x');
+        let mut next = || parts.next().ok_or(ParseError::InvalidShortChannelId);
+        let block_height = next()?.parse().map_err(|_| ParseError::InvalidShortChannelId)?;
+        let tx_index = next()?.parse().map_err(|_| ParseError::InvalidShortChannelId)?;
+        let output_index = next()?.parse().map_err(|_| ParseError::InvalidShortChannelId)?;
+        if parts.next().is_some() {
+            return Err(ParseError::InvalidShortChannelId)
+        }
+        ShortChannelId::new(block_height, tx_index, output_index).ok_or(ParseError::InvalidShortChannelId)
+    }
+}
+
+impl_tlv_value_fixed!(ChannelId, PaymentHash, PaymentPreimage, PaymentSecret, ShortChannelId);
+
+/// Short channel ids are serialized like they are displayed, e.g. `"539268x845x1"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ShortChannelId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ShortChannelId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = alloc::string::String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::tlv::impl_serde_value!(ChannelId, PaymentHash, PaymentPreimage, PaymentSecret, ShortChannelId);
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use core::str::FromStr;
+
+    use bitcoin_hashes::hex::FromHex;
+
+    use super::{ChannelId, Script, Txid, PaymentPreimage, PaymentHash, ShortChannelId, ParseError};
+
+    #[test]
+    fn channel_id_from_funding_outpoint() {
+        // The funding transaction of the BOLT #3 test vectors, whose txid is displayed reversed
+        let txid = Txid::from_hex("8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be").unwrap();
+        assert_eq!(
+            hex::encode(ChannelId::from_funding_outpoint(&txid, 0).0),
+            "bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489"
+        );
+        assert_eq!(
+            hex::encode(ChannelId::from_funding_outpoint(&txid, 0x0102).0),
+            "bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a48858b"
+        );
+        assert!(!ChannelId::from_funding_outpoint(&txid, 1).is_all());
+        assert!(ChannelId::ALL.is_all());
+    }
+
+    #[test]
+    fn script_bytes() {
+        let script = Script::from(hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap());
+        assert_eq!(script.len(), 22);
+        assert_eq!(&script.as_bytes()[..2], [0x00, 0x14]);
+        assert!(!script.is_empty());
+        assert!(Script::default().is_empty());
+    }
+
+    #[test]
+    fn payment_hash_of_preimage() {
+        // SHA256 of 32 zero bytes
+        assert_eq!(
+            PaymentPreimage([0; 32]).payment_hash(),
+            PaymentHash::from_str("66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925").unwrap()
+        );
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        let id = ChannelId([0x02; 32]);
+        assert_eq!(id.to_string(), "02".repeat(32));
+        assert_eq!(ChannelId::from_str(&id.to_string()), Ok(id));
+        assert_eq!(ChannelId::from_str("0202"), Err(ParseError::InvalidLength { expected: 32, got: 2 }));
+        assert_eq!(PaymentHash::from_str("zz"), Err(ParseError::InvalidHex));
+    }
+
+    #[test]
+    fn short_channel_id() {
+        let scid = ShortChannelId::new(539268, 845, 1).unwrap();
+        assert_eq!(scid.0, 0x083a8400034d0001);
+        assert_eq!((scid.block_height(), scid.tx_index(), scid.output_index()), (539268, 845, 1));
+        assert_eq!(scid.to_string(), "539268x845x1");
+        assert_eq!(ShortChannelId::from_str("539268x845x1"), Ok(scid));
+        assert_eq!(ShortChannelId(0x226).to_string(), "0x0x550");
+
+        assert_eq!(ShortChannelId::new(1 << 24, 0, 0), None);
+        for s in ["", "1x2", "1x2x3x4", "1x2xa", "16777216x0x0", "0x0x65536"] {
+            assert_eq!(ShortChannelId::from_str(s), Err(ParseError::InvalidShortChannelId));
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use core::fmt;
+use alloc::vec::Vec;
+
+use crate::io::{self, Read, Write};
+
+use crate::msgs::*;
+use crate::ser::{Readable, ReadableArgs, Writeable, DecodeError};
+use crate::pretty::Describe;
+
+/// The maximum size of a message, including its 2-byte type, as allowed by the transport.
+pub const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Every message is prefixed by a 2-byte big-endian type which tells the receiver how to
+/// interpret the payload that follows.
+pub trait Type {
+    const TYPE: u16;
+}
+
+macro_rules! impl_type {
+    ($st: ident, $typ: expr) => {
+        impl Type for $st {
+            const TYPE: u16 = $typ;
+        }
+    }
+}
+
+impl_type!(WarningMessage, 1);
+impl_type!(Init, 16);
+impl_type!(ErrorMessage, 17);
+impl_type!(Ping, 18);
+impl_type!(Pong, 19);
+impl_type!(OpenChannel, 32);
+impl_type!(AcceptChannel, 33);
+impl_type!(FundingCreated, 34);
+impl_type!(FundingSigned, 35);
+impl_type!(FundingLocked, 36);
+impl_type!(Shutdown, 38);
+impl_type!(ClosingSigned, 39);
+impl_type!(UpdateAddHTLC, 128);
+impl_type!(UpdateFulfillHTLC, 130);
+impl_type!(UpdateFailHTLC, 131);
+impl_type!(CommitmentSigned, 132);
+impl_type!(RevokeAndACK, 133);
+impl_type!(UpdateFee, 134);
+impl_type!(UpdateFailMalformedHTLC, 135);
+impl_type!(ChannelReestablish, 136);
+
+/// A decoded Lightning message, tagged by its type.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Message {
+    #[cfg_attr(feature = "serde", serde(rename = "warning"))]
+    Warning(WarningMessage),
+    #[cfg_attr(feature = "serde", serde(rename = "init"))]
+    Init(Init),
+    #[cfg_attr(feature = "serde", serde(rename = "error"))]
+    Error(ErrorMessage),
+    #[cfg_attr(feature = "serde", serde(rename = "ping"))]
+    Ping(Ping),
+    #[cfg_attr(feature = "serde", serde(rename = "pong"))]
+    Pong(Pong),
+    #[cfg_attr(feature = "serde", serde(rename = "open_channel"))]
+    OpenChannel(OpenChannel),
+    #[cfg_attr(feature = "serde", serde(rename = "accept_channel"))]
+    AcceptChannel(AcceptChannel),
+    #[cfg_attr(feature = "serde", serde(rename = "funding_created"))]
+    FundingCreated(FundingCreated),
+    #[cfg_attr(feature = "serde", serde(rename = "funding_signed"))]
+    FundingSigned(FundingSigned),
+    #[cfg_attr(feature = "serde", serde(rename = "funding_locked"))]
+    FundingLocked(FundingLocked),
+    #[cfg_attr(feature = "serde", serde(rename = "shutdown"))]
+    Shutdown(Shutdown),
+    #[cfg_attr(feature = "serde", serde(rename = "closing_signed"))]
+    ClosingSigned(ClosingSigned),
+    #[cfg_attr(feature = "serde", serde(rename = "update_add_htlc"))]
+    UpdateAddHTLC(UpdateAddHTLC),
+    #[cfg_attr(feature = "serde", serde(rename = "update_fulfill_htlc"))]
+    UpdateFulfillHTLC(UpdateFulfillHTLC),
+    #[cfg_attr(feature = "serde", serde(rename = "update_fail_htlc"))]
+    UpdateFailHTLC(UpdateFailHTLC),
+    #[cfg_attr(feature = "serde", serde(rename = "commitment_signed"))]
+    CommitmentSigned(CommitmentSigned),
+    #[cfg_attr(feature = "serde", serde(rename = "revoke_and_ack"))]
+    RevokeAndACK(RevokeAndACK),
+    #[cfg_attr(feature = "serde", serde(rename = "update_fee"))]
+    UpdateFee(UpdateFee),
+    #[cfg_attr(feature = "serde", serde(rename = "update_fail_malformed_htlc"))]
+    UpdateFailMalformedHTLC(UpdateFailMalformedHTLC),
+    #[cfg_attr(feature = "serde", serde(rename = "channel_reestablish"))]
+    ChannelReestablish(ChannelReestablish),
+    /// A message of odd type which we don't understand. It's ok to be odd, so the payload is kept
+    /// as-is and left to the caller to ignore.
+    #[cfg_attr(feature = "serde", serde(rename = "unknown"))]
+    Unknown {
+        typ: u16,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_hex"))]
+        payload: Vec<u8>,
+    },
+}
+
+/// Evaluates `$body` with `$m` bound to the message wrapped by each known variant of `Message`.
+macro_rules! for_each_known_msg {
+    ($msg: expr, $m: ident => $body: expr, $unknown: pat => $unknown_body: expr) => {
+        match $msg {
+            Message::Warning($m) => $body,
+            Message::Init($m) => $body,
+            Message::Error($m) => $body,
+            Message::Ping($m) => $body,
+            Message::Pong($m) => $body,
+            Message::OpenChannel($m) => $body,
+            Message::AcceptChannel($m) => $body,
+            Message::FundingCreated($m) => $body,
+            Message::FundingSigned($m) => $body,
+            Message::FundingLocked($m) => $body,
+            Message::Shutdown($m) => $body,
+            Message::ClosingSigned($m) => $body,
+            Message::UpdateAddHTLC($m) => $body,
+            Message::UpdateFulfillHTLC($m) => $body,
+            Message::UpdateFailHTLC($m) => $body,
+            Message::CommitmentSigned($m) => $body,
+            Message::RevokeAndACK($m) => $body,
+            Message::UpdateFee($m) => $body,
+            Message::UpdateFailMalformedHTLC($m) => $body,
+            Message::ChannelReestablish($m) => $body,
+            $unknown => $unknown_body,
+        }
+    }
+}
+
+impl Message {
+    /// The 2-byte type this message is sent with.
+    pub fn typ(&self) -> u16 {
+        match self {
+            Message::Warning(_) => WarningMessage::TYPE,
+            Message::Init(_) => Init::TYPE,
+            Message::Error(_) => ErrorMessage::TYPE,
+            Message::Ping(_) => Ping::TYPE,
+            Message::Pong(_) => Pong::TYPE,
+            Message::OpenChannel(_) => OpenChannel::TYPE,
+            Message::AcceptChannel(_) => AcceptChannel::TYPE,
+            Message::FundingCreated(_) => FundingCreated::TYPE,
+            Message::FundingSigned(_) => FundingSigned::TYPE,
+            Message::FundingLocked(_) => FundingLocked::TYPE,
+            Message::Shutdown(_) => Shutdown::TYPE,
+            Message::ClosingSigned(_) => ClosingSigned::TYPE,
+            Message::UpdateAddHTLC(_) => UpdateAddHTLC::TYPE,
+            Message::UpdateFulfillHTLC(_) => UpdateFulfillHTLC::TYPE,
+            Message::UpdateFailHTLC(_) => UpdateFailHTLC::TYPE,
+            Message::CommitmentSigned(_) => CommitmentSigned::TYPE,
+            Message::RevokeAndACK(_) => RevokeAndACK::TYPE,
+            Message::UpdateFee(_) => UpdateFee::TYPE,
+            Message::UpdateFailMalformedHTLC(_) => UpdateFailMalformedHTLC::TYPE,
+            Message::ChannelReestablish(_) => ChannelReestablish::TYPE,
+            Message::Unknown { typ, .. } => *typ,
+        }
+    }
+}
+
+/// Reads a full message, i.e. the 2-byte type followed by the payload, dispatching on the type.
+///
+/// The reader is expected to be bounded to a single message since messages ending in a TLV
+/// stream consume everything up to the end of the reader.
+///
+/// The `chain_hash` of messages such as open_channel isn't checked, so an open_channel for any
+/// chain is accepted. A node should use [`read_message_on_chains`] with the chains it operates on.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, DecodeError> {
+    read_message_inner(reader, None)
+}
+
+/// Like `read_message`, but rejects messages tied to a chain not in `chains`, such as an
+/// open_channel for a chain the node doesn't operate on.
+pub fn read_message_on_chains<R: Read>(reader: &mut R, chains: &[ChainHash]) -> Result<Message, DecodeError> {
+    read_message_inner(reader, Some(chains))
+}
+
+fn read_message_inner<R: Read>(reader: &mut R, chains: Option<&[ChainHash]>) -> Result<Message, DecodeError> {
+    // Reading one byte past the limit tells a message which is too long apart from one which
+    // is exactly at the limit.
+    let mut bounded = reader.take(MAX_MESSAGE_LEN as u64 + 1);
+    let msg = read_message_bounded(&mut bounded, chains)?;
+    if bounded.limit() == 0 {
+        return Err(DecodeError::MessageTooLong)
+    }
+    Ok(msg)
+}
+
+fn read_message_bounded<R: Read>(reader: &mut R, chains: Option<&[ChainHash]>) -> Result<Message, DecodeError> {
+    let typ: u16 = Readable::read(reader)?;
+
+    let msg = match typ {
+        WarningMessage::TYPE => Message::Warning(Readable::read(reader)?),
+        Init::TYPE => Message::Init(Readable::read(reader)?),
+        ErrorMessage::TYPE => Message::Error(Readable::read(reader)?),
+        Ping::TYPE => Message::Ping(Readable::read(reader)?),
+        Pong::TYPE => Message::Pong(Readable::read(reader)?),
+        OpenChannel::TYPE => Message::OpenChannel(match chains {
+            Some(chains) => ReadableArgs::read(reader, chains)?,
+            None => Readable::read(reader)?,
+        }),
+        AcceptChannel::TYPE => Message::AcceptChannel(Readable::read(reader)?),
+        FundingCreated::TYPE => Message::FundingCreated(Readable::read(reader)?),
+        FundingSigned::TYPE => Message::FundingSigned(Readable::read(reader)?),
+        FundingLocked::TYPE => Message::FundingLocked(Readable::read(reader)?),
+        Shutdown::TYPE => Message::Shutdown(Readable::read(reader)?),
+        ClosingSigned::TYPE => Message::ClosingSigned(Readable::read(reader)?),
+        UpdateAddHTLC::TYPE => Message::UpdateAddHTLC(Readable::read(reader)?),
+        UpdateFulfillHTLC::TYPE => Message::UpdateFulfillHTLC(Readable::read(reader)?),
+        UpdateFailHTLC::TYPE => Message::UpdateFailHTLC(Readable::read(reader)?),
+        CommitmentSigned::TYPE => Message::CommitmentSigned(Readable::read(reader)?),
+        RevokeAndACK::TYPE => Message::RevokeAndACK(Readable::read(reader)?),
+        UpdateFee::TYPE => Message::UpdateFee(Readable::read(reader)?),
+        UpdateFailMalformedHTLC::TYPE => Message::UpdateFailMalformedHTLC(Readable::read(reader)?),
+        ChannelReestablish::TYPE => Message::ChannelReestablish(Readable::read(reader)?),
+        // It's ok to be odd: unknown odd messages are ignored, unknown even messages must close
+        // the connection.
+        t if t % 2 == 0 => return Err(DecodeError::UnknownEvenType(t as u64)),
+        t => {
+            let mut payload = Vec::new();
+            reader.read_to_end(&mut payload).map_err(|e| DecodeError::Io(e.kind()))?;
+            Message::Unknown { typ: t, payload }
+        }
+    };
+
+    Ok(msg)
+}
+
+/// Writes the type of the message followed by its payload. Messages longer than
+/// `MAX_MESSAGE_LEN` can't be sent, so nothing is written for them.
+impl Writeable for Message {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        let mut msg = Vec::new();
+        self.typ().write(&mut msg)?;
+        for_each_known_msg!(self, m => m.write(&mut msg)?,
+            Message::Unknown { payload, .. } => {
+                msg.extend_from_slice(payload);
+                payload.len()
+            }
+        );
+        if msg.len() > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "message exceeds 65535 bytes"))
+        }
+        writer.write_all(&msg)?;
+        Ok(msg.len())
+    }
+
+    fn write_fmt<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        self.typ().write_fmt(writer)?;
+        for_each_known_msg!(self, m => m.write_fmt(writer),
+            Message::Unknown { payload, .. } => {
+                for byte in payload {
+                    write!(writer, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        )
+    }
+}
+
+/// The hex encoding of the message including its type, or with `{:#}` its description, e.g.
+/// `pong ignored=00`.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return self.write_fmt(f)
+        }
+        for_each_known_msg!(self, m => m.describe(f),
+            Message::Unknown { typ, payload } => {
+                write!(f, "unknown type={} payload=", typ)?;
+                payload.describe(f)
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::io;
+    use crate::msgs::{ChainHash, Ping};
+    use crate::ser::{DecodeError, Writeable};
+    use super::{read_message, read_message_on_chains, Message, MAX_MESSAGE_LEN};
+
+    #[test]
+    fn dispatch_known_types() {
+        let test_vectors = [
+            ("001000000000", 16),
+            ("0012000400020000", 18),
+            ("00130000", 19),
+            ("00860202020202020202020202020202020202020202020202020202020202020202000003e8", 134),
+        ];
+
+        for (vector, typ) in test_vectors {
+            let input = hex::decode(vector).expect("input");
+            let mut buff = input.as_slice();
+            let msg = read_message(&mut buff).expect("no failure");
+            assert_eq!(msg.typ(), typ);
+            assert!(!matches!(msg, Message::Unknown { .. }));
+            assert_eq!(hex::encode(msg.encode().unwrap()), vector);
+        }
+    }
+
+    #[test]
+    fn unknown_odd_types_are_ignored() {
+        let input = hex::decode("8001deadbeef").expect("input");
+        let mut buff = input.as_slice();
+        let msg = read_message(&mut buff).expect("no failure");
+        assert_eq!(msg, Message::Unknown { typ: 0x8001, payload: vec![0xde, 0xad, 0xbe, 0xef] });
+        assert_eq!(hex::encode(msg.encode().unwrap()), "8001deadbeef");
+    }
+
+    #[test]
+    fn unknown_even_types_fail() {
+        let input = hex::decode("8000deadbeef").expect("input");
+        let mut buff = input.as_slice();
+        assert_eq!(read_message(&mut buff).unwrap_err(), DecodeError::UnknownEvenType(0x8000));
+    }
+
+    #[test]
+    fn truncated_messages_fail() {
+        let test_vectors = [
+            ("", DecodeError::ShortRead),
+            ("00", DecodeError::ShortRead),
+            ("0012", DecodeError::ShortRead.in_field("num_pong_bytes")),
+            ("001200040002", DecodeError::ShortRead.in_field("ignored")),
+        ];
+
+        for (vector, error) in test_vectors {
+            let input = hex::decode(vector).expect("input");
+            let mut buff = input.as_slice();
+            assert_eq!(read_message(&mut buff).unwrap_err(), error);
+        }
+    }
+
+    #[test]
+    fn reject_unknown_chains() {
+        let open_channel = hex::decode([
+            "0020",
+            "06226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f",
+            &"02".repeat(32),
+            &"00".repeat(56),
+            &"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".repeat(6),
+            "00",
+        ].concat()).unwrap();
+
+        // read_message doesn't look at the chain
+        assert!(matches!(read_message(&mut &open_channel[..]), Ok(Message::OpenChannel(_))));
+        let msg = read_message_on_chains(&mut &open_channel[..], &[ChainHash::REGTEST]).expect("no failure");
+        assert!(matches!(msg, Message::OpenChannel(_)));
+        assert_eq!(
+            read_message_on_chains(&mut &open_channel[..], &[ChainHash::BITCOIN]).unwrap_err().root(),
+            &DecodeError::UnknownChain
+        );
+        // Messages which aren't tied to a chain are unaffected
+        assert!(read_message_on_chains(&mut &hex::decode("00130000").unwrap()[..], &[]).is_ok());
+    }
+
+    #[test]
+    fn message_size_limit() {
+        // 2-byte type, 4 bytes of ping fields and the padding add up to exactly 65535 bytes
+        let ping = Message::Ping(Ping::new(0, 65529));
+        let bytes = ping.encode().expect("fits");
+        assert_eq!(bytes.len(), MAX_MESSAGE_LEN);
+        assert_eq!(read_message(&mut &bytes[..]).unwrap(), ping);
+
+        let too_long = Message::Ping(Ping::new(0, 65530));
+        assert_eq!(too_long.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let mut buff = Vec::new();
+        assert!(too_long.write(&mut buff).is_err());
+        assert!(buff.is_empty());
+
+        let mut unknown = vec![0x80, 0x01];
+        unknown.extend_from_slice(&[0; MAX_MESSAGE_LEN - 2]);
+        assert!(read_message(&mut &unknown[..]).is_ok());
+        unknown.push(0);
+        assert_eq!(read_message(&mut &unknown[..]).unwrap_err(), DecodeError::MessageTooLong);
+    }
+}
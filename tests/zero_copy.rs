@@ -0,0 +1,81 @@
+//! Checks that decoding borrowed messages doesn't allocate. This has its own test binary since it
+//! replaces the global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lightning_bolt1::borrowed::read_message_ref;
+
+/// Counts every allocation. The binary has a single test, so nothing else allocates meanwhile
+/// except the test harness, which is waiting for it.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    let res = f();
+    (res, ALLOCATIONS.load(Ordering::SeqCst) - before)
+}
+
+const CHANNEL_ID: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+const POINT: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const SIGNATURE: &str = concat!(
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    "2222222222222222222222222222222222222222222222222222222222222222"
+);
+
+/// Messages whose decoding shouldn't allocate. The TLV values of `open_channel` are scripts and
+/// features, so it's only included without any.
+fn test_vectors() -> Vec<Vec<u8>> {
+    [
+        [ "0001", CHANNEL_ID, "0004", "74657374" ].concat(),
+        [ "0011", CHANNEL_ID, "0000" ].concat(),
+        "001200040002aaaa".to_string(),
+        "00130004bbbbbbbb".to_string(),
+        [
+            "0020",
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000",
+            CHANNEL_ID,
+            &"00".repeat(56),
+            &POINT.repeat(6),
+            "01",
+        ].concat(),
+        [ "0026", CHANNEL_ID, "0016", "0014751e76e8199196d454941c45d1b3a323f1433bd6" ].concat(),
+        [
+            "0027", CHANNEL_ID, "00000000000001f4", SIGNATURE,
+            "0110", "00000000000001f4", "00000000000003e8",
+        ].concat(),
+        [
+            "0080", CHANNEL_ID, "0000000000000002", "00000000000186a0",
+            &"03".repeat(32), "00000090", &"05".repeat(1366), "0021", POINT,
+        ].concat(),
+        [ "0083", CHANNEL_ID, "0000000000000002", "0003", "010203" ].concat(),
+        [ "0084", CHANNEL_ID, SIGNATURE, "0002", SIGNATURE, SIGNATURE ].concat(),
+        [ "0086", CHANNEL_ID, "000003e8" ].concat(),
+        "8001deadbeef".to_string(),
+    ].iter().map(|v| hex::decode(v).unwrap()).collect()
+}
+
+#[test]
+fn decoding_does_not_allocate() {
+    for vector in test_vectors() {
+        let (msg, count) = allocations(|| read_message_ref(&vector));
+        assert!(msg.is_ok());
+        assert_eq!(count, 0, "{:?}", msg);
+    }
+}